@@ -0,0 +1,127 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::{hint::black_box, str::FromStr};
+use trade_match::{
+    clients::{Client, Clients},
+    engine::Engine,
+    orders::{NewOrder, NewOrders, Order, Orders},
+    parallel, read_file, DataParser,
+};
+
+const DEPTHS: [usize; 4] = [10, 100, 1_000, 5_000];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.bench_function("NewOrder::from_str", |b| {
+        b.iter(|| NewOrder::from_str(black_box("C5\tb\tC\t15\t4")).unwrap())
+    });
+    group.bench_function("Client::from_str", |b| {
+        b.iter(|| Client::from_str(black_box("C1\t1000\t130\t240\t760\t320")).unwrap())
+    });
+    group.finish();
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    for count in DEPTHS {
+        let engine = book(0);
+        let orders: Vec<Order> = (0..count)
+            .map(|index| engine.resolve(&order(index, "b", 100, 10)).unwrap())
+            .collect();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &orders, |b, orders| {
+            b.iter_batched(
+                || orders.clone(),
+                |orders| {
+                    let mut book = Orders::new();
+                    for (index, order) in orders.into_iter().enumerate() {
+                        book.insert(index, order);
+                    }
+                    book
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// One buy order sweeping a book of resting sells, and one that does not
+// cross and only rests, at several book depths.
+fn depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("depth");
+    for depth in DEPTHS {
+        let engine = book(depth);
+        let sweep = engine
+            .resolve(&order(depth, "b", 100 + depth as u32, depth * 10))
+            .unwrap();
+        let rest = engine.resolve(&order(depth, "b", 1, 10)).unwrap();
+        group.bench_with_input(BenchmarkId::new("sweep", depth), &engine, |b, engine| {
+            b.iter_batched(
+                || engine.clone(),
+                |mut engine| engine.process(sweep).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("rest", depth), &engine, |b, engine| {
+            b.iter_batched(
+                || engine.clone(),
+                |mut engine| engine.process(rest).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// The shipped input end to end, on one thread and sharded by asset.
+fn shipped(c: &mut Criterion) {
+    let clients: Clients = read_file("./Clients.txt".to_string()).unwrap();
+    let orders: NewOrders = read_file("./Orders.txt".to_string()).unwrap();
+    let mut group = c.benchmark_group("Orders.txt");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(orders.order.len() as u64));
+    group.bench_function("sequential", |b| {
+        b.iter_batched(
+            || (Engine::new(clients.clone()), orders.clone()),
+            |(mut engine, orders)| engine.match_orders(orders).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("parallel", |b| {
+        b.iter_batched(
+            || (Engine::new(clients.clone()), orders.clone()),
+            |(mut engine, orders)| parallel::match_orders(&mut engine, orders).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// Sells of asset A from nine clients, one per price level, and a buyer C0
+// who can afford all of them.
+fn book(depth: usize) -> Engine {
+    let mut clients = Clients::new();
+    for index in 0..10 {
+        let line = format!("C{}\t1000000000\t1000000\t0\t0\t0", index);
+        clients.insert(index, Client::from_str(&line).unwrap());
+    }
+    let mut engine = Engine::new(clients);
+    for index in 0..depth {
+        engine
+            .enter(&order(index, "s", 100 + index as u32, 10))
+            .unwrap();
+    }
+    engine
+}
+
+fn order(index: usize, operation: &str, price: u32, volume: usize) -> NewOrder {
+    let client = if operation == "b" { 0 } else { 1 + index % 9 };
+    let line = format!("C{}\t{}\tA\t{}\t{}", client, operation, price, volume);
+    NewOrder {
+        index,
+        ..NewOrder::from_str(&line).unwrap()
+    }
+}
+
+criterion_group!(benches, parse, insert, depth, shipped);
+criterion_main!(benches);
@@ -0,0 +1,196 @@
+#![no_main]
+
+// Arbitrary clients and message sequences fed to the engine. After every
+// message the totals of dollars and of each asset must be unchanged and
+// each order's filled, resting and cancelled volume must add up to what
+// was sent.
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+use trade_match::{
+    clients::{Asset, Assets, Client, Clients},
+    engine::Engine,
+    orders::{NewOrder, Order, OrderType},
+    pnl::{CostMethod, Pnl},
+    symbols::Symbol,
+    DataParser,
+};
+
+// E and F are held by no client read from a text file.
+const ASSETS: [&str; 6] = ["A", "B", "C", "D", "E", "F"];
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    clients: Vec<(u32, Vec<(u8, u32)>)>,
+    messages: Vec<Message>,
+}
+
+#[derive(Debug, Arbitrary)]
+enum Message {
+    Order {
+        client: u8,
+        buy: bool,
+        asset: u8,
+        price: u32,
+        volume: u32,
+    },
+    Cancel(u16),
+    Replace {
+        order: u16,
+        price: u32,
+        volume: u32,
+    },
+}
+
+#[derive(Default)]
+struct Volumes {
+    original: u64,
+    filled: u64,
+    cancelled: u64,
+}
+
+fuzz_target!(|input: Input| {
+    let mut clients = Clients::new();
+    for (index, (dollars, balances)) in input.clients.iter().take(8).enumerate() {
+        let mut assets = Assets::new();
+        for (asset, balance) in balances {
+            let symbol = ASSETS[*asset as usize % ASSETS.len()].to_string();
+            assets.insert(Asset {
+                symbol,
+                balance: *balance,
+            });
+        }
+        let pnl = Pnl::open(&assets, CostMethod::Fifo);
+        clients.insert(
+            index,
+            Client {
+                index,
+                id: Symbol::default(),
+                name: format!("C{}", index),
+                dollar_balance: *dollars,
+                asset_balances: assets,
+                pnl,
+            },
+        );
+    }
+    let count = clients.client.len().max(1);
+    let mut engine = Engine::new(clients);
+    let totals = totals(&engine);
+    let mut volumes: BTreeMap<usize, Volumes> = BTreeMap::new();
+
+    for message in input.messages {
+        let index = volumes.len() + 1;
+        let (order, cancelled) = match message {
+            Message::Order {
+                client,
+                buy,
+                asset,
+                price,
+                volume,
+            } => match engine.resolve(&NewOrder {
+                index,
+                client_name: format!("C{}", client as usize % count),
+                operation: if buy { OrderType::Buy } else { OrderType::Sell },
+                asset: ASSETS[asset as usize % ASSETS.len()].to_string(),
+                order_price: price,
+                value: volume,
+            }) {
+                Ok(order) => (order, None),
+                // Unknown clients and assets are rejected like any other order.
+                Err(_) => {
+                    volumes.insert(index, Volumes::default());
+                    check(&engine, &totals, &volumes);
+                    continue;
+                }
+            },
+            Message::Cancel(order) => {
+                let order = order as usize % index.max(1);
+                if let Ok(order) = engine.cancel(order) {
+                    volumes.get_mut(&order.index).unwrap().cancelled += u64::from(order.value);
+                }
+                check(&engine, &totals, &volumes);
+                continue;
+            }
+            // A replace is a cancel followed by a new order for the rest.
+            Message::Replace {
+                order,
+                price,
+                volume,
+            } => match engine.cancel(order as usize % index.max(1)) {
+                Ok(cancelled) => (
+                    Order {
+                        index,
+                        order_price: price,
+                        value: volume,
+                        ..cancelled
+                    },
+                    Some(cancelled),
+                ),
+                Err(_) => continue,
+            },
+        };
+        if let Some(cancelled) = cancelled {
+            volumes.get_mut(&cancelled.index).unwrap().cancelled += u64::from(cancelled.value);
+        }
+
+        let original = u64::from(order.value);
+        match engine.process(order) {
+            Ok(trades) => {
+                volumes.insert(
+                    index,
+                    Volumes {
+                        original,
+                        ..Volumes::default()
+                    },
+                );
+                for trade in trades {
+                    assert!(trade.volume > 0);
+                    assert_ne!(trade.buyer, trade.seller);
+                    assert!(trade.price <= trade.buy_limit && trade.price >= trade.sell_limit);
+                    volumes.get_mut(&trade.buy_index).unwrap().filled += u64::from(trade.volume);
+                    volumes.get_mut(&trade.sell_index).unwrap().filled += u64::from(trade.volume);
+                }
+            }
+            // Rejected orders never reach the books. Indexes stay unique.
+            Err(_) => {
+                volumes.insert(index, Volumes::default());
+            }
+        }
+        check(&engine, &totals, &volumes);
+    }
+});
+
+fn check(engine: &Engine, totals: &BTreeMap<&str, u64>, volumes: &BTreeMap<usize, Volumes>) {
+    assert_eq!(&self::totals(engine), totals, "totals are not conserved");
+    for (index, volume) in volumes {
+        let remaining = engine
+            .get_order(*index)
+            .map_or(0, |order| u64::from(order.value));
+        assert_eq!(
+            volume.filled + remaining + volume.cancelled,
+            volume.original,
+            "volume of order {} does not add up",
+            index
+        );
+    }
+    for client in engine.clients.client.values() {
+        client.to_string();
+    }
+    for asset in engine.assets() {
+        engine.depth(&asset).to_string();
+    }
+}
+
+// Dollars under "$" and units of every asset, over all clients.
+fn totals(engine: &Engine) -> BTreeMap<&'static str, u64> {
+    let mut totals = BTreeMap::new();
+    for client in engine.clients.client.values() {
+        *totals.entry("$").or_default() += u64::from(client.dollar_balance);
+        for asset in ASSETS {
+            if let Some(balance) = client.asset_balances.get(asset) {
+                *totals.entry(asset).or_default() += u64::from(balance.balance);
+            }
+        }
+    }
+    totals
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+use trade_match::clients::Client;
+
+fuzz_target!(|line: &str| {
+    if let Ok(client) = Client::from_str(line) {
+        // A parsed client always writes back as one Result.txt line.
+        let written = client.to_string();
+        assert!(written.starts_with(&client.name));
+        assert_eq!(written.matches('\n').count(), 1);
+    }
+});
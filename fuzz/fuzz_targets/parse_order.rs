@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+use trade_match::orders::NewOrder;
+
+fuzz_target!(|line: &str| {
+    if let Ok(order) = NewOrder::from_str(line) {
+        assert!(!order.client_name.is_empty());
+        assert!(!order.asset.is_empty());
+    }
+});
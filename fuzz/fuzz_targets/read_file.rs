@@ -0,0 +1,19 @@
+#![no_main]
+
+// Whole input files in every format, read through the same stream as
+// read_file and validate_file.
+use libfuzzer_sys::fuzz_target;
+use trade_match::{clients::Clients, formats::Format, orders::NewOrders, stream};
+
+fuzz_target!(|data: &[u8]| {
+    for format in [Format::Text, Format::Csv, Format::Jsonl] {
+        if let Ok(orders) = stream::<NewOrders>(data, "fuzz", format) {
+            orders.for_each(drop);
+        }
+        if let Ok(clients) = stream::<Clients>(data, "fuzz", format) {
+            for client in clients.flatten() {
+                client.1.to_string();
+            }
+        }
+    }
+});
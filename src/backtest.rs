@@ -0,0 +1,546 @@
+use crate::{
+    engine::{buy_candidates, sell_candidates, Engine, Trade},
+    errors::{OrderErrors, TradeMatchErrors},
+    formats::Record,
+    orders::{NewOrder, NewOrders, Order, OrderType},
+    pnl::Pnl,
+    symbols::Symbol,
+    DataParser, OnError, Volume,
+};
+use std::{collections::BTreeMap, str::FromStr};
+
+pub const EXECUTION_HEADER: &str =
+    "sequence\tindex\tclient\tside\tasset\tprice\tvolume\tfilled\tavg_price\tqueue_orders\tqueue_volume\tresting\tstatus";
+pub const BACKTEST_PNL_HEADER: &str = "client\trealized\tunrealized";
+
+// Backtests a strategy against a historical orders file. The market's
+// orders are replayed as they are, with the strategy's orders slotted in
+// between them by sequence number, and every strategy order is followed
+// through its fills and its place in the queue.
+//
+// Time priority follows the order index, so everything is renumbered in
+// arrival order. Trades of the run carry those indexes, not the lines of
+// either file.
+
+// A strategy order sent once `sequence` market orders have gone through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Injected {
+    pub sequence: usize,
+    pub order: NewOrder,
+}
+
+// Strategy file: like an orders file with the sequence number in front.
+#[derive(Debug, Clone, Default)]
+pub struct Strategy {
+    pub injected: Vec<Injected>,
+}
+
+impl DataParser for Strategy {
+    type Item = Injected;
+    type Err = OrderErrors;
+
+    fn new() -> Strategy {
+        Strategy::default()
+    }
+
+    fn insert(&mut self, _index: usize, data: Self::Item) {
+        self.injected.push(data);
+    }
+
+    fn parse(line: &str) -> Result<Self::Item, Self::Err> {
+        let line = line.trim_start();
+        let (sequence, order) = line
+            .split_once(char::is_whitespace)
+            .ok_or(OrderErrors::ParseInsufficentInputError)?;
+        Ok(Injected {
+            sequence: sequence
+                .parse()
+                .map_err(|_| OrderErrors::ParseSequenceError)?,
+            order: NewOrder::from_str(order)?,
+        })
+    }
+
+    fn parse_record(record: &Record) -> Result<Self::Item, Self::Err> {
+        Ok(Injected {
+            sequence: record
+                .get("sequence")
+                .ok_or(OrderErrors::ParseInsufficentInputError)?
+                .parse()
+                .map_err(|_| OrderErrors::ParseSequenceError)?,
+            order: NewOrder::from_record(record)?,
+        })
+    }
+
+    fn remove(&mut self, orders: Vec<Self::Item>) {
+        self.injected.retain(|injected| !orders.contains(injected));
+    }
+}
+
+// Orders resting ahead of an order on its side of the book when it came to
+// rest: those an incoming order able to fill them all would fill first. The
+// engine tries both sides lowest priced first and then by time, so a bid
+// queues behind lower bids, not higher ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Queue {
+    pub orders: usize,
+    pub volume: u64,
+}
+
+// How one strategy order fared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub sequence: usize,
+    pub order: NewOrder,
+    pub filled: u64,
+    pub notional: u64,
+    // None when the order never rested.
+    pub queue: Option<Queue>,
+    // Volume still resting once the whole market has been replayed.
+    pub resting: Volume,
+    // Why the engine turned the order away, if it did.
+    pub rejected: Option<String>,
+}
+
+impl Execution {
+    pub fn average_price(&self) -> Option<f64> {
+        match self.filled {
+            0 => None,
+            filled => Some(self.notional as f64 / filled as f64),
+        }
+    }
+}
+
+impl std::fmt::Display for Execution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let order = &self.order;
+        let side = match order.operation {
+            OrderType::Buy => "buy",
+            _ => "sell",
+        };
+        let average_price = self
+            .average_price()
+            .map_or("-".to_string(), |price| format!("{:.2}", price));
+        let (queue_orders, queue_volume) = self
+            .queue
+            .map_or(("-".to_string(), "-".to_string()), |queue| {
+                (queue.orders.to_string(), queue.volume.to_string())
+            });
+        let status = self
+            .rejected
+            .as_ref()
+            .map_or("accepted".to_string(), |reason| {
+                format!("rejected: {}", reason)
+            });
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.sequence,
+            order.index,
+            order.client_name,
+            side,
+            order.asset,
+            order.order_price,
+            order.value,
+            self.filled,
+            average_price,
+            queue_orders,
+            queue_volume,
+            self.resting,
+            status
+        )
+    }
+}
+
+// P&L of the strategy's own fills as one client, once the market has been
+// replayed. The client's opening inventory and its orders in the market file
+// do not count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientPnl {
+    pub client: String,
+    pub realized: f64,
+    pub unrealized: f64,
+}
+
+impl std::fmt::Display for ClientPnl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{:.2}\t{:.2}",
+            self.client, self.realized, self.unrealized
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Backtest {
+    pub executions: Vec<Execution>,
+    pub pnl: Vec<ClientPnl>,
+    // Every fill of the run, the market's own included.
+    pub trades: Vec<Trade>,
+    // Market orders the engine rejected and the run went on without, by
+    // their index in the orders file, with the reason.
+    pub skipped: Vec<(usize, String)>,
+}
+
+// Replays the market through the engine with the strategy's orders slotted
+// in. Strategy orders with the same sequence number go in file order, and
+// those numbered past the end of the market after it. A strategy order the
+// engine rejects is reported with its execution. A rejected market order
+// ends the backtest, or is skipped when on_error says so.
+pub fn run(
+    engine: &mut Engine,
+    market: NewOrders,
+    strategy: Strategy,
+    on_error: OnError,
+) -> Result<Backtest, TradeMatchErrors> {
+    let mut injected = strategy.injected;
+    injected.sort_by_key(|injected| injected.sequence);
+    let mut injected = injected.into_iter().peekable();
+
+    let mut tracker = Tracker::default();
+    let mut skipped = Vec::new();
+    let mut index = 0;
+    let mut market = market.order.into_iter();
+    for sequence in 0.. {
+        while let Some(Injected { sequence, order }) =
+            injected.next_if(|injected| injected.sequence <= sequence)
+        {
+            index += 1;
+            tracker.inject(engine, sequence, NewOrder { index, ..order });
+        }
+        let Some((line, order)) = market.next() else {
+            break;
+        };
+        index += 1;
+        match engine.enter(&NewOrder { index, ..order }) {
+            Ok(fills) => tracker.record(engine, fills),
+            Err(error) if on_error == OnError::Skip => skipped.push((line, error.to_string())),
+            Err(error) => return Err(error),
+        }
+    }
+    for Injected { sequence, order } in injected {
+        index += 1;
+        tracker.inject(engine, sequence, NewOrder { index, ..order });
+    }
+
+    let Tracker {
+        mut executions,
+        pnl,
+        trades,
+        ..
+    } = tracker;
+    for execution in executions.iter_mut() {
+        execution.resting = engine
+            .get_order(execution.order.index)
+            .map_or(0, |order| order.value);
+    }
+    let pnl = pnl
+        .into_iter()
+        .map(|(client, pnl)| ClientPnl {
+            client,
+            realized: pnl.realized(),
+            unrealized: pnl.unrealized_at(&engine.quotes),
+        })
+        .collect();
+    Ok(Backtest {
+        executions,
+        pnl,
+        trades,
+        skipped,
+    })
+}
+
+// Executions of the strategy orders sent so far, by index, and the P&L of
+// their fills by the client they were sent as.
+#[derive(Default)]
+struct Tracker {
+    executions: Vec<Execution>,
+    positions: BTreeMap<usize, usize>,
+    pnl: BTreeMap<String, Pnl>,
+    trades: Vec<Trade>,
+}
+
+impl Tracker {
+    fn inject(&mut self, engine: &mut Engine, sequence: usize, order: NewOrder) {
+        let index = order.index;
+        if let Some(client) = engine.clients.get(&order.client_name) {
+            self.pnl.entry(client.name.clone()).or_insert_with(|| Pnl {
+                method: client.pnl.method,
+                ..Pnl::default()
+            });
+        }
+        self.positions.insert(index, self.executions.len());
+        self.executions.push(Execution {
+            sequence,
+            order,
+            filled: 0,
+            notional: 0,
+            queue: None,
+            resting: 0,
+            rejected: None,
+        });
+        let execution = self.executions.len() - 1;
+        match engine.enter(&self.executions[execution].order) {
+            Ok(fills) => {
+                self.record(engine, fills);
+                self.executions[execution].queue = queue(engine, index);
+            }
+            Err(error) => self.executions[execution].rejected = Some(error.to_string()),
+        }
+    }
+
+    // Adds fills to the strategy orders on either side of them and to the
+    // P&L of the clients that sent those orders.
+    fn record(&mut self, engine: &Engine, mut fills: Vec<Trade>) {
+        for fill in &fills {
+            let opening = engine
+                .quote(&fill.asset)
+                .and_then(|quote| quote.open_price)
+                .unwrap_or(fill.price);
+            for (index, client, side) in [
+                (fill.buy_index, &fill.buyer, OrderType::Buy),
+                (fill.sell_index, &fill.seller, OrderType::Sell),
+            ] {
+                let Some(&position) = self.positions.get(&index) else {
+                    continue;
+                };
+                let execution = &mut self.executions[position];
+                execution.filled += u64::from(fill.volume);
+                execution.notional += u64::from(fill.price) * u64::from(fill.volume);
+                if let Some(pnl) = self.pnl.get_mut(&**client) {
+                    match side {
+                        OrderType::Buy => pnl.buy(&fill.asset, fill.price, fill.volume, opening),
+                        _ => pnl.sell(&fill.asset, fill.price, fill.volume, opening),
+                    }
+                }
+            }
+        }
+        self.trades.append(&mut fills);
+    }
+}
+
+fn queue(engine: &Engine, index: usize) -> Option<Queue> {
+    let resting = engine.get_order(index)?;
+    // An order from no client that would fill against every order on the
+    // resting order's side, which the engine tries in its own order.
+    let incoming = |operation, order_price| Order {
+        index: usize::MAX,
+        client_id: Symbol(u32::MAX),
+        operation,
+        order_price,
+        ..resting
+    };
+    let candidates = match resting.operation {
+        OrderType::Buy => sell_candidates(&engine.buy_orders, &incoming(OrderType::Sell, 0)),
+        _ => buy_candidates(&engine.sell_orders, &incoming(OrderType::Buy, u32::MAX)),
+    };
+    // A bid at 0 is never tried, so everything that is goes before it.
+    let ahead = candidates
+        .iter()
+        .position(|other| other.index == index)
+        .unwrap_or(candidates.len());
+    Some(Queue {
+        orders: ahead,
+        volume: candidates[..ahead]
+            .iter()
+            .map(|other| u64::from(other.value))
+            .sum(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::{Client, Clients},
+        errors::GeneralErrors,
+        read_file,
+    };
+
+    fn engine() -> Engine {
+        let clients: Clients = read_file("./Clients.txt".to_string()).unwrap();
+        Engine::new(clients)
+    }
+
+    fn market() -> NewOrders {
+        read_file("./Orders.txt".to_string()).unwrap()
+    }
+
+    fn small_engine(lines: &[&str]) -> Engine {
+        let mut clients = Clients::new();
+        for (index, line) in lines.iter().enumerate() {
+            clients.insert(index + 1, Client::from_str(line).unwrap());
+        }
+        Engine::new(clients)
+    }
+
+    fn small_market(lines: &[&str]) -> NewOrders {
+        let mut market = NewOrders::new();
+        for (index, line) in lines.iter().enumerate() {
+            market.insert(index + 1, NewOrder::from_str(line).unwrap());
+        }
+        market
+    }
+
+    #[test]
+    fn test_parse() {
+        let injected = Strategy::parse("12\tC1\tb\tA\t10\t5").unwrap();
+        assert_eq!(injected.sequence, 12);
+        assert_eq!(injected.order.client_name, "C1");
+        assert_eq!(injected.order.value, 5);
+        assert_eq!(
+            Strategy::parse("x\tC1\tb\tA\t10\t5").unwrap_err(),
+            OrderErrors::ParseSequenceError
+        );
+    }
+
+    // Without a strategy the backtest is a plain replay.
+    #[test]
+    fn test_empty_strategy() {
+        let mut expected = engine();
+        let expected = expected.match_orders(market()).unwrap();
+        let backtest = run(&mut engine(), market(), Strategy::default(), OnError::Stop).unwrap();
+        assert!(backtest.executions.is_empty());
+        assert_eq!(backtest.trades, expected);
+    }
+
+    #[test]
+    fn test_run() {
+        let strategy = Strategy {
+            injected: vec![
+                // Far from the market: rests behind nothing and never fills.
+                Strategy::parse("0\tC1\tb\tA\t1\t5").unwrap(),
+                // Joins the first order's level, behind it.
+                Strategy::parse("1\tC1\tb\tC\t15\t4").unwrap(),
+            ],
+        };
+        let backtest = run(&mut engine(), market(), strategy, OnError::Stop).unwrap();
+
+        let far = &backtest.executions[0];
+        assert_eq!(far.order.index, 1);
+        assert_eq!(far.queue, Some(Queue::default()));
+        assert_eq!(far.filled, 0);
+        assert_eq!(far.resting, 5);
+
+        let joined = &backtest.executions[1];
+        assert_eq!(joined.order.index, 3);
+        assert_eq!(
+            joined.queue,
+            Some(Queue {
+                orders: 1,
+                volume: 4
+            })
+        );
+        assert_eq!(u64::from(joined.resting) + joined.filled, 4);
+        assert_eq!(backtest.pnl.len(), 1);
+
+        // Numbered past the end of the market, it still goes in last.
+        let mut market = market();
+        market.order.retain(|index, _| *index <= 10);
+        let strategy = Strategy {
+            injected: vec![Strategy::parse("100000\tC1\tb\tA\t1\t5").unwrap()],
+        };
+        let backtest = run(&mut engine(), market, strategy, OnError::Stop).unwrap();
+        assert_eq!(backtest.executions[0].order.index, 11);
+    }
+
+    // Only the strategy's fills count towards its P&L, not the client's
+    // opening inventory or its own orders in the market file.
+    #[test]
+    fn test_strategy_pnl() {
+        let mut engine = small_engine(&[
+            "C1\t1000\t10\t0\t0\t0",
+            "C2\t1000\t10\t0\t0\t0",
+            "C3\t1000\t0\t0\t0\t0",
+        ]);
+        let market = small_market(&[
+            "C2\ts\tA\t10\t10",
+            "C3\tb\tA\t10\t6",
+            "C3\tb\tA\t12\t5",
+            // Sells 5 of C1's opening units at 12.
+            "C1\ts\tA\t11\t5",
+        ]);
+        let strategy = Strategy {
+            injected: vec![Strategy::parse("1\tC1\tb\tA\t10\t4").unwrap()],
+        };
+        let backtest = run(&mut engine, market, strategy, OnError::Stop).unwrap();
+
+        assert_eq!(backtest.executions[0].filled, 4);
+        // 4 bought at 10 and marked at 12.
+        assert_eq!(
+            backtest.pnl,
+            [ClientPnl {
+                client: "C1".to_string(),
+                realized: 0.0,
+                unrealized: 8.0
+            }]
+        );
+        let c1 = engine.clients.get("C1").unwrap();
+        assert_eq!(c1.realized_pnl(), 10.0);
+    }
+
+    // A sell fills the lower bid first, so the higher bid queues behind it
+    // and fills only with what is left.
+    #[test]
+    fn test_bid_queue() {
+        let mut engine = small_engine(&["C1\t1000\t0\t0\t0\t0", "C2\t0\t10\t0\t0\t0"]);
+        let market = small_market(&["C2\ts\tA\t5\t6"]);
+        let strategy = Strategy {
+            injected: vec![
+                Strategy::parse("0\tC1\tb\tA\t10\t4").unwrap(),
+                Strategy::parse("0\tC1\tb\tA\t12\t4").unwrap(),
+            ],
+        };
+        let backtest = run(&mut engine, market, strategy, OnError::Stop).unwrap();
+
+        let (lower, higher) = (&backtest.executions[0], &backtest.executions[1]);
+        assert_eq!(lower.queue, Some(Queue::default()));
+        assert_eq!(
+            higher.queue,
+            Some(Queue {
+                orders: 1,
+                volume: 4
+            })
+        );
+        assert_eq!((lower.filled, higher.filled), (4, 2));
+        assert_eq!(backtest.trades[0].buy_index, lower.order.index);
+    }
+
+    #[test]
+    fn test_rejections() {
+        let mut market = market();
+        market.order.retain(|index, _| *index <= 10);
+        market.order.get_mut(&4).unwrap().client_name = "C99".to_string();
+        let strategy = Strategy {
+            injected: vec![
+                Strategy::parse("2\tC99\tb\tA\t1\t5").unwrap(),
+                Strategy::parse("3\tC1\tb\tA\t1\t5").unwrap(),
+            ],
+        };
+
+        // The strategy's own rejects never end the run, the market's only do
+        // unless they are skipped.
+        assert!(run(
+            &mut engine(),
+            market.clone(),
+            strategy.clone(),
+            OnError::Stop
+        )
+        .is_err());
+        let backtest = run(&mut engine(), market, strategy, OnError::Skip).unwrap();
+        assert_eq!(backtest.skipped.len(), 1);
+        assert_eq!(backtest.skipped[0].0, 4);
+
+        let rejected = &backtest.executions[0];
+        assert!(rejected.rejected.is_some());
+        assert_eq!((rejected.queue, rejected.resting), (None, 0));
+        assert!(rejected
+            .to_string()
+            .trim_end()
+            .ends_with(&format!("rejected: {}", GeneralErrors::GetClientError)));
+        let accepted = &backtest.executions[1];
+        assert_eq!(accepted.rejected, None);
+        assert_eq!(accepted.resting, 5);
+        assert!(accepted.to_string().trim_end().ends_with("\taccepted"));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    engine::Trade,
+    errors::{BinaryErrors, GeneralErrors, TradeMatchErrors},
+    orders::{NewOrder, NewOrders, Order, OrderType},
+    symbols::Registry,
+    Price, Volume,
+};
+use std::{
+    io::{self, Read},
+    path::Path,
+    str,
+};
+
+// Fixed-layout binary messages. A stream starts with MAGIC and is followed by
+// MESSAGE_SIZE byte messages, integers little-endian and names zero padded:
+//
+//   0  kind         u8   b'O' order, b'C' cancel, b'T' trade
+//   1  side         u8   b'b' or b's' for orders, 0 otherwise
+//   4  price        u32  order limit or trade price
+//   8  volume       u32
+//   12 limit        u32  buy limit of a trade
+//   16 index        u64  order index, cancelled index or buy index of a trade
+//   24 other_index  u64  sell index of a trade
+//   32 client       [u8; 8]  order client or buyer
+//   40 counterparty [u8; 8]  seller of a trade
+//   48 asset        [u8; 8]
+//   56 other_limit  u32  sell limit of a trade
+pub const MAGIC: &[u8; 8] = b"TMATCH\x00\x01";
+pub const MESSAGE_SIZE: usize = 64;
+pub const NAME_SIZE: usize = 8;
+
+const KIND: usize = 0;
+const SIDE: usize = 1;
+const PRICE: usize = 4;
+const VOLUME: usize = 8;
+const LIMIT: usize = 12;
+const INDEX: usize = 16;
+const OTHER_INDEX: usize = 24;
+const CLIENT: usize = 32;
+const COUNTERPARTY: usize = 40;
+const ASSET: usize = 48;
+const OTHER_LIMIT: usize = 56;
+
+type Result<T> = std::result::Result<T, BinaryErrors>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Order,
+    Cancel,
+    Trade,
+}
+
+pub fn encode_order(order: &NewOrder) -> Result<[u8; MESSAGE_SIZE]> {
+    let mut bytes = [0; MESSAGE_SIZE];
+    bytes[KIND] = b'O';
+    bytes[SIDE] = match order.operation {
+        OrderType::Buy => b'b',
+        OrderType::Sell => b's',
+        OrderType::IsNotOrderType => return Err(BinaryErrors::InvalidSideError(0)),
+    };
+    put_u32(&mut bytes, PRICE, order.order_price);
+    put_u32(&mut bytes, VOLUME, order.value);
+    put_u64(&mut bytes, INDEX, order.index as u64);
+    put_name(&mut bytes, CLIENT, &order.client_name)?;
+    put_name(&mut bytes, ASSET, &order.asset)?;
+    Ok(bytes)
+}
+
+pub fn encode_cancel(index: usize) -> [u8; MESSAGE_SIZE] {
+    let mut bytes = [0; MESSAGE_SIZE];
+    bytes[KIND] = b'C';
+    put_u64(&mut bytes, INDEX, index as u64);
+    bytes
+}
+
+pub fn encode_trade(trade: &Trade) -> Result<[u8; MESSAGE_SIZE]> {
+    let mut bytes = [0; MESSAGE_SIZE];
+    bytes[KIND] = b'T';
+    put_u32(&mut bytes, PRICE, trade.price);
+    put_u32(&mut bytes, VOLUME, trade.volume);
+    put_u32(&mut bytes, LIMIT, trade.buy_limit);
+    put_u32(&mut bytes, OTHER_LIMIT, trade.sell_limit);
+    put_u64(&mut bytes, INDEX, trade.buy_index as u64);
+    put_u64(&mut bytes, OTHER_INDEX, trade.sell_index as u64);
+    put_name(&mut bytes, CLIENT, &trade.buyer)?;
+    put_name(&mut bytes, COUNTERPARTY, &trade.seller)?;
+    put_name(&mut bytes, ASSET, &trade.asset)?;
+    Ok(bytes)
+}
+
+// Converts parsed orders, in index order, into a binary stream.
+pub fn encode_orders(orders: &NewOrders) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + orders.order.len() * MESSAGE_SIZE);
+    bytes.extend_from_slice(MAGIC);
+    for order in orders.order.values() {
+        bytes.extend_from_slice(&encode_order(order)?);
+    }
+    Ok(bytes)
+}
+
+// Binary streams are recognised by their .bin extension.
+pub fn is_binary(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("bin"))
+}
+
+// Messages of a binary stream, borrowed from `bytes` without copying.
+pub fn messages(bytes: &[u8]) -> Result<impl Iterator<Item = MessageView<'_>>> {
+    let body = bytes
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or(BinaryErrors::MagicError)?;
+    if body.len() % MESSAGE_SIZE != 0 {
+        return Err(BinaryErrors::LengthError(body.len()));
+    }
+    Ok(body
+        .chunks_exact(MESSAGE_SIZE)
+        .map(|bytes| MessageView { bytes }))
+}
+
+// Messages read from `reader` one at a time, for streams too large to hold
+// in memory. Each item is a copy of one message to take a view of.
+pub fn stream<'a>(
+    mut reader: impl Read + 'a,
+    file_path: &str,
+) -> std::result::Result<
+    impl Iterator<Item = std::result::Result<[u8; MESSAGE_SIZE], TradeMatchErrors>> + 'a,
+    TradeMatchErrors,
+> {
+    let file = file_path.to_string();
+    let io_error = move |source| TradeMatchErrors::IoError {
+        path: file.clone(),
+        source,
+    };
+    let mut magic = [0; MAGIC.len()];
+    match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => {}
+        Ok(()) => return Err(BinaryErrors::MagicError.into()),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(BinaryErrors::MagicError.into())
+        }
+        Err(error) => return Err(io_error(error)),
+    }
+    Ok(std::iter::from_fn(move || {
+        let mut bytes = [0; MESSAGE_SIZE];
+        let mut filled = 0;
+        while filled < MESSAGE_SIZE {
+            match reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => return Some(Err(BinaryErrors::LengthError(filled).into())),
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Some(Err(io_error(error))),
+            }
+        }
+        Some(Ok(bytes))
+    }))
+}
+
+// Read-only view of one message. Fields are decoded on access, and names
+// are returned as slices of the underlying buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageView<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> MessageView<'a> {
+    pub fn new(bytes: &'a [u8; MESSAGE_SIZE]) -> MessageView<'a> {
+        MessageView { bytes }
+    }
+
+    pub fn kind(&self) -> Result<Kind> {
+        match self.bytes[KIND] {
+            b'O' => Ok(Kind::Order),
+            b'C' => Ok(Kind::Cancel),
+            b'T' => Ok(Kind::Trade),
+            kind => Err(BinaryErrors::InvalidKindError(kind)),
+        }
+    }
+
+    pub fn side(&self) -> Result<OrderType> {
+        match self.bytes[SIDE] {
+            b'b' => Ok(OrderType::Buy),
+            b's' => Ok(OrderType::Sell),
+            side => Err(BinaryErrors::InvalidSideError(side)),
+        }
+    }
+
+    pub fn price(&self) -> Price {
+        self.u32(PRICE)
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.u32(VOLUME)
+    }
+
+    pub fn index(&self) -> usize {
+        self.u64(INDEX) as usize
+    }
+
+    pub fn client(&self) -> Result<&'a str> {
+        self.name(CLIENT)
+    }
+
+    pub fn asset(&self) -> Result<&'a str> {
+        self.name(ASSET)
+    }
+
+    // The order as the engine takes it. Its names are looked up in the
+    // registry straight from the buffer, so nothing is copied.
+    pub fn to_order(&self, registry: &Registry) -> std::result::Result<Order, TradeMatchErrors> {
+        Ok(Order {
+            index: self.index(),
+            client_id: registry
+                .clients
+                .get(self.client()?)
+                .ok_or(GeneralErrors::GetClientError)?,
+            operation: self.side()?,
+            asset_id: registry
+                .assets
+                .get(self.asset()?)
+                .ok_or(GeneralErrors::GetAssetError)?,
+            order_price: self.price(),
+            value: self.volume(),
+        })
+    }
+
+    pub fn to_new_order(&self) -> Result<NewOrder> {
+        Ok(NewOrder {
+            index: self.index(),
+            client_name: self.client()?.to_string(),
+            operation: self.side()?,
+            asset: self.asset()?.into(),
+            order_price: self.price(),
+            value: self.volume(),
+        })
+    }
+
+    pub fn to_trade(&self) -> Result<Trade> {
+        Ok(Trade {
+            buy_index: self.index(),
+            sell_index: self.u64(OTHER_INDEX) as usize,
+            buyer: self.client()?.into(),
+            seller: self.name(COUNTERPARTY)?.into(),
+            asset: self.asset()?.into(),
+            price: self.price(),
+            volume: self.volume(),
+            buy_limit: self.u32(LIMIT),
+            sell_limit: self.u32(OTHER_LIMIT),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        let mut field = [0; 4];
+        field.copy_from_slice(&self.bytes[offset..offset + 4]);
+        u32::from_le_bytes(field)
+    }
+
+    fn u64(&self, offset: usize) -> u64 {
+        let mut field = [0; 8];
+        field.copy_from_slice(&self.bytes[offset..offset + 8]);
+        u64::from_le_bytes(field)
+    }
+
+    fn name(&self, offset: usize) -> Result<&'a str> {
+        let field = &self.bytes[offset..offset + NAME_SIZE];
+        let length = field
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(NAME_SIZE);
+        str::from_utf8(&field[..length]).map_err(|_| BinaryErrors::InvalidNameError)
+    }
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_name(bytes: &mut [u8], offset: usize, name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > NAME_SIZE || name.contains('\0') {
+        return Err(BinaryErrors::NameLengthError(name.to_string()));
+    }
+    bytes[offset..offset + name.len()].copy_from_slice(name.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataParser;
+
+    fn order(index: usize, client: &str, operation: OrderType) -> NewOrder {
+        NewOrder {
+            index,
+            client_name: client.to_string(),
+            operation,
+            asset: "A".to_string(),
+            order_price: 12,
+            value: 4,
+        }
+    }
+
+    #[test]
+    fn test_orders_round_trip() {
+        let mut orders = NewOrders::new();
+        orders.insert(1, order(1, "C1", OrderType::Buy));
+        orders.insert(2, order(2, "CLIENT08", OrderType::Sell));
+        let bytes = encode_orders(&orders).unwrap();
+        assert_eq!(bytes.len(), MAGIC.len() + 2 * MESSAGE_SIZE);
+
+        let decoded: Vec<NewOrder> = messages(&bytes)
+            .unwrap()
+            .map(|message| message.to_new_order().unwrap())
+            .collect();
+        assert_eq!(decoded, orders.order.into_values().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_to_order() {
+        let mut registry = Registry::new();
+        registry.clients.intern("C0");
+        let c1 = registry.clients.intern("C1");
+        let a = registry.assets.intern("A");
+        let bytes = encode_order(&order(3, "C1", OrderType::Sell)).unwrap();
+        let decoded = MessageView::new(&bytes).to_order(&registry).unwrap();
+        assert_eq!(
+            (decoded.index, decoded.client_id, decoded.asset_id),
+            (3, c1, a)
+        );
+        assert_eq!(decoded.operation, OrderType::Sell);
+        assert_eq!((decoded.order_price, decoded.value), (12, 4));
+
+        let bytes = encode_order(&order(4, "C9", OrderType::Buy)).unwrap();
+        assert!(matches!(
+            MessageView::new(&bytes).to_order(&registry),
+            Err(TradeMatchErrors::EngineError(GeneralErrors::GetClientError))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_and_trade() {
+        let trade = Trade {
+            buy_index: 7,
+            sell_index: 3,
+            buyer: "C1".into(),
+            seller: "C2".into(),
+            asset: "B".into(),
+            price: 10,
+            volume: 2,
+            buy_limit: 11,
+            sell_limit: 9,
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&encode_cancel(5));
+        bytes.extend_from_slice(&encode_trade(&trade).unwrap());
+
+        let decoded: Vec<MessageView> = messages(&bytes).unwrap().collect();
+        assert_eq!(decoded[0].kind().unwrap(), Kind::Cancel);
+        assert_eq!(decoded[0].index(), 5);
+        assert_eq!(decoded[1].kind().unwrap(), Kind::Trade);
+        assert_eq!(decoded[1].to_trade().unwrap(), trade);
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&encode_cancel(3));
+        bytes.extend_from_slice(&encode_order(&order(4, "C1", OrderType::Sell)).unwrap());
+        let messages: Vec<[u8; MESSAGE_SIZE]> = stream(bytes.as_slice(), "-")
+            .unwrap()
+            .map(|message| message.unwrap())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(MessageView::new(&messages[0]).index(), 3);
+        assert_eq!(
+            MessageView::new(&messages[1]).to_new_order().unwrap(),
+            order(4, "C1", OrderType::Sell)
+        );
+
+        bytes.truncate(bytes.len() - 1);
+        let last = stream(bytes.as_slice(), "-").unwrap().last().unwrap();
+        assert!(matches!(
+            last,
+            Err(TradeMatchErrors::BinaryError(BinaryErrors::LengthError(63)))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_streams() {
+        assert_eq!(messages(b"TMATCH").err(), Some(BinaryErrors::MagicError));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[0; 10]);
+        assert_eq!(messages(&bytes).err(), Some(BinaryErrors::LengthError(10)));
+        assert_eq!(
+            encode_order(&order(1, "LONGCLIENT", OrderType::Buy)),
+            Err(BinaryErrors::NameLengthError("LONGCLIENT".to_string()))
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{
+    orders::{Order, Orders},
+    symbols::{Registry, Symbol},
+    Price, Volume,
+};
+use std::collections::BTreeSet;
+
+// Aggregated resting volume at one price (Level 2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level {
+    pub price: Price,
+    pub volume: Volume,
+    pub orders: usize,
+}
+
+// Level 2 snapshot of one asset: bids best (highest) first, asks best (lowest) first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depth {
+    pub asset: String,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+// Level 3 snapshot of one asset: every resting order, bids highest first
+// and asks lowest first, earlier first within a price. Asks are listed in
+// the order the engine fills them, bids are not: an incoming sell fills
+// the lowest bid above its price first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderDepth {
+    pub asset: String,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+// Snapshots are of an asset by name and by the id the registry gave it,
+// None when it has none and so nothing can rest.
+impl OrderDepth {
+    pub fn new(
+        asset: &str,
+        id: Option<Symbol>,
+        buy_orders: &Orders,
+        sell_orders: &Orders,
+    ) -> OrderDepth {
+        let mut bids = resting(id, buy_orders);
+        let mut asks = resting(id, sell_orders);
+        // Stable sorts keep index (time) priority within a price.
+        bids.sort_by_key(|order| std::cmp::Reverse(order.order_price));
+        asks.sort_by_key(|order| order.order_price);
+        OrderDepth {
+            asset: asset.to_string(),
+            bids,
+            asks,
+        }
+    }
+
+    // The snapshot with its clients named, for printing.
+    pub fn named<'a>(&'a self, registry: &'a Registry) -> NamedOrderDepth<'a> {
+        NamedOrderDepth {
+            depth: self,
+            registry,
+        }
+    }
+}
+
+pub struct NamedOrderDepth<'a> {
+    depth: &'a OrderDepth,
+    registry: &'a Registry,
+}
+
+impl Depth {
+    pub fn new(
+        asset: &str,
+        id: Option<Symbol>,
+        buy_orders: &Orders,
+        sell_orders: &Orders,
+    ) -> Depth {
+        Depth::from(&OrderDepth::new(asset, id, buy_orders, sell_orders))
+    }
+}
+
+impl From<&OrderDepth> for Depth {
+    fn from(depth: &OrderDepth) -> Depth {
+        Depth {
+            asset: depth.asset.clone(),
+            bids: aggregate(&depth.bids),
+            asks: aggregate(&depth.asks),
+        }
+    }
+}
+
+// Every asset with at least one resting order.
+pub fn assets(buy_orders: &Orders, sell_orders: &Orders) -> BTreeSet<Symbol> {
+    buy_orders
+        .order
+        .values()
+        .chain(sell_orders.order.values())
+        .map(|order| order.asset_id)
+        .collect()
+}
+
+fn resting(asset: Option<Symbol>, orders: &Orders) -> Vec<Order> {
+    orders
+        .order
+        .values()
+        .filter(|order| Some(order.asset_id) == asset)
+        .copied()
+        .collect()
+}
+
+// Volumes at a level are capped at the largest Volume.
+fn aggregate(orders: &[Order]) -> Vec<Level> {
+    let mut levels: Vec<Level> = Vec::new();
+    for order in orders {
+        match levels.last_mut() {
+            Some(level) if level.price == order.order_price => {
+                level.volume = level.volume.saturating_add(order.value);
+                level.orders += 1;
+            }
+            _ => levels.push(Level {
+                price: order.order_price,
+                volume: order.value,
+                orders: 1,
+            }),
+        }
+    }
+    levels
+}
+
+// Preparing price levels for recording: asset, side, price, volume, order count.
+impl std::fmt::Display for Depth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (side, levels) in [("bid", &self.bids), ("ask", &self.asks)] {
+            for level in levels {
+                writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}",
+                    self.asset, side, level.price, level.volume, level.orders
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Preparing resting orders for recording: asset, side, price, volume, index, client.
+impl std::fmt::Display for NamedOrderDepth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let depth = self.depth;
+        for (side, orders) in [("bid", &depth.bids), ("ask", &depth.asks)] {
+            for order in orders {
+                writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    depth.asset,
+                    side,
+                    order.order_price,
+                    order.value,
+                    order.index,
+                    self.registry
+                        .clients
+                        .name(order.client_id)
+                        .map_or("?", |name| name)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::OrderType;
+
+    const A: Symbol = Symbol(0);
+    const B: Symbol = Symbol(1);
+
+    fn order(index: usize, operation: OrderType, asset: Symbol, price: u32, value: u32) -> Order {
+        Order {
+            index,
+            client_id: Symbol(index as u32),
+            operation,
+            asset_id: asset,
+            order_price: price,
+            value,
+        }
+    }
+
+    fn books() -> (Orders, Orders) {
+        let mut buy_orders = Orders::new();
+        let mut sell_orders = Orders::new();
+        buy_orders.insert(1, order(1, OrderType::Buy, A, 10, 3));
+        buy_orders.insert(2, order(2, OrderType::Buy, A, 12, 1));
+        buy_orders.insert(3, order(3, OrderType::Buy, A, 10, 2));
+        buy_orders.insert(4, order(4, OrderType::Buy, B, 99, 2));
+        sell_orders.insert(5, order(5, OrderType::Sell, A, 15, 4));
+        sell_orders.insert(6, order(6, OrderType::Sell, A, 14, 6));
+        (buy_orders, sell_orders)
+    }
+
+    #[test]
+    fn test_order_depth() {
+        let (buy_orders, sell_orders) = books();
+        let depth = OrderDepth::new("A", Some(A), &buy_orders, &sell_orders);
+
+        let bids: Vec<usize> = depth.bids.iter().map(|order| order.index).collect();
+        let asks: Vec<usize> = depth.asks.iter().map(|order| order.index).collect();
+        assert_eq!(bids, vec![2, 1, 3]);
+        assert_eq!(asks, vec![6, 5]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let (buy_orders, sell_orders) = books();
+        let depth = Depth::new("A", Some(A), &buy_orders, &sell_orders);
+
+        assert_eq!(
+            depth.bids,
+            vec![
+                Level {
+                    price: 12,
+                    volume: 1,
+                    orders: 1
+                },
+                Level {
+                    price: 10,
+                    volume: 5,
+                    orders: 2
+                }
+            ]
+        );
+        assert_eq!(depth.asks[0].price, 14);
+        assert_eq!(depth.to_string().lines().next(), Some("A\tbid\t12\t1\t1"));
+    }
+
+    #[test]
+    fn test_assets() {
+        let (buy_orders, sell_orders) = books();
+        let assets: Vec<Symbol> = assets(&buy_orders, &sell_orders).into_iter().collect();
+        assert_eq!(assets, vec![A, B]);
+    }
+}
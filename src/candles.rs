@@ -0,0 +1,240 @@
+use crate::{engine::Trade, Price};
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Instant};
+
+pub const CSV_HEADER: &str = "asset,bucket,open,high,low,close,volume,trades";
+
+// When a candle closes: after N trades of its asset, after N engine
+// messages, or after a wall-clock interval in seconds (server mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Trades(usize),
+    Messages(usize),
+    Interval(u64),
+}
+
+// OHLCV bar of one asset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candle {
+    pub asset: String,
+    pub bucket: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: u64,
+    pub trades: usize,
+}
+
+// Aggregates the fill stream into candles per asset.
+#[derive(Debug, Clone)]
+pub struct Candles {
+    pub bucket: Bucket,
+    open: BTreeMap<String, Candle>,
+    closed: Vec<Candle>,
+    // Per-asset candle counter for trade buckets.
+    sequence: BTreeMap<String, u64>,
+    messages: u64,
+    started: Instant,
+}
+
+impl Candles {
+    pub fn new(bucket: Bucket) -> Candles {
+        Candles {
+            bucket,
+            open: BTreeMap::new(),
+            closed: Vec::new(),
+            sequence: BTreeMap::new(),
+            messages: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // Feeds the fills produced by one engine message.
+    pub fn on_message(&mut self, trades: &[Trade]) {
+        self.tick(Instant::now());
+        let bucket = self.current_bucket(Instant::now());
+        for trade in trades {
+            let candle = self
+                .open
+                .entry(trade.asset.to_string())
+                .or_insert_with(|| Candle {
+                    asset: trade.asset.to_string(),
+                    bucket,
+                    open: trade.price,
+                    high: trade.price,
+                    low: trade.price,
+                    close: trade.price,
+                    volume: 0,
+                    trades: 0,
+                });
+            candle.high = candle.high.max(trade.price);
+            candle.low = candle.low.min(trade.price);
+            candle.close = trade.price;
+            candle.volume += u64::from(trade.volume);
+            candle.trades += 1;
+
+            if let Bucket::Trades(count) = self.bucket {
+                if candle.trades >= count {
+                    self.close(&trade.asset);
+                }
+            }
+        }
+
+        self.messages += 1;
+        if let Bucket::Messages(count) = self.bucket {
+            if self.messages.is_multiple_of(count.max(1) as u64) {
+                self.close_all();
+            }
+        }
+    }
+
+    // Closes candles whose wall-clock interval has passed.
+    pub fn tick(&mut self, now: Instant) {
+        if let Bucket::Interval(_) = self.bucket {
+            let bucket = self.current_bucket(now);
+            let expired: Vec<String> = self
+                .open
+                .values()
+                .filter(|candle| candle.bucket < bucket)
+                .map(|candle| candle.asset.clone())
+                .collect();
+            for asset in expired {
+                self.close(&asset);
+            }
+        }
+    }
+
+    // Candles closed since the last call.
+    pub fn take_closed(&mut self) -> Vec<Candle> {
+        std::mem::take(&mut self.closed)
+    }
+
+    // Closes whatever is still open at the end of a run.
+    pub fn finish(&mut self) -> Vec<Candle> {
+        self.close_all();
+        self.take_closed()
+    }
+
+    fn current_bucket(&self, now: Instant) -> u64 {
+        match self.bucket {
+            Bucket::Trades(_) => 0,
+            Bucket::Messages(count) => self.messages / count.max(1) as u64,
+            Bucket::Interval(seconds) => {
+                now.saturating_duration_since(self.started).as_secs() / seconds.max(1)
+            }
+        }
+    }
+
+    fn close(&mut self, asset: &str) {
+        if let Some(mut candle) = self.open.remove(asset) {
+            if let Bucket::Trades(_) = self.bucket {
+                let sequence = self.sequence.entry(asset.to_string()).or_insert(0);
+                candle.bucket = *sequence;
+                *sequence += 1;
+            }
+            self.closed.push(candle);
+        }
+    }
+
+    fn close_all(&mut self) {
+        let assets: Vec<String> = self.open.keys().cloned().collect();
+        for asset in assets {
+            self.close(&asset);
+        }
+    }
+}
+
+// Preparing candles for recording as a CSV row.
+impl std::fmt::Display for Candle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{},{}",
+            self.asset,
+            self.bucket,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume,
+            self.trades
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn trade(asset: &str, price: Price, volume: u32) -> Trade {
+        Trade {
+            buy_index: 1,
+            sell_index: 2,
+            buyer: "C1".into(),
+            seller: "C2".into(),
+            asset: asset.into(),
+            price,
+            volume,
+            buy_limit: price,
+            sell_limit: price,
+        }
+    }
+
+    #[test]
+    fn test_trade_buckets() {
+        let mut candles = Candles::new(Bucket::Trades(2));
+        candles.on_message(&[trade("A", 10, 1), trade("B", 50, 1)]);
+        candles.on_message(&[trade("A", 12, 2), trade("A", 9, 3)]);
+
+        let closed = candles.take_closed();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(
+            closed[0],
+            Candle {
+                asset: "A".to_string(),
+                bucket: 0,
+                open: 10,
+                high: 12,
+                low: 10,
+                close: 12,
+                volume: 3,
+                trades: 2,
+            }
+        );
+
+        let rest = candles.finish();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].bucket, 1);
+        assert_eq!(rest[0].open, 9);
+        assert_eq!(rest[1].asset, "B");
+    }
+
+    #[test]
+    fn test_message_buckets() {
+        let mut candles = Candles::new(Bucket::Messages(2));
+        candles.on_message(&[trade("A", 10, 1)]);
+        candles.on_message(&[]);
+        candles.on_message(&[trade("A", 11, 1)]);
+
+        let closed = candles.take_closed();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].close, 10);
+        assert_eq!(candles.finish()[0].bucket, 1);
+    }
+
+    #[test]
+    fn test_interval_buckets() {
+        let mut candles = Candles::new(Bucket::Interval(60));
+        candles.on_message(&[trade("A", 10, 1)]);
+        candles.tick(Instant::now());
+        assert!(candles.take_closed().is_empty());
+
+        candles.tick(Instant::now() + Duration::from_secs(61));
+        let closed = candles.take_closed();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].to_string(), "A,0,10,10,10,10,1,1\n");
+    }
+}
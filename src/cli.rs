@@ -0,0 +1,297 @@
+use crate::{config::FilePath, generator::Synthetic, OnError};
+use clap::{Parser, Subcommand, ValueEnum};
+
+// Command line of the trade_match binary. Path flags override config.yaml.
+#[derive(Debug, Parser)]
+#[command(name = "trade_match", version, about = "Order matching engine")]
+pub struct Cli {
+    /// Config file to read paths and settings from.
+    #[arg(long, global = true, default_value = "config")]
+    pub config: String,
+
+    /// Clients file, overriding `clients` from the config.
+    #[arg(long, global = true)]
+    pub clients: Option<String>,
+
+    /// Orders file, overriding `orders` from the config.
+    #[arg(long, global = true)]
+    pub orders: Option<String>,
+
+    /// Result file, overriding `result` from the config.
+    #[arg(long, global = true)]
+    pub output: Option<String>,
+
+    /// Stop at invalid input lines or skip them, overriding `on_error` from the config.
+    #[arg(long, global = true, value_enum)]
+    pub on_error: Option<OnError>,
+
+    /// Format of what is printed to stdout.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Tab)]
+    pub format: OutputFormat,
+
+    /// Match each asset's book on a thread of its own. Binary order files
+    /// are always matched on one thread.
+    #[arg(long, global = true)]
+    pub parallel: bool,
+
+    /// Progress on stderr: -v for a summary, -vv for every trade.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Match the orders file and write every configured output (the default).
+    Run,
+    /// Match the orders file and print the resulting trades.
+    Replay,
+    /// Parse the clients and orders files without matching and report every invalid line.
+    Validate,
+    /// Convert the orders file into binary messages for fast replay.
+    Convert {
+        /// Binary file to write, or - for stdout. Read back as orders when its
+        /// name ends in .bin or it is piped to --orders -.
+        destination: String,
+    },
+    /// Match the orders file and print what is left in the books.
+    Book {
+        /// 2 for aggregated price levels, 3 for individual orders.
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..=3))]
+        level: u8,
+        /// Only this asset.
+        #[arg(long)]
+        asset: Option<String>,
+    },
+    /// Match the orders file and print a report.
+    Report {
+        #[arg(long, value_enum, default_value_t = ReportKind::Quality)]
+        kind: ReportKind,
+    },
+    /// Write a seeded synthetic clients file and order flow.
+    Generate {
+        /// Clients file to write.
+        client_file: String,
+        /// Orders file to write. Cancels need a binary file ending in .bin.
+        order_file: String,
+        #[command(flatten)]
+        settings: Synthetic,
+    },
+    /// Replay the orders file with a strategy's orders slotted in and print
+    /// how each of them was filled, where it queued and the strategy's P&L.
+    Backtest {
+        /// Strategy orders, one per line: how many orders of the orders file
+        /// go through first, then the order's fields as in the orders file.
+        strategy: String,
+    },
+    /// Let the clients trade as market makers, momentum takers and noise
+    /// traders in turn and print how each of them ended up.
+    Simulate {
+        /// Number of steps. Every agent takes one turn in each.
+        #[arg(long, default_value_t = 1000)]
+        steps: usize,
+        /// Seed of the noise traders.
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+        /// Price the agents trade around until an asset first trades.
+        #[arg(long, default_value_t = 15)]
+        fair: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab separated, as in the output files.
+    Tab,
+    /// Columns padded for reading in a terminal.
+    Table,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportKind {
+    Quality,
+    Pnl,
+    Margin,
+    Ledger,
+}
+
+impl Cli {
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Run)
+    }
+
+    // Applies the path flags on top of the config file values.
+    pub fn apply(&self, mut file_path: FilePath) -> FilePath {
+        if let Some(clients) = &self.clients {
+            file_path.clients = clients.clone();
+        }
+        if let Some(orders) = &self.orders {
+            file_path.orders = orders.clone();
+        }
+        if let Some(output) = &self.output {
+            file_path.result = output.clone();
+        }
+        if let Some(on_error) = self.on_error {
+            file_path.on_error = on_error;
+        }
+        file_path
+    }
+}
+
+// Renders tab separated lines in the requested format.
+pub fn render(lines: &str, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Tab => lines.to_string(),
+        OutputFormat::Table => {
+            let rows: Vec<Vec<&str>> = lines
+                .lines()
+                .map(|line| line.split('\t').collect())
+                .collect();
+            let mut widths: Vec<usize> = Vec::new();
+            for row in &rows {
+                for (column, cell) in row.iter().enumerate() {
+                    if widths.len() <= column {
+                        widths.push(0);
+                    }
+                    widths[column] = widths[column].max(cell.len());
+                }
+            }
+            rows.iter()
+                .map(|row| {
+                    let cells: Vec<String> = row
+                        .iter()
+                        .enumerate()
+                        .map(|(column, cell)| format!("{:<width$}", cell, width = widths[column]))
+                        .collect();
+                    format!("{}\n", cells.join("  ").trim_end())
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_config;
+
+    #[test]
+    fn test_default_command() {
+        let cli = Cli::parse_from(["trade_match"]);
+        assert_eq!(cli.command(), Command::Run);
+        assert_eq!(cli.config, "config");
+        assert_eq!(cli.verbose, 0);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let cli = Cli::parse_from([
+            "trade_match",
+            "book",
+            "--level",
+            "3",
+            "--orders",
+            "./Other.txt",
+            "--output",
+            "./Out.txt",
+            "-vv",
+            "--on-error",
+            "skip",
+            "--parallel",
+        ]);
+        assert_eq!(
+            cli.command(),
+            Command::Book {
+                level: 3,
+                asset: None
+            }
+        );
+        assert_eq!(cli.verbose, 2);
+        assert!(cli.parallel);
+
+        let file_path = cli.apply(get_config().unwrap());
+        assert_eq!(file_path.orders, "./Other.txt");
+        assert_eq!(file_path.clients, "./Clients.txt");
+        assert_eq!(file_path.result, "./Out.txt");
+        assert_eq!(file_path.on_error, OnError::Skip);
+    }
+
+    #[test]
+    fn test_convert() {
+        let cli = Cli::parse_from(["trade_match", "convert", "./Orders.bin"]);
+        assert_eq!(
+            cli.command(),
+            Command::Convert {
+                destination: "./Orders.bin".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_generate() {
+        let cli = Cli::parse_from([
+            "trade_match",
+            "generate",
+            "./Clients.csv",
+            "./Orders.bin",
+            "--seed",
+            "7",
+            "--cancel-rate",
+            "0.1",
+        ]);
+        let Command::Generate {
+            client_file,
+            order_file,
+            settings,
+        } = cli.command()
+        else {
+            panic!("not the generate command");
+        };
+        assert_eq!(client_file, "./Clients.csv");
+        assert_eq!(order_file, "./Orders.bin");
+        assert_eq!(settings.seed, 7);
+        assert_eq!(settings.cancel_rate, 0.1);
+        assert_eq!(settings.num_clients, 10);
+    }
+
+    #[test]
+    fn test_backtest() {
+        let cli = Cli::parse_from(["trade_match", "backtest", "./Strategy.txt"]);
+        assert_eq!(
+            cli.command(),
+            Command::Backtest {
+                strategy: "./Strategy.txt".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_simulate() {
+        let cli = Cli::parse_from(["trade_match", "simulate", "--steps", "50"]);
+        assert_eq!(
+            cli.command(),
+            Command::Simulate {
+                steps: 50,
+                seed: 1,
+                fair: 15
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        assert!(Cli::try_parse_from(["trade_match", "book", "--level", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_render_table() {
+        let lines = "C1\t1000\t5\nC10\t7\t12\n";
+        assert_eq!(render(lines, OutputFormat::Tab), lines);
+        assert_eq!(
+            render(lines, OutputFormat::Table),
+            "C1   1000  5\nC10  7     12\n"
+        );
+    }
+}
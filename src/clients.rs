@@ -1,29 +1,51 @@
 use crate::errors::{ClientErrors, GeneralErrors};
-use crate::{orders::Order, DataParser};
-use std::{
-    collections::{btree_map::Entry, BTreeMap},
-    str::FromStr,
+use crate::{
+    formats::Record,
+    ledger::DOLLARS,
+    orders::Order,
+    pnl::{CostMethod, Pnl},
+    quotes::Quotes,
+    symbols::{Registry, Symbol, Symbols},
+    DataParser,
 };
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr};
 
+// Clients by the id the registry gave their name, with their balances by the
+// ids of their assets. Names are only looked up for input and output.
 #[derive(Debug, Clone)]
 pub struct Clients {
-    pub client: BTreeMap<String, Client>,
+    pub registry: Registry,
+    pub client: BTreeMap<Symbol, Client>,
 }
 
-#[derive(Debug, Clone)]
+// In JSON the client is named like its CSV column and balances are a list
+// of assets. Cost basis is not part of it and starts from the balances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
+    #[serde(skip)]
     pub index: usize,
+    #[serde(skip)]
+    pub id: Symbol,
+    #[serde(rename = "client")]
     pub name: String,
     pub dollar_balance: u32,
+    #[serde(rename = "assets")]
     pub asset_balances: Assets,
+    #[serde(skip)]
+    pub pnl: Pnl,
 }
 
-#[derive(Debug, Clone)]
+// A client read on its own numbers its assets in the order they were read.
+// Once it joins a Clients they are keyed by the registry's asset ids, which
+// are what the engine looks balances up by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Asset>", into = "Vec<Asset>")]
 pub struct Assets {
-    pub asset: BTreeMap<String, Asset>,
+    pub asset: BTreeMap<Symbol, Asset>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Asset {
     pub symbol: String,
     pub balance: u32,
@@ -32,69 +54,81 @@ pub struct Asset {
 type Result<T, E> = std::result::Result<T, E>;
 
 impl Clients {
-    pub fn get_mut<T>(&mut self, client_id: T) -> Option<&mut Client>
-    where
-        T: Into<String>,
-    {
-        let client_id = &client_id.into();
-        self.client.get_mut(client_id)
+    pub fn id(&self, name: &str) -> Option<Symbol> {
+        self.registry.clients.get(name)
     }
 
-    pub fn get_entry<T>(&mut self, client_id: T) -> Entry<String, Client>
-    where
-        T: Into<String>,
-    {
-        let client_id = client_id.into();
-        self.client.entry(client_id)
+    pub fn get(&self, name: &str) -> Option<&Client> {
+        self.client.get(&self.id(name)?)
     }
 
-    pub fn get<T>(&self, client_id: T) -> Option<Client>
-    where
-        T: Into<String>,
-    {
-        let client_id = &client_id.into();
-        Some(self.client.get(client_id)?.clone())
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Client> {
+        let id = self.id(name)?;
+        self.client.get_mut(&id)
+    }
+
+    pub fn by_id(&self, id: Symbol) -> Option<&Client> {
+        self.client.get(&id)
     }
-}
 
+    pub fn by_id_mut(&mut self, id: Symbol) -> Option<&mut Client> {
+        self.client.get_mut(&id)
+    }
+
+    // Clients in name order, the order every output lists them in.
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
+        let mut clients: Vec<&Client> = self.client.values().collect();
+        clients.sort_by(|a, b| a.name.cmp(&b.name));
+        clients.into_iter()
+    }
 
-// Preparing clients for recording.
+    pub fn len(&self) -> usize {
+        self.client.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.client.is_empty()
+    }
+
+    // Restarts every client's cost basis from its current balances.
+    pub fn set_cost_method(&mut self, method: CostMethod) {
+        for client in self.client.values_mut() {
+            client.pnl = Pnl::open(&client.asset_balances, method);
+        }
+    }
+}
+
+// Preparing clients for recording. The text layout always has the columns
+// A to D; a client read from CSV or JSON without one of them shows 0.
 impl std::fmt::Display for Client {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}\t", self.name))?;
-        f.write_fmt(format_args!("{}\t", self.dollar_balance))?;
-        f.write_fmt(format_args!(
-            "{}\t",
-            self.asset_balances.get("A").unwrap().balance
-        ))?;
-        f.write_fmt(format_args!(
-            "{}\t",
-            self.asset_balances.get("B").unwrap().balance
-        ))?;
-        f.write_fmt(format_args!(
-            "{}\t",
-            self.asset_balances.get("C").unwrap().balance
-        ))?;
-        f.write_fmt(format_args!(
-            "{}\n",
-            self.asset_balances.get("D").unwrap().balance
-        ))
+        f.write_fmt(format_args!("{}", self.dollar_balance))?;
+        for symbol in ["A", "B", "C", "D"] {
+            let balance = self.asset_balances.get(symbol);
+            f.write_fmt(format_args!(
+                "\t{}",
+                balance.map_or(0, |asset| asset.balance)
+            ))?;
+        }
+        writeln!(f)
     }
 }
 
 impl Client {
     // Purchase Error Checking.
     pub fn check_buy_error(&self, cache_order: &Order, order: &Order) -> Result<(), GeneralErrors> {
-        if self.dollar_balance < cache_order.order_price * order.value {
-            return Err(GeneralErrors::NotEnaughDollars);
+        // An overflowing cost can never be covered either.
+        match cache_order.order_price.checked_mul(order.value) {
+            Some(cost) if cost <= self.dollar_balance => Ok(()),
+            _ => Err(GeneralErrors::NotEnaughDollars),
         }
-        Ok(())
     }
     // Sales Error Checking.
     pub fn check_sell_error(&self, order: &Order) -> Result<(), GeneralErrors> {
         let asset_balance = self
             .asset_balances
-            .get(&order.asset)
+            .by_id(order.asset_id)
             .ok_or(GeneralErrors::GetClientError)?;
         if asset_balance.balance < order.value {
             return Err(GeneralErrors::NotEnaughAsset);
@@ -102,45 +136,117 @@ impl Client {
         Ok(())
     }
 
-    // There are no balance checks in the two functions 
+    // Whether the balances can take the dollars and units of a fill without
+    // overflowing.
+    pub fn check_credit(
+        &self,
+        asset: Symbol,
+        dollars: u32,
+        units: u32,
+    ) -> Result<(), GeneralErrors> {
+        let balance = self
+            .asset_balances
+            .by_id(asset)
+            .map_or(0, |asset| asset.balance);
+        match (
+            self.dollar_balance.checked_add(dollars),
+            balance.checked_add(units),
+        ) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(GeneralErrors::BalanceOverflowError),
+        }
+    }
+
+    // Adds dollars, or units of an asset named as in the ledger, to the
+    // balances, or takes them away when the amount is negative.
+    pub fn adjust(&mut self, asset: &str, amount: i64) -> Result<(), GeneralErrors> {
+        let (balance, short) = if asset == DOLLARS {
+            (&mut self.dollar_balance, GeneralErrors::NotEnaughDollars)
+        } else {
+            let asset = self
+                .asset_balances
+                .get_mut(asset)
+                .ok_or(GeneralErrors::GetAssetError)?;
+            (&mut asset.balance, GeneralErrors::NotEnaughAsset)
+        };
+        let adjusted = i64::from(*balance) + amount;
+        if adjusted < 0 {
+            return Err(short);
+        }
+        *balance = u32::try_from(adjusted).map_err(|_| GeneralErrors::BalanceOverflowError)?;
+        // Units moved in or out change the position at cost.
+        let volume = amount.unsigned_abs() as u32;
+        match (asset, amount > 0) {
+            (DOLLARS, _) => {}
+            (asset, true) => self.pnl.deposit(asset, volume),
+            (asset, false) => self.pnl.withdraw(asset, volume),
+        }
+        Ok(())
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.pnl.realized()
+    }
+
+    // Unrealized P&L of all holdings marked to each asset's last trade price.
+    pub fn unrealized_pnl(&self, quotes: &Quotes) -> f64 {
+        self.pnl.unrealized_at(quotes)
+    }
+
+    // There are no balance checks in the two functions
     // described below, because the checks are performed in the functions above.
     // These functions are used to reduce repetitive code in main.rs.
     pub fn buy(&mut self, cache_order: &Order, order_value: u32) {
-        let mut asset_balance = self.asset_balances.get_mut(&cache_order.asset).unwrap();
+        let asset_balance = self.asset_balances.by_id_mut(cache_order.asset_id).unwrap();
         self.dollar_balance -= cache_order.order_price * order_value;
         asset_balance.balance += order_value;
     }
 
     pub fn sell(&mut self, cache_order: &Order, order_value: u32) {
-        let mut asset_balance = self.asset_balances.get_mut(&cache_order.asset).unwrap();
+        let asset_balance = self.asset_balances.by_id_mut(cache_order.asset_id).unwrap();
         asset_balance.balance -= order_value;
         self.dollar_balance += cache_order.order_price * order_value;
     }
 }
 
-
 impl DataParser for Clients {
     type Item = Client;
     type Err = ClientErrors;
 
     fn new() -> Clients {
         Clients {
+            registry: Registry::new(),
             client: BTreeMap::new(),
         }
     }
 
     fn insert(&mut self, index: usize, mut data: Self::Item) {
         data.index = index;
-        self.client.insert(data.name.clone(), data);
+        data.id = self.registry.clients.intern(&data.name);
+        data.asset_balances.register(&mut self.registry.assets);
+        self.client.insert(data.id, data);
     }
 
     fn parse(line: &str) -> Result<Self::Item, Self::Err> {
         Self::Item::from_str(line)
     }
 
+    fn parse_record(record: &Record) -> Result<Self::Item, Self::Err> {
+        Self::Item::from_record(record)
+    }
+
+    fn parse_json(line: &str) -> Result<Self::Item, Self::Err> {
+        let mut client: Client = serde_json::from_str(line)
+            .map_err(|error| ClientErrors::ParseJsonError(error.to_string()))?;
+        client.pnl = Pnl::open(&client.asset_balances, CostMethod::default());
+        Ok(client)
+    }
+
     fn remove(&mut self, clients: Vec<Self::Item>) {
         for client in clients {
-            self.client.remove(&client.name);
+            if let Some(id) = self.id(&client.name) {
+                self.client.remove(&id);
+            }
         }
     }
 }
@@ -188,28 +294,58 @@ impl FromStr for Client {
                 .map_err(|_| ParseAssetBalancesError)?,
         };
 
-        let mut asset_balances: Assets = Assets {
-            asset: BTreeMap::new(),
-        };
+        let asset_balances = Assets::from(vec![a_balance, b_balance, c_balance, d_balance]);
 
-        asset_balances
-            .asset
-            .insert(a_balance.symbol.clone(), a_balance);
-        asset_balances
-            .asset
-            .insert(b_balance.symbol.clone(), b_balance);
-        asset_balances
-            .asset
-            .insert(c_balance.symbol.clone(), c_balance);
-        asset_balances
-            .asset
-            .insert(d_balance.symbol.clone(), d_balance);
+        let pnl = Pnl::open(&asset_balances, CostMethod::default());
 
         Ok(Client {
             index,
+            id: Symbol::default(),
             name,
             dollar_balance,
             asset_balances,
+            pnl,
+        })
+    }
+}
+
+impl Client {
+    // Converting a CSV row with client and dollar_balance columns. Every
+    // other column is the balance of the asset it is named after.
+    pub fn from_record(record: &Record) -> Result<Self, ClientErrors> {
+        use ClientErrors::*;
+        let name = record.get("client").ok_or(ParseInsufficentInput)?;
+        if name.is_empty() {
+            return Err(ParseClientIdError);
+        }
+        let dollar_balance = record
+            .get("dollar_balance")
+            .ok_or(ParseInsufficentInput)?
+            .parse::<u32>()
+            .map_err(|_| ParseDollarBalanceError)?;
+
+        let mut asset_balances = Assets::new();
+        for (column, value) in record.columns() {
+            if column.eq_ignore_ascii_case("client")
+                || column.eq_ignore_ascii_case("dollar_balance")
+            {
+                continue;
+            }
+            let balance = value.parse::<u32>().map_err(|_| ParseAssetBalancesError)?;
+            asset_balances.insert(Asset {
+                symbol: column.to_string(),
+                balance,
+            });
+        }
+        let pnl = Pnl::open(&asset_balances, CostMethod::default());
+
+        Ok(Client {
+            index: 0,
+            id: Symbol::default(),
+            name: name.to_string(),
+            dollar_balance,
+            asset_balances,
+            pnl,
         })
     }
 }
@@ -221,20 +357,84 @@ impl Assets {
         }
     }
 
-    pub fn get_mut<T>(&mut self, asset_id: T) -> Option<&mut Asset>
-    where
-        T: Into<String>,
-    {
-        let asset_id = &asset_id.into();
-        self.asset.get_mut(asset_id)
+    // Adds a balance under the next id, or replaces the one of the same
+    // asset. Ids given here are only the registry's once the client has been
+    // inserted into a Clients.
+    pub fn insert(&mut self, asset: Asset) {
+        let id = match self
+            .asset
+            .iter()
+            .find(|(_, held)| held.symbol == asset.symbol)
+        {
+            Some((id, _)) => *id,
+            None => self
+                .asset
+                .keys()
+                .next_back()
+                .map_or(Symbol(0), |id| Symbol(id.0 + 1)),
+        };
+        self.asset.insert(id, asset);
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Asset> {
+        self.asset.values().find(|asset| asset.symbol == symbol)
+    }
+
+    pub fn get_mut(&mut self, symbol: &str) -> Option<&mut Asset> {
+        self.asset.values_mut().find(|asset| asset.symbol == symbol)
+    }
+
+    pub fn remove(&mut self, symbol: &str) -> Option<Asset> {
+        let id = self.id(symbol)?;
+        self.asset.remove(&id)
+    }
+
+    pub fn id(&self, symbol: &str) -> Option<Symbol> {
+        self.asset
+            .iter()
+            .find(|(_, asset)| asset.symbol == symbol)
+            .map(|(id, _)| *id)
     }
 
-    pub fn get<T>(&self, asset_id: T) -> Option<Asset>
-    where
-        T: Into<String>,
-    {
-        let asset_id = &asset_id.into();
-        Some(self.asset.get(asset_id)?.clone())
+    pub fn by_id(&self, id: Symbol) -> Option<&Asset> {
+        self.asset.get(&id)
+    }
+
+    pub fn by_id_mut(&mut self, id: Symbol) -> Option<&mut Asset> {
+        self.asset.get_mut(&id)
+    }
+
+    // Balances in name order, the order every output lists them in.
+    pub fn iter(&self) -> impl Iterator<Item = &Asset> {
+        let mut assets: Vec<&Asset> = self.asset.values().collect();
+        assets.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        assets.into_iter()
+    }
+
+    // Keys the balances by the ids the registry has for their names.
+    pub fn register(&mut self, symbols: &mut Symbols) {
+        self.asset = std::mem::take(&mut self.asset)
+            .into_values()
+            .map(|asset| (symbols.intern(&asset.symbol), asset))
+            .collect();
+    }
+}
+
+impl From<Vec<Asset>> for Assets {
+    fn from(assets: Vec<Asset>) -> Assets {
+        let mut balances = Assets::new();
+        for asset in assets {
+            balances.insert(asset);
+        }
+        balances
+    }
+}
+
+impl From<Assets> for Vec<Asset> {
+    fn from(assets: Assets) -> Vec<Asset> {
+        let mut assets: Vec<Asset> = assets.asset.into_values().collect();
+        assets.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        assets
     }
 }
 
@@ -287,4 +487,54 @@ mod tests {
         let expected_error = ClientErrors::ParseAssetBalancesError;
         assert_eq!(actual_error, expected_error)
     }
+
+    #[test]
+    fn test_display_missing_assets() {
+        let mut client = Client::from_str("C1\t10\t1\t2\t3\t4").unwrap();
+        assert_eq!(client.to_string(), "C1\t10\t1\t2\t3\t4\n");
+        client.asset_balances.remove("B");
+        assert_eq!(client.to_string(), "C1\t10\t1\t0\t3\t4\n");
+    }
+
+    #[test]
+    fn test_check_credit() {
+        let mut clients = Clients::new();
+        clients.insert(1, Client::from_str("C1\t10\t1\t2\t3\t4").unwrap());
+        let client = clients.get("C1").unwrap();
+        let a = clients.registry.assets.get("A").unwrap();
+        assert!(client.check_credit(a, u32::MAX - 10, 0).is_ok());
+        assert!(client.check_credit(a, u32::MAX - 9, 0).is_err());
+        assert!(client.check_credit(a, 0, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_insert_registers_ids() {
+        let mut clients = Clients::new();
+        clients.insert(1, Client::from_str("C2\t10\t1\t2\t3\t4").unwrap());
+        let record = "client,dollar_balance,D,E\nC1,5,7,8\n";
+        let mut reader = crate::formats::csv_reader(record.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let row = reader.records().next().unwrap().unwrap();
+        clients.insert(
+            2,
+            Client::from_record(&Record::new(&headers, &row)).unwrap(),
+        );
+
+        let d = clients.registry.assets.get("D").unwrap();
+        let c1 = clients.by_id(clients.id("C1").unwrap()).unwrap();
+        assert_eq!(c1.asset_balances.by_id(d).unwrap().balance, 7);
+        assert_eq!(
+            clients
+                .get("C2")
+                .unwrap()
+                .asset_balances
+                .by_id(d)
+                .unwrap()
+                .balance,
+            4
+        );
+        assert_eq!(clients.registry.assets.len(), 5);
+        let names: Vec<&str> = clients.iter().map(|client| client.name.as_str()).collect();
+        assert_eq!(names, ["C1", "C2"]);
+    }
 }
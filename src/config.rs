@@ -1,17 +1,77 @@
-use config::ConfigError;
+use crate::{
+    candles::Bucket, errors::TradeMatchErrors, fix::Accounts, formats::Format,
+    margin::MarginConfig, pnl::CostMethod, risk::RiskConfig, OnError,
+};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct FilePath {
     pub orders: String,
     pub clients: String,
+    #[serde(default = "default_result")]
+    pub result: String,
+    #[serde(default)]
+    pub on_error: OnError,
+    #[serde(default)]
+    pub formats: Formats,
+    pub trades: Option<String>,
+    pub book: Option<String>,
+    pub candles: Option<CandleConfig>,
+    pub report: Option<String>,
+    pub pnl: Option<PnlConfig>,
+    pub fix: Option<FixConfig>,
+    pub risk: Option<RiskConfig>,
+    pub margin: Option<MarginConfig>,
+}
+
+// Format of each file when its extension does not tell.
+#[derive(Debug, Default, Deserialize)]
+pub struct Formats {
+    pub orders: Option<Format>,
+    pub clients: Option<Format>,
+    pub result: Option<Format>,
+    pub trades: Option<Format>,
+}
+
+// Where OHLCV candles are written and how they are bucketed.
+#[derive(Debug, Deserialize)]
+pub struct CandleConfig {
+    pub path: String,
+    pub bucket: Bucket,
+}
+
+// Where the P&L report is written and how cost basis is tracked.
+#[derive(Debug, Deserialize)]
+pub struct PnlConfig {
+    pub path: String,
+    #[serde(default)]
+    pub method: CostMethod,
+}
+
+// Address and CompID of the FIX acceptor, set to run in server mode, and
+// the accounts each initiator's CompID may trade.
+#[derive(Debug, Deserialize)]
+pub struct FixConfig {
+    pub address: String,
+    pub comp_id: String,
+    #[serde(default)]
+    pub accounts: Accounts,
+}
+
+fn default_result() -> String {
+    "./Result.txt".to_string()
 }
 
 // Reading paths to Clients.txt and Orders.txt file
-pub fn get_config() -> Result<FilePath, ConfigError> {
+pub fn get_config() -> Result<FilePath, TradeMatchErrors> {
+    get_config_from("config")
+}
+
+// Same as get_config, for a config file at another location.
+pub fn get_config_from(file: &str) -> Result<FilePath, TradeMatchErrors> {
     let mut path = config::Config::default();
-    path.merge(config::File::with_name("config"))?;
-    path.try_into()
+    path.merge(config::File::with_name(file))?;
+    Ok(path.try_into()?)
 }
 
 #[cfg(test)]
@@ -24,5 +84,63 @@ mod tests {
 
         assert_eq!(config.orders, "./Orders.txt");
         assert_eq!(config.clients, "./Clients.txt");
+        assert_eq!(config.result, "./Result.txt");
+        assert_eq!(config.on_error, OnError::Stop);
+        assert_eq!(config.formats.orders, None);
+        assert_eq!(config.trades, None);
+        assert_eq!(config.book.as_deref(), Some("./Book.txt"));
+        let candles = config.candles.unwrap();
+        assert_eq!(candles.path, "./Candles.csv");
+        assert_eq!(candles.bucket, Bucket::Trades(10));
+        assert_eq!(config.report.as_deref(), Some("./Report.txt"));
+        let pnl = config.pnl.unwrap();
+        assert_eq!(pnl.path, "./PnL.txt");
+        assert_eq!(pnl.method, CostMethod::Fifo);
+        assert!(config.fix.is_none());
+        assert!(config.risk.is_none());
+        assert!(config.margin.is_none());
+    }
+
+    #[test]
+    fn test_get_risk_config() {
+        let file = std::env::temp_dir().join("trade_match_risk_config.yaml");
+        std::fs::write(
+            &file,
+            "orders: ./Orders.txt\nclients: ./Clients.txt\nrisk:\n  default:\n    max_order_volume: 100\n  clients:\n    C1:\n      max_open_orders: 3\n",
+        )
+        .unwrap();
+        let risk = get_config_from(file.to_str().unwrap())
+            .unwrap()
+            .risk
+            .unwrap();
+        assert_eq!(risk.default.max_order_volume, Some(100));
+        assert_eq!(risk.clients["C1"].max_open_orders, Some(3));
+        assert_eq!(risk.clients["C1"].max_order_volume, None);
+    }
+
+    #[test]
+    fn test_get_margin_config() {
+        let file = std::env::temp_dir().join("trade_match_margin_config.yaml");
+        std::fs::write(
+            &file,
+            "orders: ./Orders.txt\nclients: ./Clients.txt\nmargin:\n  clients: [C1, C2]\n  leverage: 3\n",
+        )
+        .unwrap();
+        let margin = get_config_from(file.to_str().unwrap())
+            .unwrap()
+            .margin
+            .unwrap();
+        assert_eq!(margin.clients, ["C1", "C2"]);
+        assert_eq!(margin.leverage, 3.0);
+        assert_eq!(margin.maintenance, 0.25);
+        assert_eq!(margin.interest, 0.0);
+    }
+
+    #[test]
+    fn test_get_config_from_missing_file() {
+        assert!(matches!(
+            get_config_from("./no_such_config.yaml"),
+            Err(TradeMatchErrors::ConfigError(_))
+        ));
     }
 }
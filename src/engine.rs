@@ -0,0 +1,1118 @@
+use crate::{
+    binary::{Kind, MessageView},
+    book::{self, Depth, OrderDepth},
+    clients::{Client, Clients},
+    errors::{GeneralErrors, TradeMatchErrors},
+    ledger::{EntryKind, Holder, Ledger, Line, Unit, DOLLARS},
+    margin::Margin,
+    orders::{NewOrder, NewOrders, Order, OrderType, Orders},
+    quotes::{Quote, Quotes},
+    risk::{Exposure, Risk},
+    symbols::{Symbol, Symbols},
+    Price, Volume,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc};
+
+// A single fill between an incoming order and a resting one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub buy_index: usize,
+    pub sell_index: usize,
+    pub buyer: Arc<str>,
+    pub seller: Arc<str>,
+    pub asset: Arc<str>,
+    pub price: Price,
+    pub volume: Volume,
+    // Limit prices of the two orders, kept for execution quality reports.
+    pub buy_limit: Price,
+    pub sell_limit: Price,
+}
+
+// Matching engine: client balances, the resting buy and sell books and
+// the Level 1 quotes derived from them. Orders in the books carry the ids
+// the clients' registry gave their client and asset names. Every order passes the
+// risk limits before it is matched, and margin accounts borrow what their
+// orders need. Every balance movement is booked in the ledger.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    pub clients: Clients,
+    pub buy_orders: Orders,
+    pub sell_orders: Orders,
+    pub quotes: Quotes,
+    pub risk: Risk,
+    pub margin: Margin,
+    pub ledger: Ledger,
+}
+
+// What matching one order did to the opposite book: the indexes of the
+// resting orders it filled completely, the remaining volume of those it
+// filled in part, and the fills themselves.
+#[derive(Debug, Default)]
+pub struct Matched {
+    pub completed: Vec<usize>,
+    pub updated: Vec<(usize, Volume)>,
+    pub trades: Vec<Trade>,
+}
+
+impl Matched {
+    fn fill(&mut self, resting: &Order, volume: Volume) {
+        match resting.value - volume {
+            0 => self.completed.push(resting.index),
+            remaining => self.updated.push((resting.index, remaining)),
+        }
+    }
+}
+
+impl Engine {
+    pub fn new(clients: Clients) -> Engine {
+        Engine {
+            ledger: Ledger::open(&clients, false),
+            clients,
+            buy_orders: Orders::new(),
+            sell_orders: Orders::new(),
+            quotes: Quotes::new(),
+            risk: Risk::default(),
+            margin: Margin::default(),
+        }
+    }
+
+    // Keeps every ledger entry for the journal, not only the balances. The
+    // ledger is opened again, so this comes before anything is matched.
+    pub fn keep_journal(&mut self) {
+        self.ledger = Ledger::open(&self.clients, true);
+    }
+
+    // Runs every order through the books in index order, stopping at the
+    // first one that is rejected.
+    pub fn match_orders(&mut self, orders: NewOrders) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let mut trades = Vec::new();
+        for fills in self.stream(orders.order.into_values()) {
+            trades.append(&mut fills?);
+        }
+        Ok(trades)
+    }
+
+    // Matches orders as the iterator yields them, for instance from a file
+    // stream or a channel receiver, giving the fills of each order in turn.
+    pub fn stream<'a, I>(
+        &'a mut self,
+        orders: I,
+    ) -> impl Iterator<Item = Result<Vec<Trade>, TradeMatchErrors>> + 'a
+    where
+        I: IntoIterator<Item = NewOrder>,
+        I::IntoIter: 'a,
+    {
+        orders.into_iter().map(move |order| self.enter(&order))
+    }
+
+    // Matches an order as it was entered, once its names are resolved.
+    pub fn enter(&mut self, order: &NewOrder) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let order = self.resolve(order)?;
+        self.process(order)
+    }
+
+    // The engine's order for one as it was entered, with the ids the
+    // clients' registry gave its client and asset names.
+    pub fn resolve(&self, order: &NewOrder) -> Result<Order, TradeMatchErrors> {
+        let registry = &self.clients.registry;
+        Ok(Order {
+            index: order.index,
+            client_id: registry
+                .clients
+                .get(&order.client_name)
+                .ok_or(GeneralErrors::GetClientError)?,
+            operation: order.operation,
+            asset_id: registry
+                .assets
+                .get(&order.asset)
+                .ok_or(GeneralErrors::GetAssetError)?,
+            order_price: order.order_price,
+            value: order.value,
+        })
+    }
+
+    // Matches a single order against the opposite book and rests the remainder.
+    // Orders from unknown clients, for unknown assets, without a side or
+    // over their client's risk limits are rejected before they reach the books,
+    // as are orders of margin accounts that cannot borrow what they need.
+    // Accounts the fills leave below maintenance are liquidated right after,
+    // and those fills come last.
+    pub fn process(&mut self, order: Order) -> Result<Vec<Trade>, TradeMatchErrors> {
+        self.admit(&order)?;
+        if !self.margin.is_active() {
+            return Ok(self.execute(order));
+        }
+        let mut borrowers = Vec::new();
+        if self.lend(&order, false)? {
+            borrowers.push(order.client_id);
+        }
+        borrowers.append(&mut self.lend_to_resting(&order));
+        let mut trades = self.execute(order);
+        self.release(borrowers);
+        trades.append(&mut self.liquidate()?);
+        Ok(trades)
+    }
+
+    // Lends a margin account what an order of its needs to fill and books
+    // it. A liquidation order is covered whatever the leverage. Whether the
+    // account is on margin.
+    fn lend(&mut self, order: &Order, liquidation: bool) -> Result<bool, TradeMatchErrors> {
+        let Some(client) = self.clients.by_id_mut(order.client_id) else {
+            return Ok(false);
+        };
+        if !self.margin.loans.contains_key(&client.name) {
+            return Ok(false);
+        }
+        let before = client.clone();
+        match liquidation {
+            true => self.margin.cover(client, order)?,
+            false => self.margin.borrow(client, order, &self.quotes)?,
+        }
+        self.ledger
+            .record_change(EntryKind::Loan, Holder::Lender, &before, client);
+        Ok(true)
+    }
+
+    // Lends to the margin accounts whose resting orders an incoming order
+    // may fill, as far as their leverage allows. A resting order that cannot
+    // borrow is skipped like any other that its balances do not cover.
+    fn lend_to_resting(&mut self, order: &Order) -> Vec<Symbol> {
+        let candidates = match order.operation {
+            OrderType::Buy => buy_candidates(&self.sell_orders, order),
+            _ => sell_candidates(&self.buy_orders, order),
+        };
+        let resting: Vec<Order> = candidates
+            .into_iter()
+            .filter(|resting| {
+                self.clients
+                    .by_id(resting.client_id)
+                    .is_some_and(|client| self.margin.loans.contains_key(&client.name))
+            })
+            .map(|resting| Order {
+                value: resting.value.min(order.value),
+                ..*resting
+            })
+            .collect();
+        let mut borrowers = Vec::new();
+        for resting in resting {
+            if matches!(self.lend(&resting, false), Ok(true))
+                && !borrowers.contains(&resting.client_id)
+            {
+                borrowers.push(resting.client_id);
+            }
+        }
+        borrowers
+    }
+
+    // Pays back what the fills left of the borrowers' loans, so nothing is
+    // owed for orders that only rest.
+    fn release(&mut self, borrowers: Vec<Symbol>) {
+        for id in borrowers {
+            if let Some(client) = self.clients.by_id_mut(id) {
+                let before = client.clone();
+                self.margin.repay(client);
+                self.ledger
+                    .record_change(EntryKind::Repayment, Holder::Lender, &before, client);
+            }
+        }
+    }
+
+    fn execute(&mut self, mut order: Order) -> Vec<Trade> {
+        let Matched {
+            completed,
+            updated,
+            trades,
+        } = match order.operation {
+            OrderType::Buy => buy_assets(&self.sell_orders, &mut self.clients, &mut order),
+            _ => sell_assets(&self.buy_orders, &mut self.clients, &mut order),
+        };
+        match order.operation {
+            OrderType::Buy => rest(
+                &mut self.buy_orders,
+                &mut self.sell_orders,
+                order,
+                completed,
+                updated,
+            ),
+            _ => rest(
+                &mut self.sell_orders,
+                &mut self.buy_orders,
+                order,
+                completed,
+                updated,
+            ),
+        }
+        self.record(&trades);
+        self.update_quote(order.asset_id);
+        trades
+    }
+
+    // Sends the liquidation orders of every account below maintenance.
+    // They are numbered down from usize::MAX, apart from the orders callers
+    // number, and never rest themselves. Whatever they bring in pays back the account's loans.
+    pub fn liquidate(&mut self) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let mut trades = Vec::new();
+        for name in self.margin.check_calls(&self.clients, &self.quotes) {
+            let client = self
+                .clients
+                .get(&name)
+                .cloned()
+                .ok_or(GeneralErrors::GetClientError)?;
+            for mut order in self.margin.liquidation_orders(&client, &self.quotes) {
+                order.index = usize::MAX - self.margin.liquidations;
+                self.margin.liquidations += 1;
+                self.lend(&order, true)?;
+                let borrowers = self.lend_to_resting(&order);
+                let index = order.index;
+                trades.append(&mut self.execute(order));
+                if self.get_order(index).is_some() {
+                    self.cancel(index)?;
+                }
+                self.release(borrowers);
+            }
+            self.release(vec![client.id]);
+        }
+        Ok(trades)
+    }
+
+    // Ends a trading session: margin accounts are charged interest and pay
+    // back what they can, and any that falls below maintenance is liquidated.
+    pub fn end_session(&mut self) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let before: Vec<(Client, u64)> = self
+            .margin
+            .loans
+            .iter()
+            .filter_map(|(name, loan)| Some((self.clients.get(name).cloned()?, loan.interest)))
+            .collect();
+        self.margin.end_session(&mut self.clients, &self.quotes);
+        for (before, interest) in &before {
+            // Interest adds to what the lender has out and is earned apart
+            // from the loans themselves.
+            let charged = self.margin.loans[&before.name].interest - interest;
+            self.ledger.post(
+                EntryKind::Interest,
+                vec![
+                    Line::new(Holder::Interest, Unit::Dollars, charged as i64),
+                    Line::new(Holder::Lender, Unit::Dollars, -(charged as i64)),
+                ],
+                &self.clients.registry,
+            )?;
+            if let Some(after) = self.clients.get(&before.name) {
+                self.ledger
+                    .record_change(EntryKind::Repayment, Holder::Lender, before, after);
+            }
+        }
+        self.liquidate()
+    }
+
+    // Pays dollars, or units of an asset, into a client's balances.
+    pub fn deposit(
+        &mut self,
+        client: &str,
+        asset: &str,
+        amount: u32,
+    ) -> Result<(), TradeMatchErrors> {
+        self.move_balance(
+            EntryKind::Deposit,
+            Holder::External,
+            client,
+            asset,
+            i64::from(amount),
+        )
+    }
+
+    pub fn withdraw(
+        &mut self,
+        client: &str,
+        asset: &str,
+        amount: u32,
+    ) -> Result<(), TradeMatchErrors> {
+        self.move_balance(
+            EntryKind::Withdrawal,
+            Holder::External,
+            client,
+            asset,
+            -i64::from(amount),
+        )
+    }
+
+    pub fn charge_fee(&mut self, client: &str, dollars: u32) -> Result<(), TradeMatchErrors> {
+        self.move_balance(
+            EntryKind::Fee,
+            Holder::Fees,
+            client,
+            DOLLARS,
+            -i64::from(dollars),
+        )
+    }
+
+    fn move_balance(
+        &mut self,
+        kind: EntryKind,
+        other: Holder,
+        client: &str,
+        asset: &str,
+        amount: i64,
+    ) -> Result<(), TradeMatchErrors> {
+        let id = self
+            .clients
+            .id(client)
+            .ok_or(GeneralErrors::GetClientError)?;
+        let unit = Unit::of(&self.clients.registry, asset).ok_or(GeneralErrors::GetAssetError)?;
+        self.clients
+            .by_id_mut(id)
+            .ok_or(GeneralErrors::GetClientError)?
+            .adjust(asset, amount)?;
+        self.ledger.transfer(kind, id, other, unit, amount);
+        Ok(())
+    }
+
+    // Checks that an order can enter the books: its client has to hold its
+    // asset. Unless the risk limits depend on fills, whether it is admitted
+    // depends only on the clients, never on what was matched before.
+    pub fn admit(&self, order: &Order) -> Result<(), TradeMatchErrors> {
+        let client = self
+            .clients
+            .by_id(order.client_id)
+            .ok_or(GeneralErrors::GetClientError)?;
+        if client.asset_balances.by_id(order.asset_id).is_none() {
+            return Err(GeneralErrors::GetAssetError.into());
+        }
+        if order.operation == OrderType::IsNotOrderType {
+            return Err(GeneralErrors::NoSuchOperationError.into());
+        }
+        self.risk
+            .check(order, client, || self.exposure(client, order.asset_id))?;
+        Ok(())
+    }
+
+    // Updates quotes, cost basis and realized P&L with the fills of one order.
+    pub fn record(&mut self, trades: &[Trade]) {
+        self.quotes.record_trades(trades);
+        self.record_pnl(trades);
+        self.risk.record(trades);
+        for trade in trades {
+            self.ledger.settle(trade, &self.clients.registry);
+        }
+    }
+
+    // Starts a new trading day: traded volume and the daily risk totals go
+    // back to zero.
+    pub fn start_day(&mut self) {
+        self.quotes.reset_volume();
+        self.risk.start_day();
+    }
+
+    // Number of the client's orders resting in either book.
+    pub fn open_orders(&self, client: &str) -> usize {
+        let id = self.clients.id(client);
+        self.buy_orders
+            .order
+            .values()
+            .chain(self.sell_orders.order.values())
+            .filter(|order| Some(order.client_id) == id)
+            .count()
+    }
+
+    // What the client has at stake in one asset: its resting orders, and
+    // the units it holds less what a margin account owes of them.
+    pub fn exposure(&self, client: &Client, asset: Symbol) -> Exposure {
+        let mut exposure = Exposure {
+            may_short: self.margin.loans.contains_key(&client.name),
+            ..Exposure::default()
+        };
+        for (order, buying) in self
+            .buy_orders
+            .order
+            .values()
+            .map(|order| (order, true))
+            .chain(self.sell_orders.order.values().map(|order| (order, false)))
+            .filter(|(order, _)| order.client_id == client.id)
+        {
+            exposure.open_orders += 1;
+            match (order.asset_id == asset, buying) {
+                (false, _) => {}
+                (true, true) => exposure.buying += u64::from(order.value),
+                (true, false) => exposure.selling += u64::from(order.value),
+            }
+        }
+        let held = client
+            .asset_balances
+            .by_id(asset)
+            .map_or(0, |asset| asset.balance);
+        let owed = self
+            .clients
+            .registry
+            .assets
+            .name(asset)
+            .and_then(|name| self.margin.loans.get(&client.name)?.units.get(&**name))
+            .copied()
+            .unwrap_or_default();
+        exposure.position = i64::from(held) - owed as i64;
+        exposure
+    }
+
+    // Applies one binary message: orders are matched, cancels take the order
+    // off the book and trades, being engine output, are ignored.
+    pub fn apply(&mut self, message: &MessageView) -> Result<Vec<Trade>, TradeMatchErrors> {
+        match message.kind()? {
+            Kind::Order => self.process(message.to_order(&self.clients.registry)?),
+            Kind::Cancel => self.cancel(message.index()).map(|_| Vec::new()),
+            Kind::Trade => Ok(Vec::new()),
+        }
+    }
+
+    // Updates cost basis and realized P&L of both sides of every fill.
+    fn record_pnl(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            let opening = self
+                .quotes
+                .get(&*trade.asset)
+                .and_then(|quote| quote.open_price)
+                .unwrap_or(trade.price);
+            if let Some(buyer) = self.clients.get_mut(&trade.buyer) {
+                buyer
+                    .pnl
+                    .buy(&trade.asset, trade.price, trade.volume, opening);
+            }
+            if let Some(seller) = self.clients.get_mut(&trade.seller) {
+                seller
+                    .pnl
+                    .sell(&trade.asset, trade.price, trade.volume, opening);
+            }
+        }
+    }
+
+    // Looks up a resting order in either book.
+    pub fn get_order(&self, index: usize) -> Option<Order> {
+        self.buy_orders
+            .get(index)
+            .or_else(|| self.sell_orders.get(index))
+    }
+
+    // Assets that still have resting orders.
+    pub fn assets(&self) -> BTreeSet<String> {
+        book::assets(&self.buy_orders, &self.sell_orders)
+            .into_iter()
+            .map(|asset| name(&self.clients.registry.assets, asset).to_string())
+            .collect()
+    }
+
+    // Level 1 quote of one asset, once it has seen any order.
+    pub fn quote(&self, asset: &str) -> Option<Quote> {
+        self.quotes.get(asset)
+    }
+
+    // Level 2 snapshot of one asset's resting orders.
+    pub fn depth(&self, asset: &str) -> Depth {
+        let id = self.clients.registry.assets.get(asset);
+        Depth::new(asset, id, &self.buy_orders, &self.sell_orders)
+    }
+
+    // Level 3 snapshot of one asset's resting orders.
+    pub fn order_depth(&self, asset: &str) -> OrderDepth {
+        let id = self.clients.registry.assets.get(asset);
+        OrderDepth::new(asset, id, &self.buy_orders, &self.sell_orders)
+    }
+
+    // Removes a resting order from the book it sits in.
+    pub fn cancel(&mut self, index: usize) -> Result<Order, TradeMatchErrors> {
+        let order = self
+            .buy_orders
+            .remove(index)
+            .or_else(|| self.sell_orders.remove(index))
+            .ok_or(GeneralErrors::GetOrderError)?;
+        self.update_quote(order.asset_id);
+        Ok(order)
+    }
+
+    // Brings an asset's best bid and ask up to date with the books.
+    pub fn update_quote(&mut self, asset: Symbol) {
+        let name = name(&self.clients.registry.assets, asset);
+        self.quotes
+            .update_book(&name, asset, &self.buy_orders, &self.sell_orders);
+    }
+
+    // Cancels a resting order and re-enters it under a new index with a new
+    // price and remaining volume, so it loses its time priority. A
+    // replacement that is rejected leaves the original order resting as it was.
+    pub fn replace(
+        &mut self,
+        index: usize,
+        new_index: usize,
+        order_price: Price,
+        value: Volume,
+    ) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let order = self.cancel(index)?;
+        let replacement = Order {
+            index: new_index,
+            order_price,
+            value,
+            ..order
+        };
+        self.process(replacement).inspect_err(|_| {
+            let book = match order.operation {
+                OrderType::Buy => &mut self.buy_orders,
+                _ => &mut self.sell_orders,
+            };
+            book.insert(index, order);
+            self.update_quote(order.asset_id);
+        })
+    }
+}
+
+// Rests what is left of an order in its own book and applies its fills to
+// the opposite one.
+pub fn rest(
+    own: &mut Orders,
+    opposite: &mut Orders,
+    order: Order,
+    completed: Vec<usize>,
+    updated: Vec<(usize, Volume)>,
+) {
+    if order.value > 0 {
+        own.insert(order.index, order);
+    }
+    for index in completed {
+        opposite.remove(index);
+    }
+    for (index, value) in updated {
+        opposite.set_value(index, value);
+    }
+}
+
+// Both matching functions expect orders whose ids were assigned by the
+// clients' registry. Candidates are borrowed from the book, which is only
+// changed once the fills are known.
+pub fn buy_assets(sell_orders: &Orders, clients: &mut Clients, order: &mut Order) -> Matched {
+    fill_buy(buy_candidates(sell_orders, order), clients, order)
+}
+
+// Resting sell orders a buy order may fill against, in the order they are tried.
+pub fn buy_candidates<'a>(sell_orders: &'a Orders, order: &Order) -> Vec<&'a Order> {
+    let mut sell_asset_orders: Vec<&Order> = sell_orders
+        .order
+        .values()
+        .filter(|ord| {
+            ord.client_id != order.client_id
+                && order.asset_id == ord.asset_id
+                && order.order_price >= ord.order_price
+        })
+        .collect();
+
+    sell_asset_orders.sort_by_key(|order| order.order_price);
+    sell_asset_orders
+}
+
+// Fills a buy order against the candidates as far as both sides' balances allow.
+pub fn fill_buy<'a, I>(sell_asset_orders: I, clients: &mut Clients, order: &mut Order) -> Matched
+where
+    I: IntoIterator<Item = &'a Order>,
+{
+    let mut matched = Matched::default();
+    for sell_order in sell_asset_orders {
+        if order.value == 0 {
+            break;
+        }
+
+        // Check for balance errors.
+        let volume = order.value.min(sell_order.value);
+        let proceeds = sell_order.order_price.saturating_mul(volume);
+        let buyer_ok = clients.by_id(order.client_id).map(|c| {
+            c.check_buy_error(sell_order, order).is_ok()
+                && c.check_credit(order.asset_id, 0, volume).is_ok()
+        });
+        let seller_ok = clients.by_id(sell_order.client_id).map(|c| {
+            c.check_sell_error(order).is_ok() && c.check_credit(order.asset_id, proceeds, 0).is_ok()
+        });
+
+        // Go to the next iteration in case of an error.
+        if buyer_ok != Some(true) || seller_ok != Some(true) {
+            continue;
+        }
+
+        if let Some(c) = clients.by_id_mut(order.client_id) {
+            c.buy(sell_order, volume);
+        }
+        if let Some(c) = clients.by_id_mut(sell_order.client_id) {
+            c.sell(sell_order, volume);
+        }
+
+        matched.trades.push(Trade {
+            buy_index: order.index,
+            sell_index: sell_order.index,
+            buyer: name(&clients.registry.clients, order.client_id),
+            seller: name(&clients.registry.clients, sell_order.client_id),
+            asset: name(&clients.registry.assets, order.asset_id),
+            price: sell_order.order_price,
+            volume,
+            buy_limit: order.order_price,
+            sell_limit: sell_order.order_price,
+        });
+
+        order.value -= volume;
+        matched.fill(sell_order, volume);
+    }
+    matched
+}
+
+pub fn sell_assets(buy_orders: &Orders, clients: &mut Clients, order: &mut Order) -> Matched {
+    fill_sell(sell_candidates(buy_orders, order), clients, order)
+}
+
+// Resting buy orders a sell order may fill against, in the order they are tried.
+pub fn sell_candidates<'a>(buy_orders: &'a Orders, order: &Order) -> Vec<&'a Order> {
+    let mut buy_asset_orders: Vec<&Order> = buy_orders
+        .order
+        .values()
+        .filter(|ord| {
+            ord.client_id != order.client_id
+                && order.asset_id == ord.asset_id
+                && order.order_price < ord.order_price
+        })
+        .collect();
+
+    buy_asset_orders.sort_by_key(|order| order.order_price);
+    buy_asset_orders
+}
+
+// Fills a sell order against the candidates as far as both sides' balances allow.
+pub fn fill_sell<'a, I>(buy_asset_orders: I, clients: &mut Clients, order: &mut Order) -> Matched
+where
+    I: IntoIterator<Item = &'a Order>,
+{
+    let mut matched = Matched::default();
+    for buy_order in buy_asset_orders {
+        if order.value == 0 {
+            break;
+        }
+
+        // Check for balance errors.
+        let volume = order.value.min(buy_order.value);
+        let proceeds = buy_order.order_price.saturating_mul(volume);
+        let buyer_ok = clients.by_id(buy_order.client_id).map(|c| {
+            c.check_buy_error(buy_order, order).is_ok()
+                && c.check_credit(order.asset_id, 0, volume).is_ok()
+        });
+        let seller_ok = clients.by_id(order.client_id).map(|c| {
+            c.check_sell_error(order).is_ok() && c.check_credit(order.asset_id, proceeds, 0).is_ok()
+        });
+
+        // Go to the next iteration in case of an error.
+        if buyer_ok != Some(true) || seller_ok != Some(true) {
+            continue;
+        }
+
+        if let Some(c) = clients.by_id_mut(order.client_id) {
+            c.sell(buy_order, volume);
+        }
+        if let Some(c) = clients.by_id_mut(buy_order.client_id) {
+            c.buy(buy_order, volume);
+        }
+
+        matched.trades.push(Trade {
+            buy_index: buy_order.index,
+            sell_index: order.index,
+            buyer: name(&clients.registry.clients, buy_order.client_id),
+            seller: name(&clients.registry.clients, order.client_id),
+            asset: name(&clients.registry.assets, order.asset_id),
+            price: buy_order.order_price,
+            volume,
+            buy_limit: buy_order.order_price,
+            sell_limit: order.order_price,
+        });
+
+        order.value -= volume;
+        matched.fill(buy_order, volume);
+    }
+    matched
+}
+
+// Name behind an id the engine gave out.
+fn name(symbols: &Symbols, id: Symbol) -> Arc<str> {
+    symbols.name(id).cloned().unwrap_or_default()
+}
+
+// Preparing a trade for printing.
+impl std::fmt::Display for Trade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.buy_index,
+            self.sell_index,
+            self.buyer,
+            self.seller,
+            self.asset,
+            self.price,
+            self.volume
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binary,
+        clients::{Asset, Assets, Client},
+        errors::RiskErrors,
+        pnl::{CostMethod, Pnl},
+        risk::{Limits, RiskConfig},
+        symbols::Symbol,
+        DataParser,
+    };
+
+    fn test_clients() -> Clients {
+        let mut clients = Clients::new();
+        for (index, name) in ["C2", "C3"].iter().enumerate() {
+            let mut assets = Assets::new();
+            assets.insert(Asset {
+                symbol: "A".to_string(),
+                balance: 25,
+            });
+            let pnl = Pnl::open(&assets, CostMethod::Fifo);
+            clients.insert(
+                index + 1,
+                Client {
+                    index: 0,
+                    id: Symbol::default(),
+                    name: name.to_string(),
+                    dollar_balance: 1000,
+                    asset_balances: assets,
+                    pnl,
+                },
+            );
+        }
+        clients
+    }
+
+    // test_clients registers C2 and C3 in turn, so their ids are 0 and 1.
+    fn order(index: usize, client: &str, operation: OrderType, price: u32, value: u32) -> Order {
+        Order {
+            index,
+            client_id: Symbol(client[1..].parse::<u32>().unwrap().saturating_sub(2)),
+            operation,
+            asset_id: Symbol::default(),
+            order_price: price,
+            value,
+        }
+    }
+
+    fn new_order(
+        index: usize,
+        client: &str,
+        operation: OrderType,
+        price: u32,
+        value: u32,
+    ) -> NewOrder {
+        NewOrder {
+            index,
+            client_name: client.to_string(),
+            operation,
+            asset: "A".to_string(),
+            order_price: price,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_buy() {
+        let mut clients = test_clients();
+        let mut orders: Orders = Orders::new();
+
+        let sell_order_1 = order(0, "C2", OrderType::Sell, 8, 4);
+        let mut buy_order_1 = order(2, "C3", OrderType::Buy, 10, 6);
+
+        orders.insert(sell_order_1.index, sell_order_1);
+
+        let Matched {
+            completed,
+            updated,
+            trades,
+        } = buy_assets(&orders, &mut clients, &mut buy_order_1);
+
+        assert_eq!(completed, [0]);
+        assert!(updated.is_empty());
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, 8);
+        assert_eq!(trades[0].volume, 4);
+        assert_eq!(buy_order_1.value, 2);
+        assert_eq!(clients.get("C2").unwrap().dollar_balance, 1032);
+        assert_eq!(
+            clients
+                .get("C2")
+                .unwrap()
+                .asset_balances
+                .get("A")
+                .unwrap()
+                .balance,
+            21
+        );
+        assert_eq!(clients.get("C3").unwrap().dollar_balance, 968);
+        assert_eq!(
+            clients
+                .get("C3")
+                .unwrap()
+                .asset_balances
+                .get("A")
+                .unwrap()
+                .balance,
+            29
+        );
+    }
+
+    #[test]
+    fn test_sell() {
+        let mut clients = test_clients();
+        let mut orders: Orders = Orders::new();
+
+        let mut sell_order_1 = order(0, "C2", OrderType::Sell, 8, 4);
+        let buy_order_1 = order(2, "C3", OrderType::Buy, 10, 6);
+
+        orders.insert(buy_order_1.index, buy_order_1);
+
+        let Matched {
+            completed,
+            updated,
+            trades,
+        } = sell_assets(&orders, &mut clients, &mut sell_order_1);
+
+        assert!(completed.is_empty());
+        assert_eq!(updated, [(2, 2)]);
+        assert_eq!(trades[0].price, 10);
+        assert_eq!(clients.get("C2").unwrap().dollar_balance, 1040);
+        assert_eq!(
+            clients
+                .get("C2")
+                .unwrap()
+                .asset_balances
+                .get("A")
+                .unwrap()
+                .balance,
+            21
+        );
+        assert_eq!(clients.get("C3").unwrap().dollar_balance, 960);
+        assert_eq!(
+            clients
+                .get("C3")
+                .unwrap()
+                .asset_balances
+                .get("A")
+                .unwrap()
+                .balance,
+            29
+        );
+    }
+
+    #[test]
+    fn test_process_rests_remainder() {
+        let mut engine = Engine::new(test_clients());
+        engine
+            .enter(&new_order(1, "C2", OrderType::Sell, 8, 4))
+            .unwrap();
+        let trades = engine
+            .enter(&new_order(2, "C3", OrderType::Buy, 10, 6))
+            .unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert!(engine.sell_orders.order.is_empty());
+        assert_eq!(engine.buy_orders.get(2usize).unwrap().value, 2);
+        assert_eq!(engine.depth("A").bids[0].volume, 2);
+        assert_eq!(engine.order_depth("A").bids[0].index, 2);
+        assert!(engine.order_depth("A").asks.is_empty());
+        assert_eq!(trades[0].to_string(), "2\t1\tC3\tC2\tA\t8\t4\n");
+    }
+
+    #[test]
+    fn test_cancel_and_replace() {
+        let mut engine = Engine::new(test_clients());
+        engine
+            .enter(&new_order(1, "C2", OrderType::Sell, 12, 4))
+            .unwrap();
+        engine
+            .enter(&new_order(2, "C3", OrderType::Buy, 10, 6))
+            .unwrap();
+        assert!(engine.cancel(7).is_err());
+
+        let trades = engine.replace(2, 3, 12, 6).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert!(engine.get_order(2).is_none());
+        assert_eq!(engine.get_order(3).unwrap().value, 2);
+
+        let quote = engine.quote("A").unwrap();
+        assert_eq!(quote.bid_price, Some(12));
+        assert_eq!(quote.last_price, Some(12));
+        assert_eq!(quote.volume, 4);
+
+        assert_eq!(engine.cancel(3).unwrap().value, 2);
+        assert!(engine.buy_orders.order.is_empty());
+        assert_eq!(engine.quote("A").unwrap().bid_price, None);
+    }
+
+    #[test]
+    fn test_pnl() {
+        let mut engine = Engine::new(test_clients());
+        engine
+            .enter(&new_order(1, "C2", OrderType::Sell, 8, 4))
+            .unwrap();
+        engine
+            .enter(&new_order(2, "C3", OrderType::Buy, 8, 4))
+            .unwrap();
+        engine
+            .enter(&new_order(3, "C2", OrderType::Buy, 10, 4))
+            .unwrap();
+        engine
+            .enter(&new_order(4, "C3", OrderType::Sell, 9, 4))
+            .unwrap();
+
+        let c2 = engine.clients.get("C2").unwrap();
+        let c3 = engine.clients.get("C3").unwrap();
+        assert_eq!(c2.realized_pnl(), 0.0);
+        assert_eq!(c2.unrealized_pnl(&engine.quotes), 42.0);
+        assert_eq!(c3.realized_pnl(), 8.0);
+        assert_eq!(c3.unrealized_pnl(&engine.quotes), 50.0);
+    }
+
+    #[test]
+    fn test_process_rejects() {
+        let mut engine = Engine::new(test_clients());
+        let rejected = [
+            (
+                new_order(1, "C9", OrderType::Buy, 10, 1),
+                GeneralErrors::GetClientError,
+            ),
+            (
+                NewOrder {
+                    asset: "Z".to_string(),
+                    ..new_order(2, "C2", OrderType::Buy, 10, 1)
+                },
+                GeneralErrors::GetAssetError,
+            ),
+            (
+                new_order(3, "C2", OrderType::IsNotOrderType, 10, 1),
+                GeneralErrors::NoSuchOperationError,
+            ),
+        ];
+        for (order, expected) in rejected {
+            match engine.enter(&order) {
+                Err(TradeMatchErrors::EngineError(error)) => {
+                    assert_eq!(error.to_string(), expected.to_string())
+                }
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        }
+        assert!(engine.buy_orders.order.is_empty());
+        assert!(engine.quote("A").is_none());
+    }
+
+    #[test]
+    fn test_risk_limits() {
+        let mut engine = Engine::new(test_clients());
+        engine.risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_open_orders: Some(1),
+                ..Limits::default()
+            },
+            ..RiskConfig::default()
+        });
+        engine
+            .enter(&new_order(1, "C2", OrderType::Sell, 12, 4))
+            .unwrap();
+        assert!(matches!(
+            engine.enter(&new_order(2, "C2", OrderType::Sell, 13, 4)),
+            Err(TradeMatchErrors::RiskError(RiskErrors::OpenOrdersError(
+                1, 1
+            )))
+        ));
+        assert!(engine.get_order(2).is_none());
+        // C3 has no orders open yet, and once C2's has filled it may send more.
+        engine
+            .enter(&new_order(3, "C3", OrderType::Buy, 12, 4))
+            .unwrap();
+        engine
+            .enter(&new_order(4, "C2", OrderType::Sell, 13, 4))
+            .unwrap();
+        assert_eq!(engine.open_orders("C2"), 1);
+    }
+
+    #[test]
+    fn test_position_limit_counts_resting_buys() {
+        let mut engine = Engine::new(test_clients());
+        engine.risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_position: Some(40),
+                ..Limits::default()
+            },
+            ..RiskConfig::default()
+        });
+        // C3 holds 25 and its two resting buys would take it to 40.
+        engine
+            .enter(&new_order(1, "C3", OrderType::Buy, 5, 10))
+            .unwrap();
+        engine
+            .enter(&new_order(2, "C3", OrderType::Buy, 5, 5))
+            .unwrap();
+        assert!(matches!(
+            engine.enter(&new_order(3, "C3", OrderType::Buy, 5, 1)),
+            Err(TradeMatchErrors::RiskError(RiskErrors::PositionError(
+                41,
+                _,
+                40
+            )))
+        ));
+        assert!(engine.get_order(3).is_none());
+    }
+
+    #[test]
+    fn test_apply_binary() {
+        let mut bytes = binary::MAGIC.to_vec();
+        bytes.extend_from_slice(
+            &binary::encode_order(&new_order(1, "C2", OrderType::Sell, 8, 4)).unwrap(),
+        );
+        bytes.extend_from_slice(
+            &binary::encode_order(&new_order(2, "C2", OrderType::Sell, 9, 4)).unwrap(),
+        );
+        bytes.extend_from_slice(&binary::encode_cancel(1));
+        bytes.extend_from_slice(
+            &binary::encode_order(&new_order(3, "C3", OrderType::Buy, 10, 2)).unwrap(),
+        );
+
+        let mut engine = Engine::new(test_clients());
+        let mut trades = Vec::new();
+        for message in binary::messages(&bytes).unwrap() {
+            trades.append(&mut engine.apply(&message).unwrap());
+        }
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].sell_index, 2);
+        assert_eq!(engine.get_order(2).unwrap().value, 2);
+    }
+
+    #[test]
+    fn test_stream_from_channel() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let producer = std::thread::spawn(move || {
+            sender
+                .send(new_order(1, "C2", OrderType::Sell, 8, 4))
+                .unwrap();
+            sender
+                .send(new_order(2, "C3", OrderType::Buy, 8, 3))
+                .unwrap();
+        });
+
+        let mut engine = Engine::new(test_clients());
+        let fills: Vec<usize> = engine
+            .stream(receiver)
+            .map(|trades| trades.unwrap().len())
+            .collect();
+        producer.join().unwrap();
+        assert_eq!(fills, vec![0, 1]);
+        assert_eq!(engine.sell_orders.get(1usize).unwrap().value, 1);
+        assert_eq!(engine.clients.registry.clients.len(), 2);
+        assert_eq!(
+            engine.sell_orders.get(1usize).unwrap().client_id,
+            engine.clients.id("C2").unwrap()
+        );
+    }
+}
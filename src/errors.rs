@@ -1,3 +1,5 @@
+use config::ConfigError;
+use std::io;
 use thiserror::Error;
 
 #[derive(Debug, Clone, Error, PartialEq)]
@@ -10,6 +12,14 @@ pub enum ClientErrors {
     ParseDollarBalanceError,
     #[error("Can't parse client item: Insufficent input data")]
     ParseInsufficentInput,
+    #[error("Unable to parse client JSON: {0}")]
+    ParseJsonError(String),
+}
+
+impl From<serde_json::Error> for ClientErrors {
+    fn from(error: serde_json::Error) -> Self {
+        ClientErrors::ParseJsonError(error.to_string())
+    }
 }
 
 #[derive(Clone, Debug, Error)]
@@ -26,6 +36,8 @@ pub enum GeneralErrors {
     NotEnaughDollars,
     #[error("Your asset balance can't be negative")]
     NotEnaughAsset,
+    #[error("Your balance can't hold that much")]
+    BalanceOverflowError,
     #[error("No such operation")]
     NoSuchOperationError,
     #[error("Unable to get a client from the map")]
@@ -34,6 +46,8 @@ pub enum GeneralErrors {
     GetOrderError,
     #[error("Unable to get asset from the map")]
     GetAssetError,
+    #[error("Unable to start the FIX acceptor")]
+    FixAcceptorError,
 }
 
 #[derive(Error, Debug, Clone, PartialEq)]
@@ -52,4 +66,125 @@ pub enum OrderErrors {
     ParseInsufficentInputError,
     #[error("No such operation symbol")]
     NoSuchOperationSymbolError,
-}
\ No newline at end of file
+    #[error("Unable to parse order JSON: {0}")]
+    ParseJsonError(String),
+    #[error("Unable to parse sequence number")]
+    ParseSequenceError,
+}
+
+impl From<serde_json::Error> for OrderErrors {
+    fn from(error: serde_json::Error) -> Self {
+        OrderErrors::ParseJsonError(error.to_string())
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FixErrors {
+    #[error("Unable to parse FIX field")]
+    ParseFieldError,
+    #[error("FIX message does not start with BeginString and BodyLength")]
+    ParseHeaderError,
+    #[error("FIX message body length does not match")]
+    BodyLengthError,
+    #[error("FIX message checksum does not match")]
+    CheckSumError,
+    #[error("Required FIX tag {0} is missing")]
+    MissingFieldError(u32),
+    #[error("FIX tag {0} has an invalid value")]
+    InvalidFieldError(u32),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BinaryErrors {
+    #[error("Binary stream does not start with the expected magic bytes")]
+    MagicError,
+    #[error("Binary stream has {0} bytes that do not form whole messages")]
+    LengthError(usize),
+    #[error("Unknown binary message kind {0}")]
+    InvalidKindError(u8),
+    #[error("Unknown binary order side {0}")]
+    InvalidSideError(u8),
+    #[error("Binary message name is not valid UTF-8")]
+    InvalidNameError,
+    #[error("Name {0:?} does not fit in a binary message")]
+    NameLengthError(String),
+}
+
+// Why an order was rejected by its client's pre-trade risk limits.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RiskErrors {
+    #[error("Order volume {0} is over the limit of {1}")]
+    OrderVolumeError(u32, u32),
+    #[error("Order notional {0} is over the limit of {1}")]
+    OrderNotionalError(u64, u64),
+    #[error("{0} orders are already open, the limit is {1}")]
+    OpenOrdersError(usize, usize),
+    #[error("Position of {0} in {1} would be over the limit of {2}")]
+    PositionError(u64, String, u64),
+    #[error("Traded notional of {0} today would be over the limit of {1}")]
+    DailyNotionalError(u64, u64),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MarginErrors {
+    #[error("Owing {0:.2} would be over the {1:.2} the account's leverage allows")]
+    LeverageError(f64, f64),
+}
+
+// A ledger that does not add up.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LedgerErrors {
+    #[error("Journal entry does not balance: {1} of {0} is left over")]
+    UnbalancedError(String, i64),
+    #[error("Ledger holds {2} of {1} for {0}, the balances {3}")]
+    ReconcileError(String, String, i64, i64),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum GeneratorErrors {
+    #[error("Generator setting {0} is out of range")]
+    SettingError(&'static str),
+    #[error("Text clients files hold the assets A to D, not {0} assets")]
+    TextAssetsError(usize),
+    #[error("Cancels can only be written to a binary orders file")]
+    CancelFormatError,
+}
+
+// A line of an input file that failed to parse.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{file}:{line}: {message}: {text:?}")]
+pub struct LineError {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+    pub message: String,
+}
+
+// Every way the library can fail, so callers can handle one error type.
+#[derive(Error, Debug)]
+pub enum TradeMatchErrors {
+    #[error("Unable to read {path}: {source}")]
+    IoError { path: String, source: io::Error },
+    #[error("Unable to write {path}: {source}")]
+    WriteError { path: String, source: io::Error },
+    #[error("Unable to read {path} as CSV: {source}")]
+    CsvError { path: String, source: csv::Error },
+    #[error(transparent)]
+    ParseError(#[from] LineError),
+    #[error("{} invalid lines", .0.len())]
+    InvalidLinesError(Vec<LineError>),
+    #[error("Unable to get configuration: {0}")]
+    ConfigError(#[from] ConfigError),
+    #[error(transparent)]
+    EngineError(#[from] GeneralErrors),
+    #[error(transparent)]
+    BinaryError(#[from] BinaryErrors),
+    #[error(transparent)]
+    RiskError(#[from] RiskErrors),
+    #[error(transparent)]
+    MarginError(#[from] MarginErrors),
+    #[error(transparent)]
+    LedgerError(#[from] LedgerErrors),
+    #[error(transparent)]
+    GeneratorError(#[from] GeneratorErrors),
+}
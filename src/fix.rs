@@ -0,0 +1,1230 @@
+use crate::{
+    candles::Candles,
+    engine::{Engine, Trade},
+    errors::FixErrors,
+    orders::{Order, OrderType},
+    symbols::{Symbol, Symbols},
+    Volume,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const FIX_VERSION: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 30;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type Result<T> = std::result::Result<T, FixErrors>;
+
+// Outgoing messages paired with the CompID of the session they are meant for.
+pub type Routed = Vec<(String, Message)>;
+
+// Accounts each SenderCompID may trade. A sender that is not listed may
+// trade none.
+pub type Accounts = BTreeMap<String, BTreeSet<String>>;
+
+// FIX tags used by the gateway.
+pub mod tags {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+// FIX message types handled by the gateway.
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+use tags::*;
+
+// A FIX message without BeginString, BodyLength and CheckSum, which are
+// computed on encoding and verified on decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Message {
+        Message {
+            fields: vec![(MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Reads a required field and parses it into the requested type.
+    pub fn parse<T: FromStr>(&self, tag: u32) -> Result<T> {
+        self.get(tag)
+            .ok_or(FixErrors::MissingFieldError(tag))?
+            .parse::<T>()
+            .map_err(|_| FixErrors::InvalidFieldError(tag))
+    }
+
+    pub fn with<T: ToString>(mut self, tag: u32, value: T) -> Message {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    // Serialises the message into a complete frame.
+    pub fn encode(&self) -> String {
+        let body: String = self
+            .fields
+            .iter()
+            .map(|(tag, value)| format!("{}={}\u{1}", tag, value))
+            .collect();
+        let mut frame = format!(
+            "{}={}\u{1}{}={}\u{1}{}",
+            BEGIN_STRING,
+            FIX_VERSION,
+            BODY_LENGTH,
+            body.len(),
+            body
+        );
+        let check_sum = checksum(frame.as_bytes());
+        frame.push_str(&format!("{}={:03}\u{1}", CHECK_SUM, check_sum));
+        frame
+    }
+}
+
+// Decoding of a complete frame, verifying body length and checksum.
+impl FromStr for Message {
+    type Err = FixErrors;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let trailer = s
+            .trim_end_matches('\u{1}')
+            .rfind('\u{1}')
+            .ok_or(FixErrors::ParseHeaderError)?
+            + 1;
+        let mut fields = Vec::new();
+        for field in s.split('\u{1}').filter(|field| !field.is_empty()) {
+            let (tag, value) = field.split_once('=').ok_or(FixErrors::ParseFieldError)?;
+            let tag = tag.parse::<u32>().map_err(|_| FixErrors::ParseFieldError)?;
+            fields.push((tag, value.to_string()));
+        }
+
+        if fields.len() < 4
+            || fields[0] != (BEGIN_STRING, FIX_VERSION.to_string())
+            || fields[1].0 != BODY_LENGTH
+        {
+            return Err(FixErrors::ParseHeaderError);
+        }
+        let body_start =
+            s.find("\u{1}9=").ok_or(FixErrors::ParseHeaderError)? + fields[1].1.len() + 4;
+        let body_length = fields[1]
+            .1
+            .parse::<usize>()
+            .map_err(|_| FixErrors::BodyLengthError)?;
+        if trailer < body_start || trailer - body_start != body_length {
+            return Err(FixErrors::BodyLengthError);
+        }
+
+        let (tag, value) = fields.pop().ok_or(FixErrors::ParseHeaderError)?;
+        if tag != CHECK_SUM || value.parse::<u8>() != Ok(checksum(&s.as_bytes()[..trailer])) {
+            return Err(FixErrors::CheckSumError);
+        }
+
+        Ok(Message {
+            fields: fields.split_off(2),
+        })
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Cuts the first complete frame off the front of a receive buffer.
+// Returns None while the frame is still incomplete.
+pub fn take_frame(buffer: &mut Vec<u8>) -> Option<Result<Message>> {
+    let start = buffer.windows(2).position(|window| window == b"8=")?;
+    buffer.drain(..start);
+
+    let mut separators = buffer
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == SOH)
+        .map(|(position, _)| position);
+    let begin_end = separators.next()?;
+    let length_end = separators.next()?;
+    let body_length = std::str::from_utf8(&buffer[begin_end + 1..length_end])
+        .ok()
+        .and_then(|field| field.strip_prefix("9="))
+        .and_then(|length| length.parse::<usize>().ok());
+    let body_length = match body_length {
+        Some(body_length) => body_length,
+        None => {
+            buffer.drain(..length_end + 1);
+            return Some(Err(FixErrors::ParseHeaderError));
+        }
+    };
+
+    // The trailer is always "10=nnn<SOH>".
+    let end = length_end + 1 + body_length + 7;
+    if buffer.len() < end {
+        return None;
+    }
+    let frame: Vec<u8> = buffer.drain(..end).collect();
+    Some(
+        String::from_utf8(frame)
+            .map_err(|_| FixErrors::ParseFieldError)
+            .and_then(|frame| Message::from_str(&frame)),
+    )
+}
+
+// UTC timestamp in the FIX "YYYYMMDD-HH:MM:SS.sss" format.
+pub fn sending_time() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs();
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+
+    // Days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SessionState {
+    AwaitingLogon,
+    Active,
+    LoggedOut,
+}
+
+// Session layer of a single acceptor connection: logon/logout,
+// sequence numbers and heartbeats.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub comp_id: String,
+    pub target_comp_id: String,
+    pub next_out_seq: u64,
+    pub next_in_seq: u64,
+    pub heartbeat_interval: Duration,
+    pub state: SessionState,
+    // Messages received past a sequence gap, waiting for the resend to fill
+    // it. A Logon is answered on arrival and only holds its place.
+    pending: BTreeMap<u64, Option<Message>>,
+}
+
+impl Session {
+    pub fn new(comp_id: &str) -> Session {
+        Session {
+            comp_id: comp_id.to_string(),
+            target_comp_id: String::new(),
+            next_out_seq: 1,
+            next_in_seq: 1,
+            heartbeat_interval: Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL),
+            state: SessionState::AwaitingLogon,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    // Adds the standard header and the next outgoing sequence number.
+    pub fn stamp(&mut self, message: Message) -> Message {
+        let mut stamped = Message::new(message.msg_type())
+            .with(SENDER_COMP_ID, &self.comp_id)
+            .with(TARGET_COMP_ID, &self.target_comp_id)
+            .with(MSG_SEQ_NUM, self.next_out_seq)
+            .with(SENDING_TIME, sending_time());
+        stamped.fields.extend(message.fields.into_iter().skip(1));
+        self.next_out_seq += 1;
+        stamped
+    }
+
+    // Handles an inbound message. Session-level messages are answered here,
+    // order entry messages are passed on to the gateway.
+    pub fn handle(&mut self, message: Message, gateway: &mut Gateway) -> Routed {
+        let mut replies = Vec::new();
+        if let Some(sender) = message.get(SENDER_COMP_ID) {
+            if self.state == SessionState::AwaitingLogon {
+                self.target_comp_id = sender.to_string();
+            }
+        }
+
+        if self.state == SessionState::AwaitingLogon && message.msg_type() != msg_type::LOGON {
+            return self.logout("First message must be Logon");
+        }
+        if message.msg_type() == msg_type::LOGON && message.get(RESET_SEQ_NUM_FLAG) == Some("Y") {
+            self.next_in_seq = 1;
+            self.next_out_seq = 1;
+            self.pending.clear();
+        }
+
+        let seq_num = match message.parse::<u64>(MSG_SEQ_NUM) {
+            Ok(seq_num) => seq_num,
+            Err(_) => return self.logout("MsgSeqNum missing"),
+        };
+        if message.msg_type() == msg_type::SEQUENCE_RESET {
+            if let Ok(new_seq_no) = message.parse::<u64>(NEW_SEQ_NO) {
+                self.next_in_seq = new_seq_no.max(self.next_in_seq);
+            }
+            self.pending = self.pending.split_off(&self.next_in_seq);
+            self.release(gateway, &mut replies);
+            return replies;
+        }
+        if seq_num < self.next_in_seq {
+            if message.get(POSS_DUP_FLAG) == Some("Y") {
+                return replies;
+            }
+            return self.logout(&format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                self.next_in_seq, seq_num
+            ));
+        }
+        if seq_num > self.next_in_seq {
+            let gap_opened = self.pending.is_empty();
+            let held = if message.msg_type() == msg_type::LOGON {
+                self.dispatch(message, seq_num, gateway, &mut replies);
+                None
+            } else {
+                Some(message)
+            };
+            self.pending.insert(seq_num, held);
+            if gap_opened && self.state != SessionState::LoggedOut {
+                replies.push(
+                    self.reply(
+                        Message::new(msg_type::RESEND_REQUEST)
+                            .with(BEGIN_SEQ_NO, self.next_in_seq)
+                            .with(END_SEQ_NO, 0),
+                    ),
+                );
+            }
+            return replies;
+        }
+        self.next_in_seq = seq_num + 1;
+        self.dispatch(message, seq_num, gateway, &mut replies);
+        self.release(gateway, &mut replies);
+        replies
+    }
+
+    // Processes held messages that are now in sequence.
+    fn release(&mut self, gateway: &mut Gateway, replies: &mut Routed) {
+        while self.state != SessionState::LoggedOut {
+            let Some(held) = self.pending.remove(&self.next_in_seq) else {
+                break;
+            };
+            let seq_num = self.next_in_seq;
+            self.next_in_seq += 1;
+            if let Some(message) = held {
+                self.dispatch(message, seq_num, gateway, replies);
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        message: Message,
+        seq_num: u64,
+        gateway: &mut Gateway,
+        replies: &mut Routed,
+    ) {
+        match message.msg_type() {
+            msg_type::LOGON => {
+                if message.get(TARGET_COMP_ID) != Some(self.comp_id.as_str()) {
+                    replies.extend(self.logout("Unknown TargetCompID"));
+                    return;
+                }
+                let interval = message
+                    .parse::<u64>(HEART_BT_INT)
+                    .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL);
+                self.heartbeat_interval = Duration::from_secs(interval.max(1));
+                self.state = SessionState::Active;
+                replies.push(
+                    self.reply(
+                        Message::new(msg_type::LOGON)
+                            .with(ENCRYPT_METHOD, 0)
+                            .with(HEART_BT_INT, interval),
+                    ),
+                );
+            }
+            msg_type::HEARTBEAT | msg_type::REJECT => {}
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = Message::new(msg_type::HEARTBEAT);
+                if let Some(id) = message.get(TEST_REQ_ID) {
+                    heartbeat = heartbeat.with(TEST_REQ_ID, id);
+                }
+                replies.push(self.reply(heartbeat));
+            }
+            msg_type::RESEND_REQUEST => {
+                // Nothing is stored for resending, so the whole range is skipped.
+                // NewSeqNo must follow the reset itself, which is stamped after
+                // any replies queued before it.
+                replies.push(
+                    self.reply(
+                        Message::new(msg_type::SEQUENCE_RESET)
+                            .with(GAP_FILL_FLAG, "N")
+                            .with(NEW_SEQ_NO, self.next_out_seq + replies.len() as u64 + 1),
+                    ),
+                );
+            }
+            msg_type::LOGOUT => {
+                self.state = SessionState::LoggedOut;
+                replies.push(self.reply(Message::new(msg_type::LOGOUT)));
+            }
+            msg_type::NEW_ORDER_SINGLE => {
+                replies.extend(gateway.new_order_single(&self.target_comp_id, &message))
+            }
+            msg_type::ORDER_CANCEL_REQUEST => {
+                replies.extend(gateway.order_cancel_request(&self.target_comp_id, &message))
+            }
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => {
+                replies.extend(gateway.order_cancel_replace_request(&self.target_comp_id, &message))
+            }
+            other => replies.push(
+                self.reply(
+                    Message::new(msg_type::REJECT)
+                        .with(REF_SEQ_NUM, seq_num)
+                        .with(REF_MSG_TYPE, other)
+                        .with(SESSION_REJECT_REASON, 11)
+                        .with(TEXT, "Unsupported MsgType"),
+                ),
+            ),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == SessionState::Active
+    }
+
+    fn reply(&self, message: Message) -> (String, Message) {
+        (self.target_comp_id.clone(), message)
+    }
+
+    fn logout(&mut self, text: &str) -> Routed {
+        self.state = SessionState::LoggedOut;
+        vec![self.reply(Message::new(msg_type::LOGOUT).with(TEXT, text))]
+    }
+}
+
+// Gateway bookkeeping for an order entered over FIX.
+#[derive(Debug, Clone)]
+struct OrderRef {
+    owner: String,
+    order_id: usize,
+    cl_ord_id: String,
+    order: Order,
+    cum_qty: Volume,
+    notional: u64,
+}
+
+impl OrderRef {
+    fn ord_status(&self) -> char {
+        if self.cum_qty == 0 {
+            '0'
+        } else if self.cum_qty < self.order.value {
+            '1'
+        } else {
+            '2'
+        }
+    }
+}
+
+// Application layer: maps FIX order entry onto the matching engine.
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    pub engine: Engine,
+    pub candles: Option<Candles>,
+    pub accounts: Accounts,
+    next_index: usize,
+    next_exec_id: u64,
+    orders: BTreeMap<usize, OrderRef>,
+    cl_ord_ids: BTreeMap<(String, String), usize>,
+}
+
+impl Gateway {
+    pub fn new(engine: Engine, accounts: Accounts) -> Gateway {
+        Gateway {
+            engine,
+            candles: None,
+            accounts,
+            next_index: 1,
+            next_exec_id: 1,
+            orders: BTreeMap::new(),
+            cl_ord_ids: BTreeMap::new(),
+        }
+    }
+
+    pub fn new_order_single(&mut self, owner: &str, message: &Message) -> Routed {
+        let order = match self.to_order(owner, message) {
+            Ok(order) => order,
+            Err(text) => return vec![(owner.to_string(), self.reject(message, &text))],
+        };
+        let cl_ord_id = message.get(CL_ORD_ID).unwrap_or_default().to_string();
+        if self
+            .cl_ord_ids
+            .contains_key(&(owner.to_string(), cl_ord_id.clone()))
+        {
+            return vec![(owner.to_string(), self.reject(message, "Duplicate ClOrdID"))];
+        }
+
+        let index = self.next_index();
+        self.cl_ord_ids
+            .insert((owner.to_string(), cl_ord_id.clone()), index);
+        self.orders.insert(
+            index,
+            OrderRef {
+                owner: owner.to_string(),
+                order_id: index,
+                cl_ord_id,
+                order: Order { index, ..order },
+                cum_qty: 0,
+                notional: 0,
+            },
+        );
+
+        let trades = match self.engine.process(Order { index, ..order }) {
+            Ok(trades) => trades,
+            Err(error) => {
+                if let Some(order_ref) = self.orders.remove(&index) {
+                    self.cl_ord_ids
+                        .remove(&(owner.to_string(), order_ref.cl_ord_id));
+                }
+                return vec![(owner.to_string(), self.reject(message, &error.to_string()))];
+            }
+        };
+        let mut reports = vec![self.execution_report(index, '0', None, None)];
+        self.record(&trades);
+        reports.extend(self.fills(&trades));
+        self.forget_closed();
+        reports
+    }
+
+    pub fn order_cancel_request(&mut self, owner: &str, message: &Message) -> Routed {
+        let index = match self.open_order(owner, message) {
+            Some(index) => index,
+            None => {
+                return vec![(
+                    owner.to_string(),
+                    self.cancel_reject(message, '1', UNKNOWN_ORDER),
+                )]
+            }
+        };
+        if !self.may_amend(owner, index, message) {
+            return vec![(
+                owner.to_string(),
+                self.cancel_reject(message, '1', ACCOUNT_NOT_ALLOWED),
+            )];
+        }
+        if self.engine.cancel(index).is_err() {
+            return vec![(
+                owner.to_string(),
+                self.cancel_reject(message, '1', UNKNOWN_ORDER),
+            )];
+        }
+        self.record(&[]);
+        self.rename(owner, index, message);
+        let report = self.execution_report(index, '4', None, message.get(ORIG_CL_ORD_ID));
+        self.orders.remove(&index);
+        vec![report]
+    }
+
+    pub fn order_cancel_replace_request(&mut self, owner: &str, message: &Message) -> Routed {
+        let index = match self.open_order(owner, message) {
+            Some(index) => index,
+            None => {
+                return vec![(
+                    owner.to_string(),
+                    self.cancel_reject(message, '2', UNKNOWN_ORDER),
+                )]
+            }
+        };
+        if !self.may_amend(owner, index, message) {
+            return vec![(
+                owner.to_string(),
+                self.cancel_reject(message, '2', ACCOUNT_NOT_ALLOWED),
+            )];
+        }
+        let (price, quantity) = match (message.parse(PRICE), message.parse::<Volume>(ORDER_QTY)) {
+            (Ok(price), Ok(quantity)) if quantity > self.orders[&index].cum_qty => {
+                (price, quantity)
+            }
+            _ => {
+                return vec![(
+                    owner.to_string(),
+                    self.cancel_reject(message, '2', UNKNOWN_ORDER),
+                )]
+            }
+        };
+
+        let new_index = self.next_index();
+        let leaves = quantity - self.orders[&index].cum_qty;
+        // A rejected replacement leaves the original order resting.
+        let trades = match self.engine.replace(index, new_index, price, leaves) {
+            Ok(trades) => trades,
+            Err(error) => {
+                let text = error.to_string();
+                return vec![(
+                    owner.to_string(),
+                    self.cancel_reject(message, '2', (OTHER, &text)),
+                )];
+            }
+        };
+        self.record(&trades);
+
+        let mut order_ref = self.orders.remove(&index).unwrap();
+        order_ref.order.index = new_index;
+        order_ref.order.order_price = price;
+        order_ref.order.value = quantity;
+        self.orders.insert(new_index, order_ref);
+        self.rename(owner, new_index, message);
+
+        let mut reports =
+            vec![self.execution_report(new_index, '5', None, message.get(ORIG_CL_ORD_ID))];
+        reports.extend(self.fills(&trades));
+        self.forget_closed();
+        reports
+    }
+
+    // Feeds the fills of one order entry message to the candle aggregation.
+    fn record(&mut self, trades: &[Trade]) {
+        if let Some(candles) = self.candles.as_mut() {
+            candles.on_message(trades);
+        }
+    }
+
+    fn next_index(&mut self) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        index
+    }
+
+    fn next_exec_id(&mut self) -> u64 {
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        exec_id
+    }
+
+    // Maps a NewOrderSingle onto an engine order.
+    fn to_order(&self, owner: &str, message: &Message) -> std::result::Result<Order, String> {
+        let client_name = message.get(ACCOUNT).unwrap_or(owner).to_string();
+        if !self.may_trade(owner, &client_name) {
+            return Err(ACCOUNT_NOT_ALLOWED.1.to_string());
+        }
+        let operation = match message.get(SIDE) {
+            Some("1") => OrderType::Buy,
+            Some("2") => OrderType::Sell,
+            _ => return Err("Unsupported Side".to_string()),
+        };
+        if message.get(ORD_TYPE).unwrap_or("2") != "2" {
+            return Err("Only limit orders are supported".to_string());
+        }
+        message
+            .get(CL_ORD_ID)
+            .ok_or_else(|| FixErrors::MissingFieldError(CL_ORD_ID).to_string())?;
+        let asset: String = message.parse(SYMBOL).map_err(|e| e.to_string())?;
+        let order_price = message.parse(PRICE).map_err(|e| e.to_string())?;
+        let value = message.parse(ORDER_QTY).map_err(|e| e.to_string())?;
+        if value == 0 {
+            return Err(FixErrors::InvalidFieldError(ORDER_QTY).to_string());
+        }
+
+        let client = self
+            .engine
+            .clients
+            .get(&client_name)
+            .ok_or_else(|| "Unknown account".to_string())?;
+        let asset_id = client
+            .asset_balances
+            .id(&asset)
+            .ok_or_else(|| "Unknown symbol".to_string())?;
+
+        Ok(Order {
+            index: 0,
+            client_id: client.id,
+            operation,
+            asset_id,
+            order_price,
+            value,
+        })
+    }
+
+    fn may_trade(&self, owner: &str, account: &str) -> bool {
+        self.accounts
+            .get(owner)
+            .is_some_and(|accounts| accounts.contains(account))
+    }
+
+    // Whether a cancel or replace may touch an open order: its Account, when
+    // given, has to be the order's, and one the sender may trade.
+    fn may_amend(&self, owner: &str, index: usize, message: &Message) -> bool {
+        let account = name(
+            &self.engine.clients.registry.clients,
+            self.orders[&index].order.client_id,
+        );
+        message.get(ACCOUNT).is_none_or(|tag| tag == account) && self.may_trade(owner, account)
+    }
+
+    // Index of the open order referred to by OrigClOrdID.
+    fn open_order(&self, owner: &str, message: &Message) -> Option<usize> {
+        let orig = message.get(ORIG_CL_ORD_ID)?;
+        let index = *self
+            .cl_ord_ids
+            .get(&(owner.to_string(), orig.to_string()))?;
+        self.orders.contains_key(&index).then_some(index)
+    }
+
+    fn rename(&mut self, owner: &str, index: usize, message: &Message) {
+        if let Some(cl_ord_id) = message.get(CL_ORD_ID) {
+            self.cl_ord_ids
+                .insert((owner.to_string(), cl_ord_id.to_string()), index);
+            if let Some(order_ref) = self.orders.get_mut(&index) {
+                order_ref.cl_ord_id = cl_ord_id.to_string();
+            }
+        }
+    }
+
+    // Trade reports for both sides of every fill that involves a FIX order.
+    fn fills(&mut self, trades: &[Trade]) -> Routed {
+        let mut reports = Vec::new();
+        for trade in trades {
+            for index in [trade.buy_index, trade.sell_index] {
+                if let Some(order_ref) = self.orders.get_mut(&index) {
+                    order_ref.cum_qty += trade.volume;
+                    order_ref.notional += u64::from(trade.price) * u64::from(trade.volume);
+                    reports.push(self.execution_report(index, 'F', Some(trade), None));
+                }
+            }
+        }
+        reports
+    }
+
+    fn forget_closed(&mut self) {
+        self.orders
+            .retain(|_, order_ref| order_ref.cum_qty < order_ref.order.value);
+    }
+
+    fn execution_report(
+        &mut self,
+        index: usize,
+        exec_type: char,
+        trade: Option<&Trade>,
+        orig_cl_ord_id: Option<&str>,
+    ) -> (String, Message) {
+        let exec_id = self.next_exec_id();
+        let order_ref = &self.orders[&index];
+        let registry = &self.engine.clients.registry;
+        let ord_status = if exec_type == '4' {
+            '4'
+        } else {
+            order_ref.ord_status()
+        };
+        let leaves = if exec_type == '4' {
+            0
+        } else {
+            order_ref.order.value - order_ref.cum_qty
+        };
+        let avg_px = if order_ref.cum_qty == 0 {
+            0.0
+        } else {
+            order_ref.notional as f64 / f64::from(order_ref.cum_qty)
+        };
+
+        let mut report = Message::new(msg_type::EXECUTION_REPORT)
+            .with(ORDER_ID, order_ref.order_id)
+            .with(CL_ORD_ID, &order_ref.cl_ord_id);
+        if let Some(orig) = orig_cl_ord_id {
+            report = report.with(ORIG_CL_ORD_ID, orig);
+        }
+        report = report
+            .with(EXEC_ID, exec_id)
+            .with(EXEC_TYPE, exec_type)
+            .with(ORD_STATUS, ord_status)
+            .with(ACCOUNT, name(&registry.clients, order_ref.order.client_id))
+            .with(SYMBOL, name(&registry.assets, order_ref.order.asset_id))
+            .with(SIDE, side(order_ref.order.operation))
+            .with(ORDER_QTY, order_ref.order.value)
+            .with(PRICE, order_ref.order.order_price);
+        if let Some(trade) = trade {
+            report = report
+                .with(LAST_QTY, trade.volume)
+                .with(LAST_PX, trade.price);
+        }
+        report = report
+            .with(LEAVES_QTY, leaves)
+            .with(CUM_QTY, order_ref.cum_qty)
+            .with(AVG_PX, format!("{:.2}", avg_px));
+        (order_ref.owner.clone(), report)
+    }
+
+    fn reject(&mut self, message: &Message, text: &str) -> Message {
+        let mut report = Message::new(msg_type::EXECUTION_REPORT)
+            .with(ORDER_ID, "NONE")
+            .with(CL_ORD_ID, message.get(CL_ORD_ID).unwrap_or_default())
+            .with(EXEC_ID, self.next_exec_id())
+            .with(EXEC_TYPE, '8')
+            .with(ORD_STATUS, '8');
+        for tag in [SYMBOL, SIDE, ORDER_QTY, PRICE] {
+            if let Some(value) = message.get(tag) {
+                report = report.with(tag, value);
+            }
+        }
+        report
+            .with(LEAVES_QTY, 0)
+            .with(CUM_QTY, 0)
+            .with(AVG_PX, 0)
+            .with(TEXT, text)
+    }
+
+    fn cancel_reject(&self, message: &Message, response_to: char, reason: (u32, &str)) -> Message {
+        Message::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(ORDER_ID, "NONE")
+            .with(CL_ORD_ID, message.get(CL_ORD_ID).unwrap_or_default())
+            .with(
+                ORIG_CL_ORD_ID,
+                message.get(ORIG_CL_ORD_ID).unwrap_or_default(),
+            )
+            .with(ORD_STATUS, '8')
+            .with(CXL_REJ_RESPONSE_TO, response_to)
+            .with(CXL_REJ_REASON, reason.0)
+            .with(TEXT, reason.1)
+    }
+}
+
+// CxlRejReason and text of an order cancel reject.
+const UNKNOWN_ORDER: (u32, &str) = (1, "Unknown order");
+const ACCOUNT_NOT_ALLOWED: (u32, &str) = (OTHER, "Account not allowed");
+const OTHER: u32 = 99;
+
+fn side(operation: OrderType) -> char {
+    match operation {
+        OrderType::Buy => '1',
+        _ => '2',
+    }
+}
+
+// Account or symbol behind an id of the engine's registry.
+fn name(symbols: &Symbols, id: Symbol) -> &str {
+    symbols.name(id).map_or("?", |name| name)
+}
+
+type Outboxes = Arc<Mutex<BTreeMap<String, mpsc::Sender<Message>>>>;
+
+// TCP acceptor serving one thread per initiator connection around a shared gateway.
+pub struct Acceptor {
+    listener: TcpListener,
+    comp_id: String,
+    gateway: Arc<Mutex<Gateway>>,
+    outboxes: Outboxes,
+}
+
+impl Acceptor {
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        comp_id: &str,
+        engine: Engine,
+        accounts: Accounts,
+    ) -> io::Result<Acceptor> {
+        Ok(Acceptor {
+            listener: TcpListener::bind(address)?,
+            comp_id: comp_id.to_string(),
+            gateway: Arc::new(Mutex::new(Gateway::new(engine, accounts))),
+            outboxes: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn gateway(&self) -> Arc<Mutex<Gateway>> {
+        self.gateway.clone()
+    }
+
+    // Accepts connections until the listener fails.
+    pub fn run(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let comp_id = self.comp_id.clone();
+            let gateway = self.gateway.clone();
+            let outboxes = self.outboxes.clone();
+            thread::spawn(move || serve(stream, &comp_id, gateway, outboxes));
+        }
+        Ok(())
+    }
+}
+
+fn send(stream: &mut TcpStream, session: &mut Session, message: Message) -> io::Result<()> {
+    let message = session.stamp(message);
+    stream.write_all(message.encode().as_bytes())
+}
+
+fn serve(
+    mut stream: TcpStream,
+    comp_id: &str,
+    gateway: Arc<Mutex<Gateway>>,
+    outboxes: Outboxes,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let (sender, receiver) = mpsc::channel();
+    let mut session = Session::new(comp_id);
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut last_received = Instant::now();
+    let mut last_sent = Instant::now();
+    let mut test_request_sent = false;
+
+    let result = loop {
+        // Reports for this session's orders filled by other sessions.
+        while let Ok(message) = receiver.try_recv() {
+            send(&mut stream, &mut session, message)?;
+            last_sent = Instant::now();
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                buffer.extend_from_slice(&chunk[..read]);
+                last_received = Instant::now();
+                test_request_sent = false;
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => break Err(e),
+        }
+
+        while let Some(frame) = take_frame(&mut buffer) {
+            // Garbled messages are dropped without consuming a sequence number.
+            let message = match frame {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            let was_active = session.is_active();
+            let replies = session.handle(message, &mut gateway.lock().unwrap());
+            if !was_active && session.is_active() {
+                outboxes
+                    .lock()
+                    .unwrap()
+                    .insert(session.target_comp_id.clone(), sender.clone());
+            }
+            for (target, message) in replies {
+                if target == session.target_comp_id {
+                    send(&mut stream, &mut session, message)?;
+                    last_sent = Instant::now();
+                } else if let Some(outbox) = outboxes.lock().unwrap().get(&target) {
+                    let _ = outbox.send(message);
+                }
+            }
+        }
+        if session.state == SessionState::LoggedOut {
+            break Ok(());
+        }
+
+        if session.is_active() {
+            let interval = session.heartbeat_interval;
+            if last_sent.elapsed() >= interval {
+                send(&mut stream, &mut session, Message::new(msg_type::HEARTBEAT))?;
+                last_sent = Instant::now();
+            }
+            if last_received.elapsed() >= interval * 2 {
+                break Ok(());
+            }
+            if !test_request_sent && last_received.elapsed() >= interval + interval / 5 {
+                let test_request =
+                    Message::new(msg_type::TEST_REQUEST).with(TEST_REQ_ID, sending_time());
+                send(&mut stream, &mut session, test_request)?;
+                last_sent = Instant::now();
+                test_request_sent = true;
+            }
+        }
+    };
+
+    if session.is_active() || session.state == SessionState::LoggedOut {
+        outboxes.lock().unwrap().remove(&session.target_comp_id);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::Clients,
+        read_file,
+        risk::{Limits, Risk, RiskConfig},
+    };
+
+    fn gateway() -> Gateway {
+        let clients: Clients = read_file("./Clients.txt".to_string()).unwrap();
+        let accounts = [("CLIENT", "C42"), ("SELLER", "C2"), ("BUYER", "C1")]
+            .into_iter()
+            .map(|(owner, account)| (owner.to_string(), BTreeSet::from([account.to_string()])))
+            .collect();
+        Gateway::new(Engine::new(clients), accounts)
+    }
+
+    fn logon(session: &mut Session, gateway: &mut Gateway) -> Routed {
+        let logon = Message::new(msg_type::LOGON)
+            .with(SENDER_COMP_ID, "CLIENT")
+            .with(TARGET_COMP_ID, "MATCH")
+            .with(MSG_SEQ_NUM, 1)
+            .with(HEART_BT_INT, 5);
+        session.handle(logon, gateway)
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let message = Message::new(msg_type::HEARTBEAT)
+            .with(SENDER_COMP_ID, "A")
+            .with(TARGET_COMP_ID, "B");
+        let encoded = message.encode();
+
+        assert!(encoded.starts_with("8=FIX.4.4\u{1}9=15\u{1}35=0\u{1}"));
+        assert_eq!(Message::from_str(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_checksum_error() {
+        let encoded = Message::new(msg_type::HEARTBEAT)
+            .encode()
+            .replace("35=0", "35=1");
+        assert_eq!(
+            Message::from_str(&encoded).unwrap_err(),
+            FixErrors::CheckSumError
+        );
+    }
+
+    #[test]
+    fn test_take_frame() {
+        let encoded = Message::new(msg_type::LOGOUT).encode();
+        let mut buffer = encoded.as_bytes()[..10].to_vec();
+        assert!(take_frame(&mut buffer).is_none());
+
+        buffer.extend_from_slice(&encoded.as_bytes()[10..]);
+        buffer.extend_from_slice(b"8=FIX");
+        let message = take_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!(message.msg_type(), msg_type::LOGOUT);
+        assert_eq!(buffer, b"8=FIX");
+    }
+
+    #[test]
+    fn test_logon_and_sequence_too_low() {
+        let mut gateway = gateway();
+        let mut session = Session::new("MATCH");
+
+        let replies = logon(&mut session, &mut gateway);
+        assert_eq!(replies[0].1.msg_type(), msg_type::LOGON);
+        assert_eq!(session.heartbeat_interval, Duration::from_secs(5));
+        assert!(session.is_active());
+
+        let stale = Message::new(msg_type::HEARTBEAT).with(MSG_SEQ_NUM, 1);
+        let replies = session.handle(stale, &mut gateway);
+        assert_eq!(replies[0].1.msg_type(), msg_type::LOGOUT);
+        assert_eq!(session.state, SessionState::LoggedOut);
+    }
+
+    #[test]
+    fn test_sequence_gap_waits_for_resend() {
+        let mut gateway = gateway();
+        let mut session = Session::new("MATCH");
+        logon(&mut session, &mut gateway);
+        let order = |seq_num: u64, cl_ord_id: &str| {
+            Message::new(msg_type::NEW_ORDER_SINGLE)
+                .with(MSG_SEQ_NUM, seq_num)
+                .with(CL_ORD_ID, cl_ord_id)
+                .with(ACCOUNT, "C42")
+                .with(SYMBOL, "A")
+                .with(SIDE, 1)
+                .with(ORDER_QTY, 1)
+                .with(PRICE, 10)
+        };
+
+        let replies = session.handle(order(3, "O3"), &mut gateway);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].1.msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(replies[0].1.get(BEGIN_SEQ_NO), Some("2"));
+        assert_eq!(session.next_in_seq, 2);
+
+        let resent = order(2, "O2").with(POSS_DUP_FLAG, "Y");
+        let replies = session.handle(resent, &mut gateway);
+        let cl_ord_ids: Vec<_> = replies
+            .iter()
+            .map(|(_, report)| report.get(CL_ORD_ID))
+            .collect();
+        assert_eq!(cl_ord_ids, [Some("O2"), Some("O3")]);
+        assert_eq!(session.next_in_seq, 4);
+    }
+
+    #[test]
+    fn test_message_before_logon() {
+        let mut session = Session::new("MATCH");
+        let message = Message::new(msg_type::NEW_ORDER_SINGLE).with(MSG_SEQ_NUM, 1);
+        let replies = session.handle(message, &mut gateway());
+        assert_eq!(replies[0].1.msg_type(), msg_type::LOGOUT);
+    }
+
+    #[test]
+    fn test_new_order_single_reject() {
+        let mut gateway = gateway();
+        let order = |account| {
+            Message::new(msg_type::NEW_ORDER_SINGLE)
+                .with(CL_ORD_ID, "1")
+                .with(ACCOUNT, account)
+                .with(SYMBOL, "A")
+                .with(SIDE, 1)
+                .with(ORDER_QTY, 5)
+                .with(PRICE, 10)
+        };
+        let reports = gateway.new_order_single("CLIENT", &order("C42"));
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].1.get(EXEC_TYPE), Some("8"));
+        assert_eq!(reports[0].1.get(TEXT), Some("Unknown account"));
+
+        // C1 exists, but is not an account CLIENT may trade.
+        let reports = gateway.new_order_single("CLIENT", &order("C1"));
+        assert_eq!(reports[0].1.get(ORD_STATUS), Some("8"));
+        assert_eq!(reports[0].1.get(TEXT), Some("Account not allowed"));
+    }
+
+    #[test]
+    fn test_new_order_single_fill() {
+        let mut gateway = gateway();
+        let sell = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "S1")
+            .with(ACCOUNT, "C2")
+            .with(SYMBOL, "A")
+            .with(SIDE, 2)
+            .with(ORDER_QTY, 5)
+            .with(PRICE, 10);
+        let buy = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "B1")
+            .with(ACCOUNT, "C1")
+            .with(SYMBOL, "A")
+            .with(SIDE, 1)
+            .with(ORDER_QTY, 8)
+            .with(PRICE, 12);
+        gateway.new_order_single("SELLER", &sell);
+        let reports = gateway.new_order_single("BUYER", &buy);
+
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[1].0, "BUYER");
+        assert_eq!(reports[1].1.get(LEAVES_QTY), Some("3"));
+        assert_eq!(reports[2].0, "SELLER");
+        assert_eq!(reports[2].1.get(ORD_STATUS), Some("2"));
+        assert_eq!(reports[2].1.get(LAST_PX), Some("10"));
+    }
+
+    #[test]
+    fn test_rejected_replace_keeps_order() {
+        let mut gateway = gateway();
+        gateway.engine.risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_order_volume: Some(10),
+                ..Limits::default()
+            },
+            ..RiskConfig::default()
+        });
+        let sell = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "S1")
+            .with(ACCOUNT, "C2")
+            .with(SYMBOL, "A")
+            .with(SIDE, 2)
+            .with(ORDER_QTY, 5)
+            .with(PRICE, 10);
+        gateway.new_order_single("SELLER", &sell);
+        let replace = Message::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(ORIG_CL_ORD_ID, "S1")
+            .with(CL_ORD_ID, "S2")
+            .with(SYMBOL, "A")
+            .with(SIDE, 2)
+            .with(ORDER_QTY, 50)
+            .with(PRICE, 11);
+        let reports = gateway.order_cancel_replace_request("SELLER", &replace);
+
+        assert_eq!(reports[0].1.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(reports[0].1.get(CXL_REJ_REASON), Some("99"));
+        assert_eq!(gateway.engine.get_order(1).unwrap().order_price, 10);
+        // The gateway still knows the order by its old id.
+        let cancel = Message::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "S1")
+            .with(CL_ORD_ID, "S3")
+            .with(SYMBOL, "A")
+            .with(SIDE, 2);
+        let reports = gateway.order_cancel_request("SELLER", &cancel);
+        assert_eq!(reports[0].1.get(ORD_STATUS), Some("4"));
+        assert!(gateway.engine.get_order(1).is_none());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{clients::Clients, engine::Trade, errors::TradeMatchErrors, orders::NewOrder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+pub const ORDER_COLUMNS: [&str; 5] = ["client", "operation", "asset", "price", "volume"];
+
+pub const TRADE_COLUMNS: [&str; 7] = [
+    "buy_index",
+    "sell_index",
+    "buyer",
+    "seller",
+    "asset",
+    "price",
+    "volume",
+];
+
+// Layout of an input or output file: whitespace separated positional text,
+// CSV with a header row naming the columns, or one JSON object per line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    // Csv for files ending in .csv, Jsonl for .jsonl or .ndjson, text otherwise.
+    pub fn from_path(path: &str) -> Format {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("csv") => Format::Csv,
+            Some("jsonl") | Some("ndjson") => Format::Jsonl,
+            _ => Format::Text,
+        }
+    }
+
+    // The configured format wins over the extension.
+    pub fn resolve(path: &str, configured: Option<Format>) -> Format {
+        configured.unwrap_or_else(|| Format::from_path(path))
+    }
+}
+
+// One CSV row whose fields can be looked up by header name. Names are
+// matched ignoring case, so the columns may come in any order.
+pub struct Record<'a> {
+    headers: &'a csv::StringRecord,
+    record: &'a csv::StringRecord,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(headers: &'a csv::StringRecord, record: &'a csv::StringRecord) -> Record<'a> {
+        Record { headers, record }
+    }
+
+    pub fn get(&self, column: &str) -> Option<&'a str> {
+        let position = self
+            .headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(column))?;
+        self.record.get(position)
+    }
+
+    // Header name and value of every field.
+    pub fn columns(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.headers.iter().zip(self.record.iter())
+    }
+
+    pub fn fields(&self) -> Vec<&'a str> {
+        self.record.iter().collect()
+    }
+}
+
+// Writes final balances: the tab separated Result.txt layout, CSV with a
+// column per asset held by any client, or JSON Lines.
+pub fn write_clients(
+    clients: &Clients,
+    path: &str,
+    format: Format,
+) -> Result<(), TradeMatchErrors> {
+    match format {
+        Format::Text => {
+            let mut file = File::create(path).map_err(|source| write_error(path, source))?;
+            for client in clients.iter() {
+                write!(file, "{}", client).map_err(|source| write_error(path, source))?;
+            }
+        }
+        Format::Csv => {
+            let assets: BTreeSet<&str> = clients
+                .iter()
+                .flat_map(|client| client.asset_balances.iter())
+                .map(|asset| asset.symbol.as_str())
+                .collect();
+            let mut writer = csv_writer(path)?;
+            let mut header = vec!["client", "dollar_balance"];
+            header.extend(assets.iter().copied());
+            writer
+                .write_record(&header)
+                .map_err(|source| write_error(path, source.into()))?;
+            for client in clients.iter() {
+                let mut row = vec![client.name.clone(), client.dollar_balance.to_string()];
+                row.extend(assets.iter().map(|asset| {
+                    client
+                        .asset_balances
+                        .get(asset)
+                        .map(|asset| asset.balance)
+                        .unwrap_or_default()
+                        .to_string()
+                }));
+                writer
+                    .write_record(&row)
+                    .map_err(|source| write_error(path, source.into()))?;
+            }
+            writer.flush().map_err(|source| write_error(path, source))?;
+        }
+        Format::Jsonl => write_json_lines(clients.iter(), path)?,
+    }
+    Ok(())
+}
+
+pub fn write_trades(trades: &[Trade], path: &str, format: Format) -> Result<(), TradeMatchErrors> {
+    match format {
+        Format::Text => {
+            let mut file = File::create(path).map_err(|source| write_error(path, source))?;
+            for trade in trades {
+                write!(file, "{}", trade).map_err(|source| write_error(path, source))?;
+            }
+        }
+        Format::Csv => {
+            let mut writer = csv_writer(path)?;
+            writer
+                .write_record(TRADE_COLUMNS)
+                .map_err(|source| write_error(path, source.into()))?;
+            for trade in trades {
+                writer
+                    .write_record([
+                        trade.buy_index.to_string(),
+                        trade.sell_index.to_string(),
+                        trade.buyer.to_string(),
+                        trade.seller.to_string(),
+                        trade.asset.to_string(),
+                        trade.price.to_string(),
+                        trade.volume.to_string(),
+                    ])
+                    .map_err(|source| write_error(path, source.into()))?;
+            }
+            writer.flush().map_err(|source| write_error(path, source))?;
+        }
+        Format::Jsonl => write_json_lines(trades, path)?,
+    }
+    Ok(())
+}
+
+// Writes orders in the layout they are read back from, line by line.
+pub fn write_orders(
+    orders: &[NewOrder],
+    path: &str,
+    format: Format,
+) -> Result<(), TradeMatchErrors> {
+    match format {
+        Format::Text => {
+            let mut file =
+                BufWriter::new(File::create(path).map_err(|source| write_error(path, source))?);
+            for order in orders {
+                write!(file, "{}", order).map_err(|source| write_error(path, source))?;
+            }
+            file.flush().map_err(|source| write_error(path, source))?;
+        }
+        Format::Csv => {
+            let mut writer = csv_writer(path)?;
+            writer
+                .write_record(ORDER_COLUMNS)
+                .map_err(|source| write_error(path, source.into()))?;
+            for order in orders {
+                writer
+                    .write_record([
+                        order.client_name.clone(),
+                        order.operation.to_string(),
+                        order.asset.clone(),
+                        order.order_price.to_string(),
+                        order.value.to_string(),
+                    ])
+                    .map_err(|source| write_error(path, source.into()))?;
+            }
+            writer.flush().map_err(|source| write_error(path, source))?;
+        }
+        Format::Jsonl => write_json_lines(orders, path)?,
+    }
+    Ok(())
+}
+
+// One JSON object per line.
+fn write_json_lines<'a, T, I>(items: I, path: &str) -> Result<(), TradeMatchErrors>
+where
+    T: Serialize + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    let mut file = BufWriter::new(File::create(path).map_err(|source| write_error(path, source))?);
+    for item in items {
+        serde_json::to_writer(&mut file, item)
+            .map_err(|source| write_error(path, source.into()))?;
+        writeln!(file).map_err(|source| write_error(path, source))?;
+    }
+    file.flush().map_err(|source| write_error(path, source))
+}
+
+pub(crate) fn csv_reader<R: Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+fn csv_writer(path: &str) -> Result<csv::Writer<File>, TradeMatchErrors> {
+    csv::Writer::from_path(path).map_err(|source| write_error(path, source.into()))
+}
+
+pub(crate) fn csv_error(path: &str, source: csv::Error) -> TradeMatchErrors {
+    TradeMatchErrors::CsvError {
+        path: path.to_string(),
+        source,
+    }
+}
+
+fn write_error(path: &str, source: std::io::Error) -> TradeMatchErrors {
+    TradeMatchErrors::WriteError {
+        path: path.to_string(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        orders::{NewOrders, OrderType},
+        read_file,
+    };
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(Format::from_path("./Orders.csv"), Format::Csv);
+        assert_eq!(Format::from_path("./Orders.CSV"), Format::Csv);
+        assert_eq!(Format::from_path("./Orders.txt"), Format::Text);
+        assert_eq!(Format::from_path("./Orders.jsonl"), Format::Jsonl);
+        assert_eq!(
+            Format::resolve("./Orders.txt", Some(Format::Csv)),
+            Format::Csv
+        );
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let dir = std::env::temp_dir();
+        let orders = dir.join("trade_match_orders.csv");
+        std::fs::write(
+            &orders,
+            "Volume,Asset,Client,Price,Operation\n4,A,C1,10,buy\n\"2\",B,\"C2\",12,s\n",
+        )
+        .unwrap();
+        let orders: NewOrders = read_file(orders.to_str().unwrap().to_string()).unwrap();
+        let order = &orders.order[&2];
+        assert_eq!(order.client_name, "C2");
+        assert_eq!(order.asset, "B");
+        assert_eq!(order.order_price, 12);
+        assert_eq!(order.value, 2);
+
+        let clients = dir.join("trade_match_clients.csv");
+        std::fs::write(&clients, "client,B,dollar_balance,A\nC1,5,100,7\n").unwrap();
+        let clients: Clients = read_file(clients.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(clients.get("C1").unwrap().dollar_balance, 100);
+
+        let result = dir.join("trade_match_result.csv");
+        let result = result.to_str().unwrap();
+        write_clients(&clients, result, Format::Csv).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(result).unwrap(),
+            "client,dollar_balance,A,B\nC1,100,7,5\n"
+        );
+    }
+
+    #[test]
+    fn test_json_lines_round_trip() {
+        let dir = std::env::temp_dir();
+        let orders = dir.join("trade_match_orders.jsonl");
+        std::fs::write(
+            &orders,
+            "{\"client\":\"C1\",\"operation\":\"sell\",\"asset\":\"A\",\"price\":10,\"volume\":4}\n[\"C2\",\"b\",\"A\",12,1]\n",
+        )
+        .unwrap();
+        let orders: NewOrders = read_file(orders.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(orders.order[&1].operation, OrderType::Sell);
+        assert_eq!(orders.order[&2].order_price, 12);
+
+        let clients = dir.join("trade_match_clients.jsonl");
+        std::fs::write(
+            &clients,
+            "{\"client\":\"C1\",\"dollar_balance\":100,\"assets\":[{\"symbol\":\"A\",\"balance\":7}]}\n",
+        )
+        .unwrap();
+        let clients: Clients = read_file(clients.to_str().unwrap().to_string()).unwrap();
+        let client = clients.get("C1").unwrap();
+        assert_eq!(client.asset_balances.get("A").unwrap().balance, 7);
+        assert_eq!(client.pnl.positions["A"].quantity(), 7);
+
+        let result = dir.join("trade_match_result.jsonl");
+        let result = result.to_str().unwrap();
+        write_clients(&clients, result, Format::Jsonl).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(result).unwrap(),
+            "{\"client\":\"C1\",\"dollar_balance\":100,\"assets\":[{\"symbol\":\"A\",\"balance\":7}]}\n"
+        );
+    }
+}
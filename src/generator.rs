@@ -0,0 +1,418 @@
+use crate::{
+    binary,
+    clients::{Asset, Assets, Client, Clients},
+    engine::Engine,
+    errors::{GeneratorErrors, TradeMatchErrors},
+    formats::{write_clients, write_orders, Format},
+    orders::{NewOrder, OrderType},
+    pnl::{CostMethod, Pnl},
+    symbols::Symbol,
+    DataParser,
+};
+use clap::{Parser, ValueEnum};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+// Synthetic clients and order flow for stress tests. Every asset's price
+// follows a random walk and orders are placed around it. The flow is run
+// through an engine as it is generated, so cancels only ever name orders
+// that are still resting. The same settings always give the same files.
+
+// Settings of the generator, also the flags of the generate command.
+#[derive(Debug, Clone, PartialEq, Parser)]
+pub struct Synthetic {
+    /// Seed of the random numbers. The same seed gives the same files.
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+
+    /// Number of clients, named C1, C2 and so on.
+    #[arg(long, default_value_t = 10)]
+    pub num_clients: usize,
+
+    /// Number of assets, named A, B and so on. Text clients files hold four.
+    #[arg(long, default_value_t = 4)]
+    pub num_assets: usize,
+
+    /// Number of orders and cancels.
+    #[arg(long, default_value_t = 1000)]
+    pub messages: usize,
+
+    /// Price every asset starts at.
+    #[arg(long, default_value_t = 100)]
+    pub start_price: u32,
+
+    /// Standard deviation of a price step, taken on every order of the asset.
+    #[arg(long, default_value_t = 0.5)]
+    pub volatility: f64,
+
+    /// Standard deviation of an order's limit around the current price.
+    #[arg(long, default_value_t = 2.0)]
+    pub spread: f64,
+
+    /// Distribution of order volumes between the two bounds.
+    #[arg(long, value_enum, default_value_t = Sizes::LogUniform)]
+    pub sizes: Sizes,
+
+    #[arg(long, default_value_t = 1)]
+    pub min_volume: u32,
+
+    #[arg(long, default_value_t = 100)]
+    pub max_volume: u32,
+
+    /// Share of orders that buy.
+    #[arg(long, default_value_t = 0.5)]
+    pub buy_ratio: f64,
+
+    /// Share of messages that cancel a resting order.
+    #[arg(long, default_value_t = 0.0)]
+    pub cancel_rate: f64,
+
+    /// Largest dollar balance a client starts with.
+    #[arg(long, default_value_t = 100_000)]
+    pub max_dollars: u32,
+
+    /// Largest balance of each asset a client starts with.
+    #[arg(long, default_value_t = 1_000)]
+    pub max_units: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Sizes {
+    /// Every volume equally likely.
+    Uniform,
+    /// Small orders much more often than large ones.
+    LogUniform,
+}
+
+// A generated message: a new order, or the cancel of a resting one by index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Order(NewOrder),
+    Cancel(usize),
+}
+
+impl Default for Synthetic {
+    // The defaults of the command line flags.
+    fn default() -> Synthetic {
+        Synthetic::parse_from(["generate"])
+    }
+}
+
+impl Synthetic {
+    fn check(&self) -> Result<(), GeneratorErrors> {
+        use GeneratorErrors::SettingError;
+        let share = 0.0..=1.0;
+        if self.num_clients == 0 {
+            return Err(SettingError("num-clients"));
+        }
+        if !(1..=26).contains(&self.num_assets) {
+            return Err(SettingError("num-assets"));
+        }
+        if self.start_price == 0 {
+            return Err(SettingError("start-price"));
+        }
+        if !(self.volatility >= 0.0 && self.volatility.is_finite()) {
+            return Err(SettingError("volatility"));
+        }
+        if !(self.spread >= 0.0 && self.spread.is_finite()) {
+            return Err(SettingError("spread"));
+        }
+        if self.min_volume == 0 || self.min_volume > self.max_volume {
+            return Err(SettingError("min-volume"));
+        }
+        if !share.contains(&self.buy_ratio) {
+            return Err(SettingError("buy-ratio"));
+        }
+        if !share.contains(&self.cancel_rate) {
+            return Err(SettingError("cancel-rate"));
+        }
+        Ok(())
+    }
+
+    fn assets(&self) -> Vec<String> {
+        (b'A'..)
+            .take(self.num_assets)
+            .map(|letter| char::from(letter).to_string())
+            .collect()
+    }
+}
+
+// SplitMix64. Small and fully defined here, so a seed keeps giving the same
+// files whatever else changes in the dependencies.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [0, n).
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+
+    // Standard normal, by Box-Muller.
+    fn normal(&mut self) -> f64 {
+        let radius = (-2.0 * (1.0 - self.unit()).ln()).sqrt();
+        radius * (2.0 * std::f64::consts::PI * self.unit()).cos()
+    }
+}
+
+// Clients with random balances and the order flow they send.
+pub fn generate(settings: &Synthetic) -> Result<(Clients, Vec<Message>), TradeMatchErrors> {
+    settings.check()?;
+    let mut rng = Rng(settings.seed);
+    let assets = settings.assets();
+
+    let mut clients = Clients::new();
+    for index in 1..=settings.num_clients {
+        let dollar_balance = rng.below(settings.max_dollars as usize + 1) as u32;
+        let mut asset_balances = Assets::new();
+        for symbol in &assets {
+            let balance = rng.below(settings.max_units as usize + 1) as u32;
+            asset_balances.insert(Asset {
+                symbol: symbol.clone(),
+                balance,
+            });
+        }
+        let pnl = Pnl::open(&asset_balances, CostMethod::default());
+        clients.insert(
+            index,
+            Client {
+                index,
+                id: Symbol::default(),
+                name: format!("C{}", index),
+                dollar_balance,
+                asset_balances,
+                pnl,
+            },
+        );
+    }
+
+    let mut engine = Engine::new(clients.clone());
+    let mut prices = vec![f64::from(settings.start_price); assets.len()];
+    // Every order sent so far that may still rest. Filled and cancelled ones
+    // are dropped as they are drawn.
+    let mut sent: Vec<usize> = Vec::new();
+    let mut orders = 0;
+    let mut messages = Vec::with_capacity(settings.messages);
+    while messages.len() < settings.messages {
+        if rng.chance(settings.cancel_rate) {
+            if let Some(index) = resting(&engine, &mut sent, &mut rng) {
+                engine.cancel(index)?;
+                messages.push(Message::Cancel(index));
+                continue;
+            }
+        }
+
+        let asset = rng.below(assets.len());
+        let price = &mut prices[asset];
+        *price = (*price + rng.normal() * settings.volatility).max(1.0);
+        let order_price = (*price + rng.normal() * settings.spread).round().max(1.0) as u32;
+        let operation = match rng.chance(settings.buy_ratio) {
+            true => OrderType::Buy,
+            false => OrderType::Sell,
+        };
+        orders += 1;
+        let order = NewOrder {
+            index: orders,
+            client_name: format!("C{}", rng.below(settings.num_clients) + 1),
+            operation,
+            asset: assets[asset].clone(),
+            order_price,
+            value: volume(settings, &mut rng),
+        };
+        sent.push(order.index);
+        engine.enter(&order)?;
+        messages.push(Message::Order(order));
+    }
+    Ok((clients, messages))
+}
+
+// Draws a resting order, dropping the drawn ones that no longer rest.
+fn resting(engine: &Engine, sent: &mut Vec<usize>, rng: &mut Rng) -> Option<usize> {
+    while !sent.is_empty() {
+        let position = rng.below(sent.len());
+        let index = sent.swap_remove(position);
+        if engine.buy_orders.order.contains_key(&index)
+            || engine.sell_orders.order.contains_key(&index)
+        {
+            return Some(index);
+        }
+    }
+    None
+}
+
+fn volume(settings: &Synthetic, rng: &mut Rng) -> u32 {
+    let (min, max) = (settings.min_volume, settings.max_volume);
+    match settings.sizes {
+        Sizes::Uniform => min + rng.below((max - min) as usize + 1) as u32,
+        Sizes::LogUniform => {
+            let (low, high) = (f64::from(min).ln(), (f64::from(max) + 1.0).ln());
+            ((low + rng.unit() * (high - low)).exp() as u32).clamp(min, max)
+        }
+    }
+}
+
+// Writes the clients file and the order flow, each in the format its name
+// asks for. Cancels can only be written as binary messages and text clients
+// files only hold the assets A to D, so both are checked before writing.
+pub fn write(
+    clients: &Clients,
+    messages: &[Message],
+    client_file: &str,
+    order_file: &str,
+) -> Result<(), TradeMatchErrors> {
+    let format = Format::from_path(client_file);
+    if format == Format::Text {
+        let assets = clients
+            .client
+            .values()
+            .map(|client| client.asset_balances.asset.len())
+            .max()
+            .unwrap_or_default();
+        if assets > 4 {
+            return Err(GeneratorErrors::TextAssetsError(assets).into());
+        }
+    }
+    let binary = binary::is_binary(order_file);
+    let orders: Vec<NewOrder> = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Order(order) => Some(order.clone()),
+            Message::Cancel(_) => None,
+        })
+        .collect();
+    if !binary && orders.len() < messages.len() {
+        return Err(GeneratorErrors::CancelFormatError.into());
+    }
+
+    write_clients(clients, client_file, format)?;
+    if !binary {
+        return write_orders(&orders, order_file, Format::from_path(order_file));
+    }
+    let write_error = |source| TradeMatchErrors::WriteError {
+        path: order_file.to_string(),
+        source,
+    };
+    let mut file = BufWriter::new(File::create(order_file).map_err(write_error)?);
+    file.write_all(binary::MAGIC).map_err(write_error)?;
+    for message in messages {
+        let bytes = match message {
+            Message::Order(order) => binary::encode_order(order)?,
+            Message::Cancel(index) => binary::encode_cancel(*index),
+        };
+        file.write_all(&bytes).map_err(write_error)?;
+    }
+    file.flush().map_err(write_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{orders::NewOrders, read_file};
+
+    fn settings() -> Synthetic {
+        Synthetic {
+            messages: 500,
+            cancel_rate: 0.2,
+            ..Synthetic::default()
+        }
+    }
+
+    #[test]
+    fn test_seeded() {
+        let (clients, messages) = generate(&settings()).unwrap();
+        let (again, repeated) = generate(&settings()).unwrap();
+        assert_eq!(format!("{:?}", clients), format!("{:?}", again));
+        assert_eq!(messages, repeated);
+
+        let other = Synthetic {
+            seed: 2,
+            ..settings()
+        };
+        assert_ne!(generate(&other).unwrap().1, messages);
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = Synthetic {
+            num_clients: 3,
+            num_assets: 6,
+            min_volume: 5,
+            max_volume: 9,
+            buy_ratio: 1.0,
+            ..settings()
+        };
+        let (clients, messages) = generate(&settings).unwrap();
+        assert_eq!(clients.client.len(), 3);
+        assert_eq!(messages.len(), 500);
+        for message in &messages {
+            if let Message::Order(order) = message {
+                assert_eq!(order.operation, OrderType::Buy);
+                assert!(("A".."G").contains(&order.asset.as_str()));
+                assert!((5..=9).contains(&order.value));
+                assert!(order.order_price > 0);
+            }
+        }
+
+        let settings = Synthetic {
+            num_assets: 27,
+            ..Synthetic::default()
+        };
+        assert!(generate(&settings).is_err());
+    }
+
+    // Every cancel names a resting order, so the flow replays without errors.
+    #[test]
+    fn test_replay() {
+        let (clients, messages) = generate(&settings()).unwrap();
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, Message::Cancel(_))));
+
+        let dir = std::env::temp_dir();
+        let client_file = dir.join("trade_match_generated_clients.txt");
+        let client_file = client_file.to_str().unwrap();
+        let order_file = dir.join("trade_match_generated_orders.bin");
+        let order_file = order_file.to_str().unwrap();
+        write(&clients, &messages, client_file, order_file).unwrap();
+
+        let mut engine = Engine::new(read_file(client_file.to_string()).unwrap());
+        let bytes = std::fs::read(order_file).unwrap();
+        let mut count = 0;
+        for message in binary::messages(&bytes).unwrap() {
+            engine.apply(&message).unwrap();
+            count += 1;
+        }
+        assert_eq!(count, messages.len());
+
+        let text = dir.join("trade_match_generated_orders.txt");
+        let text = text.to_str().unwrap();
+        assert!(matches!(
+            write(&clients, &messages, client_file, text),
+            Err(TradeMatchErrors::GeneratorError(
+                GeneratorErrors::CancelFormatError
+            ))
+        ));
+        let (clients, messages) = generate(&Synthetic::default()).unwrap();
+        write(&clients, &messages, client_file, text).unwrap();
+        let orders: NewOrders = read_file(text.to_string()).unwrap();
+        assert_eq!(orders.order.len(), messages.len());
+    }
+}
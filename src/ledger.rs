@@ -0,0 +1,529 @@
+use crate::{
+    clients::{Client, Clients},
+    engine::Trade,
+    errors::LedgerErrors,
+    symbols::{Registry, Symbol},
+};
+use std::collections::BTreeMap;
+
+pub const JOURNAL_HEADER: &str = "entry\tkind\taccount\tasset\tamount";
+
+// Asset name the ledger keeps dollar balances under.
+pub const DOLLARS: &str = "$";
+
+// Double-entry ledger of every balance movement. Each journal entry is a set
+// of lines that add up to zero in every asset: what one account is debited,
+// others are credited. Clients' balances are the sums of their accounts'
+// lines, so they can be rebuilt from the journal alone and checked against
+// the balances the engine keeps. A debit is positive and adds to what an
+// account holds. Only the running sums are kept unless the journal is asked
+// for, so a long run does not hold every entry it posted.
+
+// Who an account belongs to. Everything a client gains that does not come
+// from another client comes from one of the ledger's own holders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Holder {
+    // A client, by the id the clients' registry gave its name.
+    Client(Symbol),
+    // Balances clients held when the ledger was opened.
+    Opening,
+    // Dollars and units paid in and out of the venue.
+    External,
+    Fees,
+    // Loans of margin accounts, interest charged on them included.
+    Lender,
+    // Interest the lender has charged.
+    Interest,
+}
+
+// What an amount is counted in. Assets go by their registry id, so lines
+// hold no names and are only named when the journal is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Unit {
+    Dollars,
+    Asset(Symbol),
+}
+
+impl Unit {
+    // The unit an asset name stands for, DOLLARS included.
+    pub fn of(registry: &Registry, asset: &str) -> Option<Unit> {
+        match asset {
+            DOLLARS => Some(Unit::Dollars),
+            asset => registry.assets.get(asset).map(Unit::Asset),
+        }
+    }
+
+    pub fn name<'a>(&self, registry: &'a Registry) -> &'a str {
+        match self {
+            Unit::Dollars => DOLLARS,
+            Unit::Asset(id) => registry.assets.name(*id).map_or("?", |name| name),
+        }
+    }
+}
+
+impl Holder {
+    pub fn name<'a>(&self, registry: &'a Registry) -> &'a str {
+        match self {
+            Holder::Client(id) => registry.clients.name(*id).map_or("?", |name| name),
+            Holder::Opening => "[opening]",
+            Holder::External => "[external]",
+            Holder::Fees => "[fees]",
+            Holder::Lender => "[lender]",
+            Holder::Interest => "[interest]",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Opening,
+    Trade,
+    Fee,
+    Deposit,
+    Withdrawal,
+    Loan,
+    Repayment,
+    Interest,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            EntryKind::Opening => "opening",
+            EntryKind::Trade => "trade",
+            EntryKind::Fee => "fee",
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+            EntryKind::Loan => "loan",
+            EntryKind::Repayment => "repayment",
+            EntryKind::Interest => "interest",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    pub holder: Holder,
+    pub unit: Unit,
+    pub amount: i64,
+}
+
+impl Line {
+    pub fn new(holder: Holder, unit: Unit, amount: i64) -> Line {
+        Line {
+            holder,
+            unit,
+            amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: usize,
+    pub kind: EntryKind,
+    pub lines: Vec<Line>,
+}
+
+impl Entry {
+    // The entry with its accounts and assets named, for the journal file.
+    pub fn named<'a>(&'a self, registry: &'a Registry) -> NamedEntry<'a> {
+        NamedEntry {
+            entry: self,
+            registry,
+        }
+    }
+}
+
+pub struct NamedEntry<'a> {
+    entry: &'a Entry,
+    registry: &'a Registry,
+}
+
+// The journal lines of an entry, one per line of the file.
+impl std::fmt::Display for NamedEntry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.entry.lines {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}",
+                self.entry.id,
+                self.entry.kind,
+                line.holder.name(self.registry),
+                line.unit.name(self.registry),
+                line.amount
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    // Every entry posted, when the journal is kept.
+    pub entries: Vec<Entry>,
+    journal: bool,
+    posted: usize,
+    // Sum of the lines of every account.
+    balances: BTreeMap<(Holder, Unit), i64>,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    // A ledger that keeps its entries as well as the balances.
+    pub fn with_journal() -> Ledger {
+        Ledger {
+            journal: true,
+            ..Ledger::default()
+        }
+    }
+
+    // A ledger whose first entries give every client the balances it holds.
+    pub fn open(clients: &Clients, journal: bool) -> Ledger {
+        let mut ledger = match journal {
+            true => Ledger::with_journal(),
+            false => Ledger::new(),
+        };
+        for client in clients.iter() {
+            let lines = holdings(client)
+                .flat_map(|(unit, amount)| {
+                    [
+                        Line::new(Holder::Client(client.id), unit, amount),
+                        Line::new(Holder::Opening, unit, -amount),
+                    ]
+                })
+                .collect();
+            ledger.push(EntryKind::Opening, lines);
+        }
+        ledger
+    }
+
+    // Records an entry, unless its lines leave something over in any unit.
+    // The registry names the unit in the error.
+    pub fn post(
+        &mut self,
+        kind: EntryKind,
+        lines: Vec<Line>,
+        registry: &Registry,
+    ) -> Result<(), LedgerErrors> {
+        let mut totals: BTreeMap<Unit, i64> = BTreeMap::new();
+        for line in &lines {
+            *totals.entry(line.unit).or_default() += line.amount;
+        }
+        if let Some((unit, total)) = totals.into_iter().find(|(_, total)| *total != 0) {
+            return Err(LedgerErrors::UnbalancedError(
+                unit.name(registry).to_string(),
+                total,
+            ));
+        }
+        self.push(kind, lines);
+        Ok(())
+    }
+
+    // Both legs of a fill: the units go to the buyer, the dollars to the
+    // seller. The trade's names are looked up in the registry it was made with.
+    pub fn settle(&mut self, trade: &Trade, registry: &Registry) {
+        let (Some(buyer), Some(seller), Some(asset)) = (
+            registry.clients.get(&trade.buyer),
+            registry.clients.get(&trade.seller),
+            registry.assets.get(&trade.asset),
+        ) else {
+            return;
+        };
+        let units = i64::from(trade.volume);
+        let dollars = i64::from(trade.price) * units;
+        let (buyer, seller, asset) = (
+            Holder::Client(buyer),
+            Holder::Client(seller),
+            Unit::Asset(asset),
+        );
+        self.push(
+            EntryKind::Trade,
+            vec![
+                Line::new(buyer, asset, units),
+                Line::new(seller, asset, -units),
+                Line::new(seller, Unit::Dollars, dollars),
+                Line::new(buyer, Unit::Dollars, -dollars),
+            ],
+        );
+    }
+
+    // Moves an amount of one unit from the other holder to the client, or
+    // back when it is negative.
+    pub fn transfer(
+        &mut self,
+        kind: EntryKind,
+        client: Symbol,
+        other: Holder,
+        unit: Unit,
+        amount: i64,
+    ) {
+        self.push(
+            kind,
+            vec![
+                Line::new(Holder::Client(client), unit, amount),
+                Line::new(other, unit, -amount),
+            ],
+        );
+    }
+
+    // Books whatever changed between two states of a client's balances as
+    // moving between it and the other holder.
+    pub fn record_change(
+        &mut self,
+        kind: EntryKind,
+        other: Holder,
+        before: &Client,
+        after: &Client,
+    ) {
+        let before: BTreeMap<Unit, i64> = holdings(before).collect();
+        let mut lines = Vec::new();
+        for (unit, amount) in holdings(after) {
+            let change = amount - before.get(&unit).copied().unwrap_or_default();
+            if change != 0 {
+                lines.push(Line::new(Holder::Client(after.id), unit, change));
+                lines.push(Line::new(other, unit, -change));
+            }
+        }
+        self.push(kind, lines);
+    }
+
+    pub fn balance(&self, holder: Holder, unit: Unit) -> i64 {
+        self.balances
+            .get(&(holder, unit))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // A client's balances as the journal has them.
+    pub fn balances(&self, client: Symbol) -> BTreeMap<Unit, i64> {
+        self.balances
+            .range((Holder::Client(client), Unit::Dollars)..)
+            .take_while(|((holder, _), _)| *holder == Holder::Client(client))
+            .map(|((_, unit), amount)| (*unit, *amount))
+            .collect()
+    }
+
+    // Checks every client's balances against the journal.
+    pub fn reconcile(&self, clients: &Clients) -> Result<(), LedgerErrors> {
+        let registry = &clients.registry;
+        for client in clients.iter() {
+            let mut journal = self.balances(client.id);
+            for (unit, amount) in holdings(client) {
+                let booked = journal.remove(&unit).unwrap_or_default();
+                if booked != amount {
+                    return Err(reconcile_error(registry, client, unit, booked, amount));
+                }
+            }
+            // Anything left is held in the journal but not in the balances.
+            if let Some((unit, booked)) = journal.into_iter().find(|(_, amount)| *amount != 0) {
+                return Err(reconcile_error(registry, client, unit, booked, 0));
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, kind: EntryKind, lines: Vec<Line>) {
+        let lines: Vec<Line> = lines.into_iter().filter(|line| line.amount != 0).collect();
+        if lines.is_empty() {
+            return;
+        }
+        for line in &lines {
+            *self.balances.entry((line.holder, line.unit)).or_default() += line.amount;
+        }
+        self.posted += 1;
+        if self.journal {
+            self.entries.push(Entry {
+                id: self.posted,
+                kind,
+                lines,
+            });
+        }
+    }
+}
+
+// A client's dollars and units. Asset ids are the registry's once the client
+// is in a Clients.
+fn holdings(client: &Client) -> impl Iterator<Item = (Unit, i64)> + '_ {
+    [(Unit::Dollars, i64::from(client.dollar_balance))]
+        .into_iter()
+        .chain(
+            client
+                .asset_balances
+                .asset
+                .iter()
+                .map(|(id, asset)| (Unit::Asset(*id), i64::from(asset.balance))),
+        )
+}
+
+fn reconcile_error(
+    registry: &Registry,
+    client: &Client,
+    unit: Unit,
+    booked: i64,
+    held: i64,
+) -> LedgerErrors {
+    LedgerErrors::ReconcileError(
+        client.name.clone(),
+        unit.name(registry).to_string(),
+        booked,
+        held,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::Engine,
+        errors::{GeneralErrors, TradeMatchErrors},
+        orders::{NewOrder, OrderType},
+        DataParser,
+    };
+    use std::str::FromStr;
+
+    fn engine() -> Engine {
+        let mut clients = Clients::new();
+        for (index, line) in ["C1\t100\t10\t0\t0\t0", "C2\t100\t0\t0\t0\t0"]
+            .into_iter()
+            .enumerate()
+        {
+            clients.insert(index + 1, Client::from_str(line).unwrap());
+        }
+        Engine::new(clients)
+    }
+
+    fn order(index: usize, client: &str, operation: OrderType, price: u32, value: u32) -> NewOrder {
+        NewOrder {
+            index,
+            client_name: client.to_string(),
+            operation,
+            asset: "A".to_string(),
+            order_price: price,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_post() {
+        let mut registry = Registry::new();
+        let c1 = Holder::Client(registry.clients.intern("C1"));
+        let a = Unit::Asset(registry.assets.intern("A"));
+        let mut ledger = Ledger::with_journal();
+        assert_eq!(
+            ledger.post(
+                EntryKind::Deposit,
+                vec![
+                    Line::new(c1, Unit::Dollars, 10),
+                    Line::new(Holder::External, Unit::Dollars, -10),
+                    Line::new(c1, a, 1),
+                ],
+                &registry,
+            ),
+            Err(LedgerErrors::UnbalancedError("A".to_string(), 1))
+        );
+        assert!(ledger.entries.is_empty());
+        ledger
+            .post(
+                EntryKind::Deposit,
+                vec![
+                    Line::new(c1, Unit::Dollars, 10),
+                    Line::new(Holder::External, Unit::Dollars, -10),
+                ],
+                &registry,
+            )
+            .unwrap();
+        assert_eq!(ledger.balance(Holder::External, Unit::Dollars), -10);
+        assert_eq!(
+            ledger.entries[0].named(&registry).to_string(),
+            "1\tdeposit\tC1\t$\t10\n1\tdeposit\t[external]\t$\t-10\n"
+        );
+    }
+
+    #[test]
+    fn test_movements() {
+        let mut engine = engine();
+        engine.keep_journal();
+        engine.enter(&order(1, "C2", OrderType::Buy, 6, 5)).unwrap();
+        engine
+            .enter(&order(2, "C1", OrderType::Sell, 5, 4))
+            .unwrap();
+        engine.deposit("C2", DOLLARS, 50).unwrap();
+        engine.withdraw("C2", "A", 3).unwrap();
+        engine.charge_fee("C1", 2).unwrap();
+        assert!(matches!(
+            engine.withdraw("C1", DOLLARS, 1000),
+            Err(TradeMatchErrors::EngineError(
+                GeneralErrors::NotEnaughDollars
+            ))
+        ));
+
+        let kinds: Vec<EntryKind> = engine
+            .ledger
+            .entries
+            .iter()
+            .map(|entry| entry.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                EntryKind::Opening,
+                EntryKind::Opening,
+                EntryKind::Trade,
+                EntryKind::Deposit,
+                EntryKind::Withdrawal,
+                EntryKind::Fee
+            ]
+        );
+        let c2 = engine.clients.id("C2").unwrap();
+        let a = Unit::of(&engine.clients.registry, "A").unwrap();
+        let balances = engine.ledger.balances(c2);
+        assert_eq!(balances[&Unit::Dollars], 126);
+        assert_eq!(balances[&a], 1);
+        assert_eq!(engine.ledger.balance(Holder::Fees, Unit::Dollars), 2);
+        assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+
+        // Balances changed behind the ledger's back no longer reconcile.
+        engine.clients.get_mut("C1").unwrap().dollar_balance += 1;
+        assert_eq!(
+            engine.ledger.reconcile(&engine.clients),
+            Err(LedgerErrors::ReconcileError(
+                "C1".to_string(),
+                DOLLARS.to_string(),
+                122,
+                123
+            ))
+        );
+    }
+
+    #[test]
+    fn test_movements_change_positions() {
+        let mut engine = engine();
+        engine.enter(&order(1, "C2", OrderType::Buy, 6, 5)).unwrap();
+        engine
+            .enter(&order(2, "C1", OrderType::Sell, 5, 4))
+            .unwrap();
+        // C2 bought 4 at 6. Units paid in are priced like opening inventory,
+        // at the first trade.
+        engine.deposit("C2", "A", 6).unwrap();
+        let c2 = engine.clients.get("C2").unwrap();
+        let position = &c2.pnl.positions["A"];
+        assert_eq!(position.quantity(), 10);
+        assert_eq!(position.cost_basis(6), 60.0);
+        assert_eq!(c2.unrealized_pnl(&engine.quotes), 0.0);
+
+        // Units paid out leave at their cost and realize nothing.
+        engine.withdraw("C2", "A", 7).unwrap();
+        let c2 = engine.clients.get("C2").unwrap();
+        assert_eq!(c2.pnl.positions["A"].quantity(), 3);
+        assert_eq!(c2.realized_pnl(), 0.0);
+        assert_eq!(c2.unrealized_pnl(&engine.quotes), 0.0);
+        assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+        // Without the journal only the balances were kept.
+        assert!(engine.ledger.entries.is_empty());
+    }
+}
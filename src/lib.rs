@@ -1,13 +1,33 @@
+use errors::{LineError, TradeMatchErrors};
+use formats::{Format, Record};
+use serde::Deserialize;
 use std::{
-    error, fs,
+    fs,
     io::{self, BufRead},
-    str::FromStr,
 };
 
+pub mod backtest;
+pub mod binary;
+pub mod book;
+pub mod candles;
+pub mod cli;
 pub mod clients;
 pub mod config;
+pub mod engine;
 pub mod errors;
+pub mod fix;
+pub mod formats;
+pub mod generator;
+pub mod ledger;
+pub mod margin;
 pub mod orders;
+pub mod parallel;
+pub mod pnl;
+pub mod quotes;
+pub mod reports;
+pub mod risk;
+pub mod simulator;
+pub mod symbols;
 
 pub type Volume = u32;
 pub type Price = u32;
@@ -15,39 +35,191 @@ pub type Price = u32;
 // Trait for parsing input data
 pub trait DataParser {
     type Item;
-    type Err;
+    type Err: From<serde_json::Error>;
 
     fn new() -> Self;
     fn insert(&mut self, index: usize, data: Self::Item);
     fn parse(line: &str) -> Result<Self::Item, Self::Err>;
+    // CSV rows are matched by column name where the type knows its columns,
+    // otherwise their fields are parsed like a text line.
+    fn parse_record(record: &Record) -> Result<Self::Item, Self::Err> {
+        Self::parse(&record.fields().join("\t"))
+    }
+    // JSON Lines likewise, with a JSON array standing in for a line's fields.
+    fn parse_json(line: &str) -> Result<Self::Item, Self::Err> {
+        let fields: Vec<serde_json::Value> = serde_json::from_str(line)?;
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|field| match field {
+                serde_json::Value::String(field) => field,
+                field => field.to_string(),
+            })
+            .collect();
+        Self::parse(&fields.join("\t"))
+    }
     fn remove(&mut self, orders: Vec<Self::Item>);
 }
 
-pub fn read_file<T>(file_path: String) -> Result<T, &'static dyn error::Error>
+// Parses every line of the file, stopping at the first one that fails.
+// Files ending in .csv are read as CSV with a header row, .jsonl as JSON Lines.
+pub fn read_file<T>(file_path: String) -> Result<T, TradeMatchErrors>
 where
     T: DataParser,
-    T::Item: FromStr,
-    T::Err: std::fmt::Debug,
+    T::Err: std::fmt::Display,
 {
-    let file = fs::File::open(file_path).unwrap();
-    let reader = io::BufReader::new(file);
     let mut data = T::new();
-    let mut index: usize = 1;
-    for (_, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
-        let struct_exemplar = T::parse(line.as_str()).unwrap();
+    let format = Format::from_path(&file_path);
+    for item in stream::<T>(open_input(&file_path)?, &file_path, format)? {
+        let (index, struct_exemplar) = item?;
         data.insert(index, struct_exemplar);
-        index += 1;
     }
 
     Ok(data)
 }
 
+// An item read from a stream with its index, or why it could not be read.
+pub type Parsed<I> = Result<(usize, I), TradeMatchErrors>;
+
+// Opens a file for reading line by line, or standard input for "-".
+pub fn open_input(file_path: &str) -> Result<Box<dyn BufRead>, TradeMatchErrors> {
+    if file_path == "-" {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let file = fs::File::open(file_path).map_err(|source| TradeMatchErrors::IoError {
+        path: file_path.to_string(),
+        source,
+    })?;
+    Ok(Box::new(io::BufReader::new(file)))
+}
+
+// Parses items from `reader` one at a time as they are read, so only the
+// current line is held in memory. Lines are indexed by line number, CSV
+// records by their position after the header; items only take that index
+// when inserted. A line that fails to parse comes out as a ParseError and the
+// stream carries on after it.
+pub fn stream<'a, T>(
+    reader: impl BufRead + 'a,
+    file_path: &str,
+    format: Format,
+) -> Result<Box<dyn Iterator<Item = Parsed<T::Item>> + 'a>, TradeMatchErrors>
+where
+    T: DataParser,
+    T::Item: 'a,
+    T::Err: std::fmt::Display + 'a,
+{
+    let file = file_path.to_string();
+    let line_error = move |line, text, error: T::Err| LineError {
+        file: file.clone(),
+        line,
+        text,
+        message: error.to_string(),
+    };
+    match format {
+        Format::Text | Format::Jsonl => {
+            let parse = match format {
+                Format::Jsonl => T::parse_json,
+                _ => T::parse,
+            };
+            Ok(Box::new(lines(reader, file_path).map(move |line| {
+                let (number, text) = line?;
+                let item = parse(&text).map_err(|error| line_error(number, text, error))?;
+                Ok((number, item))
+            })))
+        }
+        Format::Csv => {
+            let csv_error = |source| formats::csv_error(file_path, source);
+            let mut reader = formats::csv_reader(reader);
+            let headers = reader.headers().map_err(csv_error)?.clone();
+            let file = file_path.to_string();
+            Ok(Box::new(reader.into_records().enumerate().map(
+                move |(index, record)| {
+                    let record = record.map_err(|source| formats::csv_error(&file, source))?;
+                    let line = record
+                        .position()
+                        .map_or(index + 2, |position| position.line() as usize);
+                    let item =
+                        T::parse_record(&Record::new(&headers, &record)).map_err(|error| {
+                            line_error(line, record.iter().collect::<Vec<_>>().join(","), error)
+                        })?;
+                    Ok((index + 1, item))
+                },
+            )))
+        }
+    }
+}
+
+// Numbered lines of a reader. Invalid UTF-8 is replaced rather than failing
+// the read, so it surfaces as a parse error of that line.
+fn lines<'a>(
+    reader: impl BufRead + 'a,
+    file_path: &str,
+) -> impl Iterator<Item = Result<(usize, String), TradeMatchErrors>> + 'a {
+    let file = file_path.to_string();
+    reader.split(b'\n').enumerate().map(move |(index, line)| {
+        let line = line.map_err(|source| TradeMatchErrors::IoError {
+            path: file.clone(),
+            source,
+        })?;
+        let text = String::from_utf8_lossy(&line);
+        Ok((index + 1, text.trim_end_matches('\r').to_string()))
+    })
+}
+
+// What validate_file does with lines that fail to parse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    // Fail with every bad line of the file.
+    #[default]
+    Stop,
+    // Leave bad lines out and return them next to the parsed data.
+    Skip,
+}
+
+// Parses the whole file instead of stopping at the first bad line. Items keep
+// their line number as index, so skipping a line does not renumber the rest.
+pub fn validate_file<T>(
+    file_path: &str,
+    on_error: OnError,
+) -> Result<(T, Vec<LineError>), TradeMatchErrors>
+where
+    T: DataParser,
+    T::Err: std::fmt::Display,
+{
+    validate_file_as(file_path, Format::from_path(file_path), on_error)
+}
+
+// Same as validate_file, in a format chosen by the caller.
+pub fn validate_file_as<T>(
+    file_path: &str,
+    format: Format,
+    on_error: OnError,
+) -> Result<(T, Vec<LineError>), TradeMatchErrors>
+where
+    T: DataParser,
+    T::Err: std::fmt::Display,
+{
+    let mut data = T::new();
+    let mut errors = Vec::new();
+    for item in stream::<T>(open_input(file_path)?, file_path, format)? {
+        match item {
+            Ok((index, item)) => data.insert(index, item),
+            Err(TradeMatchErrors::ParseError(error)) => errors.push(error),
+            Err(error) => return Err(error),
+        }
+    }
+
+    match on_error {
+        OnError::Stop if !errors.is_empty() => Err(TradeMatchErrors::InvalidLinesError(errors)),
+        _ => Ok((data, errors)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use crate::clients::Clients;
+    use crate::{backtest::Strategy, clients::Clients, errors::OrderErrors, orders::NewOrders};
 
     #[test]
     fn test_read_file() {
@@ -62,4 +234,83 @@ mod tests {
         assert_eq!(client.asset_balances.get("C").unwrap().balance, 760);
         assert_eq!(client.asset_balances.get("D").unwrap().balance, 320);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_validate_file() {
+        let file = std::env::temp_dir().join("trade_match_validate_orders.txt");
+        fs::write(
+            &file,
+            "C1\tb\tA\t10\t5\nC2\tx\tA\t10\t5\nC3\ts\tB\t12\nC4\ts\tB\t12\t3\n",
+        )
+        .unwrap();
+        let file = file.to_str().unwrap();
+
+        let errors = match validate_file::<NewOrders>(file, OnError::Stop) {
+            Err(TradeMatchErrors::InvalidLinesError(errors)) => errors,
+            other => panic!("expected invalid lines, got {:?}", other),
+        };
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].text, "C2\tx\tA\t10\t5");
+        assert_eq!(errors[0].message, "No such operation symbol");
+        assert_eq!(errors[1].line, 3);
+
+        let (orders, skipped) = validate_file::<NewOrders>(file, OnError::Skip).unwrap();
+        assert_eq!(skipped, errors);
+        assert_eq!(orders.order.keys().copied().collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(orders.order[&4].client_name, "C4");
+    }
+
+    #[test]
+    fn test_validate_missing_file() {
+        let error = validate_file::<Clients>("./no_such_file.txt", OnError::Skip).unwrap_err();
+        assert!(
+            matches!(error, TradeMatchErrors::IoError { ref path, .. } if path == "./no_such_file.txt")
+        );
+    }
+
+    #[test]
+    fn test_read_file_errors() {
+        let file = std::env::temp_dir().join("trade_match_read_clients.txt");
+        fs::write(
+            &file,
+            "C1\t1000\t10\t5\t15\t0\nC2\t1000\tten\t5\t15\t0\nC3\n",
+        )
+        .unwrap();
+        let file = file.to_str().unwrap().to_string();
+
+        match read_file::<Clients>(file.clone()) {
+            Err(TradeMatchErrors::ParseError(error)) => {
+                assert_eq!(error.file, file);
+                assert_eq!(error.line, 2);
+                assert_eq!(error.message, "Unable to parse client asset balance");
+            }
+            other => panic!("expected a parse error, got {:?}", other.err()),
+        }
+        assert!(matches!(
+            read_file::<Clients>("./no_such_file.txt".to_string()),
+            Err(TradeMatchErrors::IoError { .. })
+        ));
+    }
+
+    // Strategy files read JSON Lines as arrays of fields. One that is not
+    // an array is reported as such, not as missing fields.
+    #[test]
+    fn test_parse_json_errors() {
+        let file = std::env::temp_dir().join("trade_match_strategy.jsonl");
+        fs::write(&file, "[3,\"C1\",\"b\",\"A\",10,5]\n{\"sequence\":3\n").unwrap();
+        let file = file.to_str().unwrap().to_string();
+
+        match read_file::<Strategy>(file.clone()) {
+            Err(TradeMatchErrors::ParseError(error)) => {
+                assert_eq!(error.line, 2);
+                assert!(error.message.starts_with("Unable to parse order JSON: "));
+            }
+            other => panic!("expected a parse error, got {:?}", other.err()),
+        }
+        assert!(matches!(
+            Strategy::parse_json("[\"C1\""),
+            Err(OrderErrors::ParseJsonError(_))
+        ));
+    }
+}
@@ -1,404 +1,571 @@
-use std::{fs::File, io::Write};
+use clap::Parser;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufWriter, Write},
+    process, thread,
+    time::{Duration, Instant},
+};
 use trade_match::{
+    backtest::{self, Strategy, BACKTEST_PNL_HEADER, EXECUTION_HEADER},
+    binary::{self, MessageView},
+    candles::{Candle, Candles, CSV_HEADER},
+    cli::{render, Cli, Command, OutputFormat, ReportKind},
     clients::Clients,
-    config::get_config,
-    errors::GeneralErrors,
-    orders::OrderType,
-    orders::{Order, Orders},
-    read_file, DataParser,
+    config::{get_config_from, FilePath},
+    engine::{Engine, Trade},
+    errors::{GeneralErrors, LineError, TradeMatchErrors},
+    fix::Acceptor,
+    formats::{write_clients, write_trades, Format},
+    generator::{self, Synthetic},
+    ledger::JOURNAL_HEADER,
+    margin::{Margin, MARGIN_HEADER},
+    open_input,
+    orders::{NewOrder, NewOrders},
+    parallel,
+    pnl::{self, PNL_HEADER},
+    reports::{execution_quality, REPORT_HEADER},
+    risk::Risk,
+    simulator::{Agent, MarketMaker, MomentumTaker, NoiseTrader, Simulator, SIMULATION_HEADER},
+    stream, validate_file_as, DataParser, OnError, Parsed,
 };
 
-fn main() -> Result<(), GeneralErrors> {
-    let file_path = get_config().map_err(|_| GeneralErrors::GetConfigError)?;
-    let mut clients: Clients =
-        read_file(file_path.clients).map_err(|_| GeneralErrors::ReadFileError)?;
-    let orders: Orders = read_file(file_path.orders).map_err(|_| GeneralErrors::ReadFileError)?;
-    match_orders(&mut clients, orders);
-    write_file(clients);
-    Ok(())
+fn main() {
+    if let Err(error) = execute(Cli::parse()) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
 }
 
-// A function that checks orders by operation and calls a method depending on it.
-fn match_orders(clients: &mut Clients, orders: Orders) {
-    let mut buy_orders = Orders::new();
-    let mut sell_orders = Orders::new();
-
-    for mut order in orders.order {
-        match order.1.operation {
-            OrderType::Buy => {
-                let (completed_orders, updated_orders) = buy_assets(&sell_orders, clients, &mut order.1);
-                if order.1.value > 0 {
-                    buy_orders.insert(order.0, order.1);
-                }
-                if !completed_orders.is_empty() {
-                    sell_orders.remove(completed_orders);
+fn execute(cli: Cli) -> Result<(), TradeMatchErrors> {
+    let file_path = cli.apply(get_config_from(&cli.config)?);
+    match cli.command() {
+        Command::Run => {
+            let engine = engine(&file_path)?;
+            return run(&cli, file_path, engine);
+        }
+        Command::Validate => return validate(&file_path),
+        Command::Convert { destination } => return convert(&file_path, &destination),
+        Command::Generate {
+            client_file,
+            order_file,
+            settings,
+        } => return generate(&cli, &client_file, &order_file, &settings),
+        Command::Replay => {
+            let mut engine = engine(&file_path)?;
+            let write_error = |source| TradeMatchErrors::WriteError {
+                path: "-".to_string(),
+                source,
+            };
+            match cli.format {
+                // Fills are written as they come. A table has to wait for
+                // all of them to know its column widths.
+                OutputFormat::Tab => {
+                    let mut out = BufWriter::new(io::stdout().lock());
+                    match_file(&cli, &file_path, &mut engine, |fills| {
+                        fills
+                            .iter()
+                            .try_for_each(|trade| write!(out, "{}", trade))
+                            .map_err(write_error)
+                    })?;
+                    out.flush().map_err(write_error)?;
                 }
-                if !updated_orders.is_empty() {
-                    sell_orders.update_orders(updated_orders);
+                OutputFormat::Table => {
+                    let mut lines = String::new();
+                    match_file(&cli, &file_path, &mut engine, |fills| {
+                        fills.iter().for_each(|trade| lines += &trade.to_string());
+                        Ok(())
+                    })?;
+                    print!("{}", render(&lines, cli.format));
                 }
             }
-            OrderType::Sell => {
-                let (completed_orders, updated_orders) = sell_assets(&buy_orders, clients, &mut order.1);
-                if order.1.value > 0 {
-                    sell_orders.insert(order.0, order.1);
-                }
-                if !completed_orders.is_empty() {
-                    buy_orders.remove(completed_orders);
+        }
+        Command::Book { level, asset } => {
+            let mut engine = engine(&file_path)?;
+            match_file(&cli, &file_path, &mut engine, |_| Ok(()))?;
+            print!("{}", render(&book_lines(&engine, level, asset), cli.format));
+        }
+        Command::Backtest { strategy } => {
+            let mut engine = engine(&file_path)?;
+            let format = Format::resolve(&file_path.orders, file_path.formats.orders);
+            let market: NewOrders = load(&file_path.orders, format, file_path.on_error)?;
+            let format = Format::from_path(&strategy);
+            let strategy: Strategy = load(&strategy, format, file_path.on_error)?;
+            let backtest = backtest::run(&mut engine, market, strategy, file_path.on_error)?;
+            for (index, error) in &backtest.skipped {
+                eprintln!("Skipped {}:{}: {}", file_path.orders, index, error);
+            }
+            let executions: String = backtest
+                .executions
+                .iter()
+                .map(|execution| execution.to_string())
+                .collect();
+            let pnl: String = backtest.pnl.iter().map(|pnl| pnl.to_string()).collect();
+            print!(
+                "{}\n{}",
+                render(&format!("{}\n{}", EXECUTION_HEADER, executions), cli.format),
+                render(&format!("{}\n{}", BACKTEST_PNL_HEADER, pnl), cli.format)
+            );
+        }
+        Command::Simulate { steps, seed, fair } => {
+            let engine = engine(&file_path)?;
+            let lines = simulate(&cli, engine, steps, seed, fair)?;
+            print!(
+                "{}",
+                render(&format!("{}\n{}", SIMULATION_HEADER, lines), cli.format)
+            );
+        }
+        Command::Report { kind } => {
+            let mut engine = engine(&file_path)?;
+            if kind == ReportKind::Ledger {
+                engine.keep_journal();
+            }
+            // Only the quality report needs the fills themselves.
+            let mut trades = Vec::new();
+            match_file(&cli, &file_path, &mut engine, |fills| {
+                if kind == ReportKind::Quality {
+                    trades.extend_from_slice(fills);
                 }
-                if !updated_orders.is_empty() {
-                    buy_orders.update_orders(updated_orders);
+                Ok(())
+            })?;
+            let lines = match kind {
+                ReportKind::Quality => quality_lines(&trades),
+                ReportKind::Pnl => pnl_lines(&engine),
+                ReportKind::Margin => format!("{}\n{}", MARGIN_HEADER, margin_lines(&engine)),
+                ReportKind::Ledger => {
+                    // The journal is only worth printing if it adds up to the balances.
+                    engine.ledger.reconcile(&engine.clients)?;
+                    format!("{}\n{}", JOURNAL_HEADER, ledger_lines(&engine))
                 }
-            }
-            _ => continue,
+            };
+            print!("{}", render(&lines, cli.format));
         }
     }
+    Ok(())
 }
 
-fn buy_assets(sell_orders: &Orders, clients: &mut Clients, order: &mut Order) -> (Vec<Order>, Vec<Order>) {
-    let mut sell_asset_orders: Vec<Order> = Vec::new();
-    let mut completed_orders: Vec<Order> = Vec::new();
-    let mut updated_orders: Vec<Order> = Vec::new();
-    
-    // Order vector formation.
-    for ord in &sell_orders.order {
-        if ord.1.client_name != order.client_name
-            && order.asset == ord.1.asset
-            && order.order_price >= ord.1.order_price
-        {
-            sell_asset_orders.push(ord.1.clone());
-        }
+// Engine over the clients file, tracking cost basis, checking risk limits
+// and keeping margin accounts as configured.
+fn engine(file_path: &FilePath) -> Result<Engine, TradeMatchErrors> {
+    let format = Format::resolve(&file_path.clients, file_path.formats.clients);
+    let mut clients: Clients = load(&file_path.clients, format, file_path.on_error)?;
+    if let Some(pnl) = &file_path.pnl {
+        clients.set_cost_method(pnl.method);
     }
+    let mut engine = Engine::new(clients);
+    if let Some(risk) = &file_path.risk {
+        engine.risk = Risk::new(risk.clone());
+    }
+    if let Some(margin) = &file_path.margin {
+        engine.margin = Margin::new(margin.clone());
+    }
+    Ok(engine)
+}
 
-    sell_asset_orders.sort_by(|a, b| a.order_price.cmp(&b.order_price));
-
-    for mut sell_order in sell_asset_orders {
-
-        if order.value == 0 {
-            break;
-        }
-
-        // Check for balance errors.
-        let buyer_err = clients.get(&order.client_name).and_then(|c| {
-            if c.check_buy_error(&sell_order, order).is_err() {
-                None
-            } else {
-                Some(true)
-            }
-        });
-
-        let seller_err = clients.get(&sell_order.client_name).and_then(|c| {
-            if c.check_sell_error(order).is_err() {
-                None
-            } else {
-                Some(true)
-            }
-        });
-
-        // Go to the next iteration in case of an error.
-        if buyer_err.is_none() || seller_err.is_none() {
-            continue;
-        }
-
-
-        clients
-            .get_entry(order.client_name.clone())
-            .and_modify(|c| {
-
-                if order.value > sell_order.value {
-                    c.buy(&sell_order, sell_order.value);
-                } else {
-                    c.buy(&sell_order, order.value);
-                }
-
-            });
-
-        clients
-            .get_entry(sell_order.client_name.clone())
-            .and_modify(|c| {
+// Matches the orders file and writes every configured output,
+// or serves FIX order entry when the acceptor is configured.
+fn run(cli: &Cli, file_path: FilePath, mut engine: Engine) -> Result<(), TradeMatchErrors> {
+    let mut candles = file_path
+        .candles
+        .as_ref()
+        .map(|candles| Candles::new(candles.bucket));
+    if let Some(candles) = &file_path.candles {
+        write_candles(&candles.path, &[], false)?;
+    }
 
-                if order.value > sell_order.value {
-                    c.sell(&sell_order, sell_order.value);
-                
-                    order.value -= sell_order.value;
-                    completed_orders.push(sell_order.clone());
-                } else {
-                    c.sell(&sell_order, order.value);
-                   
-                    sell_order.value -= order.value;
-                    updated_orders.push(sell_order.clone());
-                    order.value = 0;
+    // Server mode: accept orders over FIX instead of reading the orders file.
+    if let Some(fix) = file_path.fix {
+        let acceptor = Acceptor::bind(fix.address, &fix.comp_id, engine, fix.accounts)
+            .map_err(|_| GeneralErrors::FixAcceptorError)?;
+        if let (Some(candles), Some(config)) = (candles, file_path.candles) {
+            let gateway = acceptor.gateway();
+            gateway.lock().unwrap().candles = Some(candles);
+            // Flushes wall-clock candles as their interval closes.
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(1));
+                let closed = match gateway.lock().unwrap().candles.as_mut() {
+                    Some(candles) => {
+                        candles.tick(Instant::now());
+                        candles.take_closed()
+                    }
+                    None => Vec::new(),
+                };
+                if let Err(error) = write_candles(&config.path, &closed, true) {
+                    eprintln!("{}", error);
                 }
-
             });
+        }
+        return acceptor
+            .run()
+            .map_err(|_| GeneralErrors::FixAcceptorError.into());
+    }
 
-        
+    // Fills are only kept for the outputs that list them.
+    let keep = file_path.report.is_some() || file_path.trades.is_some();
+    let mut trades = Vec::new();
+    match_file(cli, &file_path, &mut engine, |fills| {
+        if let Some(candles) = candles.as_mut() {
+            candles.on_message(fills);
+        }
+        if keep {
+            trades.extend_from_slice(fills);
+        }
+        Ok(())
+    })?;
+    if let (Some(mut candles), Some(config)) = (candles, &file_path.candles) {
+        write_candles(&config.path, &candles.finish(), true)?;
+    }
+    if let Some(book) = &file_path.book {
+        write_lines(
+            book,
+            &format!(
+                "L2\n{}L3\n{}",
+                book_lines(&engine, 2, None),
+                book_lines(&engine, 3, None)
+            ),
+        )?;
+    }
+    if let Some(report) = &file_path.report {
+        write_lines(
+            report,
+            &format!("{}\n{}", REPORT_HEADER, quality_lines(&trades)),
+        )?;
     }
-    (completed_orders, updated_orders)
+    if let Some(pnl) = &file_path.pnl {
+        write_lines(
+            &pnl.path,
+            &format!("{}\n{}", PNL_HEADER, pnl_lines(&engine)),
+        )?;
+    }
+    if let Some(path) = &file_path.trades {
+        write_trades(
+            &trades,
+            path,
+            Format::resolve(path, file_path.formats.trades),
+        )?;
+    }
+    let format = Format::resolve(&file_path.result, file_path.formats.result);
+    write_clients(&engine.clients, &file_path.result, format)?;
+    if cli.verbose > 0 {
+        eprintln!("Results written to {}", file_path.result);
+    }
+    Ok(())
 }
 
-fn sell_assets(buy_orders: &Orders, clients: &mut Clients, order: &mut Order) -> (Vec<Order>, Vec<Order>) {
-    let mut buy_asset_orders: Vec<Order> = Vec::new();
-    let mut completed_orders: Vec<Order> = Vec::new();
-    let mut updated_orders: Vec<Order> = Vec::new();
-
-    // Order vector formation.
-    for ord in &buy_orders.order {
-        if ord.1.client_name != order.client_name
-            && order.asset == ord.1.asset
-            && order.order_price < ord.1.order_price
-        {
-            buy_asset_orders.push(ord.1.clone());
-        }
+// Parses both input files in full and lists every line that is not valid.
+fn validate(file_path: &FilePath) -> Result<(), TradeMatchErrors> {
+    let format = Format::resolve(&file_path.clients, file_path.formats.clients);
+    let (clients, mut errors) = read_all::<Clients>(&file_path.clients, format)?;
+    let format = Format::resolve(&file_path.orders, file_path.formats.orders);
+    let (orders, mut order_errors) = read_all::<NewOrders>(&file_path.orders, format)?;
+    errors.append(&mut order_errors);
+    for error in &errors {
+        println!("{}", error);
     }
+    println!(
+        "{}: {} clients\n{}: {} orders",
+        file_path.clients,
+        clients.client.len(),
+        file_path.orders,
+        orders.order.len()
+    );
+    if !errors.is_empty() {
+        return Err(TradeMatchErrors::InvalidLinesError(errors));
+    }
+    Ok(())
+}
 
-    buy_asset_orders.sort_by(|a, b| a.order_price.cmp(&b.order_price));
-    
-    for mut buy_order in buy_asset_orders {
+fn read_all<T>(path: &str, format: Format) -> Result<(T, Vec<LineError>), TradeMatchErrors>
+where
+    T: DataParser,
+    T::Err: std::fmt::Display,
+{
+    validate_file_as(path, format, OnError::Skip)
+}
 
-        if order.value == 0 {
-            break;
+// Reads an input file, printing its invalid lines to stderr. Depending on
+// on_error they either fail the run or are left out.
+fn load<T>(path: &str, format: Format, on_error: OnError) -> Result<T, TradeMatchErrors>
+where
+    T: DataParser,
+    T::Err: std::fmt::Display,
+{
+    match validate_file_as(path, format, on_error) {
+        Ok((data, skipped)) => {
+            for error in skipped {
+                eprintln!("Skipped {}", error);
+            }
+            Ok(data)
         }
-
-        // Check for balance errors.
-        let buyer_err = clients.get(&buy_order.client_name).and_then(|c| {
-            if c.check_buy_error(&buy_order, order).is_err() {
-                None
-            } else {
-                Some(true)
+        Err(TradeMatchErrors::InvalidLinesError(errors)) => {
+            for error in &errors {
+                eprintln!("{}", error);
             }
-        });
+            Err(TradeMatchErrors::InvalidLinesError(errors))
+        }
+        Err(error) => Err(error),
+    }
+}
 
-        let seller_err = clients.get(&order.client_name).and_then(|c| {
-            if c.check_sell_error(order).is_err() {
-                None
-            } else {
-                Some(true)
+// Runs the orders file through the engine and hands the fills of each order
+// to the sink as they come, so nothing is held on to unless the sink keeps
+// it. Orders the engine rejects are handled like invalid lines.
+fn match_file(
+    cli: &Cli,
+    file_path: &FilePath,
+    engine: &mut Engine,
+    mut sink: impl FnMut(&[Trade]) -> Result<(), TradeMatchErrors>,
+) -> Result<(), TradeMatchErrors> {
+    let mut count = 0;
+    let mut traded = 0;
+    let mut record = |index: usize, result: Result<Vec<Trade>, TradeMatchErrors>| {
+        count += 1;
+        let fills = match result {
+            Ok(fills) => fills,
+            Err(error) if file_path.on_error == OnError::Skip => {
+                eprintln!("Skipped {}:{}: {}", file_path.orders, index, error);
+                return Ok(());
             }
-        });
-        
-        // Go to the next iteration in case of an error.
-        if buyer_err.is_none() || seller_err.is_none() {
-            continue;
+            Err(error) => return Err(error),
+        };
+        if cli.verbose > 1 {
+            fills.iter().for_each(|trade| eprint!("{}", trade));
         }
+        traded += fills.len();
+        sink(&fills)
+    };
 
-        clients
-            .get_entry(order.client_name.clone())
-            .and_modify(|c| {
-
-                if order.value >= buy_order.value {
-                    c.sell(&buy_order, buy_order.value);
-                } else {
-                    c.sell(&buy_order, order.value);
-                }
-
-            });
-
-        clients
-            .get_entry(buy_order.client_name.clone())
-            .and_modify(|c| {
-                
-                if order.value > buy_order.value {
-                    c.buy(&buy_order, buy_order.value);
-
-                    order.value -= buy_order.value;
-                    completed_orders.push(buy_order.clone());
-                } else {
-                    c.buy(&buy_order, order.value);
-
-                    buy_order.value -= order.value;
-                    updated_orders.push(buy_order.clone());
-                    order.value = 0;
-                }
-
-            });
-        
+    // Standard input is recognised as binary by its leading magic bytes.
+    let mut reader = open_input(&file_path.orders)?;
+    let sniffed = reader
+        .fill_buf()
+        .is_ok_and(|buffer| buffer.starts_with(binary::MAGIC));
+    if binary::is_binary(&file_path.orders) || sniffed {
+        for message in binary::stream(reader, &file_path.orders)? {
+            let message = message?;
+            let message = MessageView::new(&message);
+            record(message.index(), engine.apply(&message))?;
+        }
+    } else if cli.parallel {
+        let orders = orders(reader, file_path)?.collect::<Result<Vec<_>, _>>()?;
+        let (indexes, orders): (Vec<usize>, Vec<NewOrder>) = orders.into_iter().unzip();
+        for (index, result) in indexes.into_iter().zip(parallel::stream(engine, orders)) {
+            record(index, result)?;
+        }
+    } else {
+        for order in orders(reader, file_path)? {
+            let (index, order) = order?;
+            record(index, engine.enter(&order))?;
+        }
     }
-    (completed_orders, updated_orders)
-}
 
-fn write_file(clients: Clients) {
-    let mut file = File::create("Result.txt").unwrap();
-    for (_, client) in clients.client {
-        write!(file, "{}", client).unwrap();
+    // The file is one session: margin accounts pay interest and settle up.
+    if engine.margin.is_active() {
+        let fills = engine.end_session()?;
+        traded += fills.len();
+        sink(&fills)?;
+        if cli.verbose > 0 {
+            engine
+                .margin
+                .calls
+                .iter()
+                .for_each(|call| eprintln!("{}", call));
+        }
+    }
+    if cli.verbose > 0 {
+        eprintln!("Matched {} orders into {} trades", count, traded);
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
+// Orders of the orders file as they are read, indexed by line like the
+// orders of a loaded file. Invalid lines are either reported and left out or
+// end the stream, depending on on_error.
+fn orders<'a>(
+    reader: Box<dyn BufRead + 'a>,
+    file_path: &'a FilePath,
+) -> Result<impl Iterator<Item = Parsed<NewOrder>> + 'a, TradeMatchErrors> {
+    let format = Format::resolve(&file_path.orders, file_path.formats.orders);
+    let orders = stream::<NewOrders>(reader, &file_path.orders, format)?;
+    Ok(orders.filter_map(move |order| match order {
+        Ok((index, order)) => Some(Ok((index, NewOrder { index, ..order }))),
+        Err(TradeMatchErrors::ParseError(error)) if file_path.on_error == OnError::Skip => {
+            eprintln!("Skipped {}", error);
+            None
+        }
+        Err(error) => Some(Err(error)),
+    }))
+}
 
-    use super::*;
-    use trade_match::{
-        clients::{Asset, Assets, Client},
-        orders::{Order, Orders},
+// Writes the orders file as a binary message stream, to stdout for "-".
+fn convert(file_path: &FilePath, destination: &str) -> Result<(), TradeMatchErrors> {
+    let write_error = |source| TradeMatchErrors::WriteError {
+        path: destination.to_string(),
+        source,
     };
+    let file: Box<dyn Write> = match destination {
+        "-" => Box::new(io::stdout().lock()),
+        _ => Box::new(File::create(destination).map_err(write_error)?),
+    };
+    let mut file = BufWriter::new(file);
+    file.write_all(binary::MAGIC).map_err(write_error)?;
+    for order in orders(open_input(&file_path.orders)?, file_path)? {
+        let (_, order) = order?;
+        file.write_all(&binary::encode_order(&order)?)
+            .map_err(write_error)?;
+    }
+    file.flush().map_err(write_error)
+}
 
-    #[test]
-    fn test_buy() {
-        let mut buyer_asset = Assets::new();
-        let client1_asset = Asset {
-            symbol: "A".to_string(),
-            balance: 25,
-        };
-        buyer_asset
-            .asset
-            .insert(client1_asset.symbol.clone(), client1_asset);
-
-        let mut seller_asset = Assets::new();
-        let client2_asset = Asset {
-            symbol: "A".to_string(),
-            balance: 25,
-        };
-        seller_asset
-            .asset
-            .insert(client2_asset.symbol.clone(), client2_asset);
-
-        let mut clients = Clients::new();
-        let buy_client = Client {
-            index: 0,
-            name: "C2".to_string(),
-            dollar_balance: 1000,
-            asset_balances: buyer_asset,
-        };
-
-        let sell_client = Client {
-            index: 0,
-            name: "C3".to_string(),
-            dollar_balance: 1000,
-            asset_balances: seller_asset,
-        };
-        clients.insert(1, buy_client);
-        clients.insert(2, sell_client);
-
-        let mut orders: Orders = Orders::new();
-
-        let sell_order_1 = Order {
-            index: 0,
-            client_name: "C2".to_string(),
-            operation: OrderType::Sell,
-            asset: "A".to_string(),
-            order_price: 8,
-            value: 4,
-        };
-
-        let mut buy_order_1 = Order {
-            index: 2,
-            client_name: "C3".to_string(),
-            operation: OrderType::Buy,
-            asset: "A".to_string(),
-            order_price: 10,
-            value: 6,
-        };
-
-        orders.insert(sell_order_1.index, sell_order_1);
-
-        buy_assets(&orders, &mut clients, &mut buy_order_1);
-
-        assert_eq!(clients.get("C2".to_string()).unwrap().dollar_balance, 1032);
-        assert_eq!(
-            clients
-                .get("C2".to_string())
-                .unwrap()
-                .asset_balances
-                .get("A".to_string())
-                .unwrap()
-                .balance,
-            21
-        );
-        assert_eq!(clients.get("C3".to_string()).unwrap().dollar_balance, 968);
-        assert_eq!(
-            clients
-                .get("C3".to_string())
-                .unwrap()
-                .asset_balances
-                .get("A".to_string())
-                .unwrap()
-                .balance,
-            29
+fn generate(
+    cli: &Cli,
+    client_file: &str,
+    order_file: &str,
+    settings: &Synthetic,
+) -> Result<(), TradeMatchErrors> {
+    let (clients, messages) = generator::generate(settings)?;
+    generator::write(&clients, &messages, client_file, order_file)?;
+    if cli.verbose > 0 {
+        eprintln!(
+            "Generated {} clients into {} and {} messages into {}",
+            clients.client.len(),
+            client_file,
+            messages.len(),
+            order_file
         );
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_sell() {
-        let mut buyer_asset = Assets::new();
-        let client1_asset = Asset {
-            symbol: "A".to_string(),
-            balance: 25,
-        };
-        buyer_asset
-            .asset
-            .insert(client1_asset.symbol.clone(), client1_asset);
+// Casts the clients, in name order, as market maker, momentum taker and
+// noise trader in turn, all of them trading every asset any client holds.
+fn simulate(
+    cli: &Cli,
+    engine: Engine,
+    steps: usize,
+    seed: u64,
+    fair: u32,
+) -> Result<String, TradeMatchErrors> {
+    let assets: Vec<String> = engine
+        .clients
+        .iter()
+        .flat_map(|client| client.asset_balances.iter())
+        .map(|asset| asset.symbol.clone())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let agents: Vec<Box<dyn Agent>> = engine
+        .clients
+        .iter()
+        .map(|client| &client.name)
+        .enumerate()
+        .map(|(turn, client)| -> Box<dyn Agent> {
+            match turn % 3 {
+                0 => Box::new(MarketMaker {
+                    client: client.clone(),
+                    assets: assets.clone(),
+                    fair,
+                    half_spread: 1,
+                    size: 5,
+                }),
+                1 => Box::new(MomentumTaker::new(client, assets.clone(), 5, 5)),
+                _ => Box::new(NoiseTrader::new(
+                    client,
+                    assets.clone(),
+                    fair,
+                    3,
+                    10,
+                    seed.wrapping_add(turn as u64),
+                )),
+            }
+        })
+        .collect();
 
-        let mut seller_asset = Assets::new();
-        let client2_asset = Asset {
-            symbol: "A".to_string(),
-            balance: 25,
-        };
-        seller_asset
-            .asset
-            .insert(client2_asset.symbol.clone(), client2_asset);
+    let mut simulator = Simulator::new(engine, agents)?;
+    let trades = simulator.run(steps)?;
+    if cli.verbose > 0 {
+        eprintln!("Simulated {} steps into {} trades", steps, trades.len());
+    }
+    Ok(simulator
+        .report()
+        .iter()
+        .map(|outcome| outcome.to_string())
+        .collect())
+}
 
-        let mut clients = Clients::new();
-        let buy_client = Client {
-            index: 0,
-            name: "C2".to_string(),
-            dollar_balance: 1000,
-            asset_balances: buyer_asset,
-        };
+// Resting orders as price levels (level 2) or individual orders (level 3).
+fn book_lines(engine: &Engine, level: u8, asset: Option<String>) -> String {
+    let assets = match asset {
+        Some(asset) => vec![asset],
+        None => engine.assets().into_iter().collect(),
+    };
+    assets
+        .iter()
+        .map(|asset| match level {
+            2 => engine.depth(asset).to_string(),
+            _ => engine
+                .order_depth(asset)
+                .named(&engine.clients.registry)
+                .to_string(),
+        })
+        .collect()
+}
 
-        let sell_client = Client {
-            index: 0,
-            name: "C3".to_string(),
-            dollar_balance: 1000,
-            asset_balances: seller_asset,
-        };
-        clients.insert(1, buy_client);
-        clients.insert(2, sell_client);
+fn quality_lines(trades: &[Trade]) -> String {
+    execution_quality(trades)
+        .iter()
+        .map(|quality| quality.to_string())
+        .collect()
+}
 
-        let mut orders: Orders = Orders::new();
+fn pnl_lines(engine: &Engine) -> String {
+    pnl::report(&engine.clients, &engine.quotes)
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
 
-        let mut sell_order_1 = Order {
-            index: 0,
-            client_name: "C2".to_string(),
-            operation: OrderType::Sell,
-            asset: "A".to_string(),
-            order_price: 8,
-            value: 4,
-        };
+fn ledger_lines(engine: &Engine) -> String {
+    engine
+        .ledger
+        .entries
+        .iter()
+        .map(|entry| entry.named(&engine.clients.registry).to_string())
+        .collect()
+}
 
-        let buy_order_1 = Order {
-            index: 2,
-            client_name: "C3".to_string(),
-            operation: OrderType::Buy,
-            asset: "A".to_string(),
-            order_price: 10,
-            value: 6,
-        };
+fn margin_lines(engine: &Engine) -> String {
+    engine
+        .margin
+        .report(&engine.clients, &engine.quotes)
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
 
-        orders.insert(buy_order_1.index, buy_order_1);
+fn write_lines(path: &str, lines: &str) -> Result<(), TradeMatchErrors> {
+    let mut file = File::create(path).map_err(|source| write_error(path, source))?;
+    write!(file, "{}", lines).map_err(|source| write_error(path, source))
+}
 
-        sell_assets(&orders, &mut clients, &mut sell_order_1);
+// Creates the candle file with its header, or appends closed candles to it.
+fn write_candles(path: &str, candles: &[Candle], append: bool) -> Result<(), TradeMatchErrors> {
+    let error = |source| write_error(path, source);
+    let mut file = if append {
+        OpenOptions::new().append(true).open(path).map_err(error)?
+    } else {
+        let mut file = File::create(path).map_err(error)?;
+        writeln!(file, "{}", CSV_HEADER).map_err(error)?;
+        file
+    };
+    for candle in candles {
+        write!(file, "{}", candle).map_err(error)?;
+    }
+    Ok(())
+}
 
-        assert_eq!(clients.get("C2".to_string()).unwrap().dollar_balance, 1040);
-        assert_eq!(
-            clients
-                .get("C2".to_string())
-                .unwrap()
-                .asset_balances
-                .get("A".to_string())
-                .unwrap()
-                .balance,
-            21
-        );
-        assert_eq!(clients.get("C3".to_string()).unwrap().dollar_balance, 960);
-        assert_eq!(
-            clients
-                .get("C3".to_string())
-                .unwrap()
-                .asset_balances
-                .get("A".to_string())
-                .unwrap()
-                .balance,
-            29
-        );
+fn write_error(path: &str, source: io::Error) -> TradeMatchErrors {
+    TradeMatchErrors::WriteError {
+        path: path.to_string(),
+        source,
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,600 @@
+use crate::{
+    clients::{Client, Clients},
+    errors::{GeneralErrors, MarginErrors, TradeMatchErrors},
+    ledger::DOLLARS,
+    orders::{Order, OrderType},
+    quotes::Quotes,
+    symbols::Symbol,
+    Price, Volume,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub const MARGIN_HEADER: &str =
+    "client\tequity\tborrowed_dollars\tborrowed_value\trequirement\tinterest";
+
+// Margin accounts. A client on margin may buy with borrowed dollars and sell
+// units it does not hold, going short. Loans are drawn just before an order
+// of the account can fill, incoming or resting, and credited to the client's
+// balances, so matching itself is unchanged. What the fills leave unused is
+// paid back right after; the rest out of those balances at the end of each
+// session, once interest has been charged. Assets are valued at their mark:
+// the last trade, or the middle of the book before the first one.
+
+// The `margin` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MarginConfig {
+    // Clients that trade on margin. Everybody else trades on cash.
+    pub clients: Vec<String>,
+    // What an account owes may reach leverage - 1 times its equity.
+    #[serde(default = "default_leverage")]
+    pub leverage: f64,
+    // Equity below this share of what an account owes gets it called.
+    #[serde(default = "default_maintenance")]
+    pub maintenance: f64,
+    // Share of what an account owes charged every session, in dollars.
+    #[serde(default)]
+    pub interest: f64,
+}
+
+fn default_leverage() -> f64 {
+    2.0
+}
+
+fn default_maintenance() -> f64 {
+    0.25
+}
+
+// What one margin account owes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Loan {
+    pub dollars: u64,
+    pub units: BTreeMap<String, u64>,
+    // Interest charged so far, already part of dollars.
+    pub interest: u64,
+    // Whether the account is under a margin call.
+    pub called: bool,
+}
+
+// An account that fell below maintenance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginCall {
+    pub client: String,
+    pub equity: f64,
+    pub requirement: f64,
+}
+
+impl std::fmt::Display for MarginCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Margin call: {} has equity {:.2} against a requirement of {:.2}",
+            self.client, self.equity, self.requirement
+        )
+    }
+}
+
+// One line of the margin report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginLine {
+    pub client: String,
+    pub equity: f64,
+    pub borrowed_dollars: u64,
+    pub borrowed_value: f64,
+    pub requirement: f64,
+    pub interest: u64,
+}
+
+impl std::fmt::Display for MarginLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{:.2}\t{}\t{:.2}\t{:.2}\t{}",
+            self.client,
+            self.equity,
+            self.borrowed_dollars,
+            self.borrowed_value,
+            self.requirement,
+            self.interest
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Margin {
+    pub config: Option<MarginConfig>,
+    pub loans: BTreeMap<String, Loan>,
+    // Every margin call so far, in the order they were made.
+    pub calls: Vec<MarginCall>,
+    // Liquidation orders sent so far. Their indexes count down from the top
+    // of the range, so they never meet the indexes callers number their own
+    // orders with, which count up.
+    pub liquidations: usize,
+}
+
+pub fn mark(quotes: &Quotes, asset: &str) -> f64 {
+    let Some(quote) = quotes.get(asset) else {
+        return 0.0;
+    };
+    match (quote.last_price, quote.bid_price, quote.ask_price) {
+        (Some(last), _, _) => f64::from(last),
+        (None, Some(bid), Some(ask)) => (f64::from(bid) + f64::from(ask)) / 2.0,
+        (None, Some(price), None) | (None, None, Some(price)) => f64::from(price),
+        (None, None, None) => 0.0,
+    }
+}
+
+impl Margin {
+    pub fn new(config: MarginConfig) -> Margin {
+        Margin {
+            loans: config
+                .clients
+                .iter()
+                .map(|client| (client.clone(), Loan::default()))
+                .collect(),
+            config: Some(config),
+            calls: Vec::new(),
+            liquidations: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.loans.is_empty()
+    }
+
+    pub fn borrowed_value(&self, client: &str, quotes: &Quotes) -> f64 {
+        let Some(loan) = self.loans.get(client) else {
+            return 0.0;
+        };
+        loan.dollars as f64
+            + loan
+                .units
+                .iter()
+                .map(|(asset, units)| *units as f64 * mark(quotes, asset))
+                .sum::<f64>()
+    }
+
+    // Balances at their marks, less what the account owes.
+    pub fn equity(&self, client: &Client, quotes: &Quotes) -> f64 {
+        let held: f64 = client
+            .asset_balances
+            .asset
+            .values()
+            .map(|asset| f64::from(asset.balance) * mark(quotes, &asset.symbol))
+            .sum();
+        f64::from(client.dollar_balance) + held - self.borrowed_value(&client.name, quotes)
+    }
+
+    pub fn requirement(&self, client: &str, quotes: &Quotes) -> f64 {
+        let maintenance = self
+            .config
+            .as_ref()
+            .map_or(0.0, |config| config.maintenance);
+        maintenance * self.borrowed_value(client, quotes)
+    }
+
+    // Lends a margin client what an order needs beyond its balances: the
+    // dollars to pay its limit for all of a buy, or the units of a sell.
+    // Fails when that would take the account over its leverage.
+    pub fn borrow(
+        &mut self,
+        client: &mut Client,
+        order: &Order,
+        quotes: &Quotes,
+    ) -> Result<(), TradeMatchErrors> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if !self.loans.contains_key(&client.name) {
+            return Ok(());
+        }
+        let (dollars, units) = shortfall(client, order);
+        if dollars == 0 && units == 0 {
+            return Ok(());
+        }
+        let asset = asset_name(client, order)?;
+        let price = match mark(quotes, &asset) {
+            mark if mark > 0.0 => mark,
+            _ => f64::from(order.order_price),
+        };
+        let owed =
+            self.borrowed_value(&client.name, quotes) + dollars as f64 + units as f64 * price;
+        let allowed = (config.leverage - 1.0) * self.equity(client, quotes);
+        if owed > allowed {
+            return Err(MarginErrors::LeverageError(owed, allowed.max(0.0)).into());
+        }
+        self.lend(client, &asset, dollars, units)
+    }
+
+    // Lends what a liquidation order needs, whatever the leverage.
+    pub fn cover(&mut self, client: &mut Client, order: &Order) -> Result<(), TradeMatchErrors> {
+        let (dollars, units) = shortfall(client, order);
+        let asset = asset_name(client, order)?;
+        self.lend(client, &asset, dollars, units)
+    }
+
+    // Credits a loan to the client's balances and books it. The units join
+    // the client's position like a deposit.
+    fn lend(
+        &mut self,
+        client: &mut Client,
+        asset: &str,
+        dollars: u64,
+        units: u64,
+    ) -> Result<(), TradeMatchErrors> {
+        let overflow = || TradeMatchErrors::from(GeneralErrors::BalanceOverflowError);
+        let dollars_lent = i64::try_from(dollars).map_err(|_| overflow())?;
+        let units_lent = i64::try_from(units).map_err(|_| overflow())?;
+        u32::try_from(u64::from(client.dollar_balance) + dollars).map_err(|_| overflow())?;
+        if units > 0 {
+            client.adjust(asset, units_lent)?;
+        }
+        if dollars > 0 {
+            client.adjust(DOLLARS, dollars_lent)?;
+        }
+
+        let loan = self.loans.entry(client.name.clone()).or_default();
+        loan.dollars += dollars;
+        if units > 0 {
+            *loan.units.entry(asset.to_string()).or_default() += units;
+        }
+        Ok(())
+    }
+
+    // Pays back as much of the loans as the client's balances cover. The
+    // units leave the client's position like a withdrawal.
+    pub fn repay(&mut self, client: &mut Client) {
+        let Some(loan) = self.loans.get_mut(&client.name) else {
+            return;
+        };
+        let dollars = loan.dollars.min(u64::from(client.dollar_balance));
+        loan.dollars -= dollars;
+        if dollars > 0 {
+            // Never more than the balance, so this cannot fail.
+            let _ = client.adjust(DOLLARS, -(dollars as i64));
+        }
+        for (asset, owed) in loan.units.iter_mut() {
+            let held = client
+                .asset_balances
+                .get(asset.as_str())
+                .map_or(0, |held| u64::from(held.balance));
+            let units = (*owed).min(held);
+            *owed -= units;
+            if units > 0 {
+                let _ = client.adjust(asset, -(units as i64));
+            }
+        }
+        loan.units.retain(|_, owed| *owed > 0);
+    }
+
+    // Ends a session: charges interest on what every account owes, then
+    // pays back what the balances cover.
+    pub fn end_session(&mut self, clients: &mut Clients, quotes: &Quotes) {
+        let rate = self.config.as_ref().map_or(0.0, |config| config.interest);
+        let names: Vec<String> = self.loans.keys().cloned().collect();
+        for name in names {
+            let interest = (self.borrowed_value(&name, quotes) * rate).ceil() as u64;
+            if let Some(loan) = self.loans.get_mut(&name) {
+                loan.dollars += interest;
+                loan.interest += interest;
+            }
+            if let Some(client) = clients.get_mut(&name) {
+                self.repay(client);
+            }
+        }
+    }
+
+    // Accounts whose equity is below maintenance. Each is called once when
+    // it falls below and again only after it has recovered in between.
+    pub fn check_calls(&mut self, clients: &Clients, quotes: &Quotes) -> Vec<String> {
+        let mut called = Vec::new();
+        let names: Vec<String> = self.loans.keys().cloned().collect();
+        for name in names {
+            let Some(client) = clients.get(&name) else {
+                continue;
+            };
+            let equity = self.equity(client, quotes);
+            let requirement = self.requirement(&name, quotes);
+            let below = self.borrowed_value(&name, quotes) > 0.0 && equity < requirement;
+            let loan = self.loans.get_mut(&name).unwrap();
+            if below && !loan.called {
+                self.calls.push(MarginCall {
+                    client: name.clone(),
+                    equity,
+                    requirement,
+                });
+            }
+            loan.called = below;
+            if below {
+                called.push(name);
+            }
+        }
+        called
+    }
+
+    // Orders that close out a called account as far as the books allow:
+    // buys of the units it is short at the best ask, then sells of what it
+    // holds free and clear just through the best bid until its dollar loan
+    // is covered. Their index is left for the engine to set.
+    pub fn liquidation_orders(&self, client: &Client, quotes: &Quotes) -> Vec<Order> {
+        let Some(loan) = self.loans.get(&client.name) else {
+            return Vec::new();
+        };
+        let held = |asset: &str| {
+            client
+                .asset_balances
+                .get(asset)
+                .map_or(0, |asset| u64::from(asset.balance))
+        };
+        let mut orders = Vec::new();
+        for (asset, owed) in &loan.units {
+            let short = owed.saturating_sub(held(asset));
+            if let (true, Some(id), Some(ask)) = (
+                short > 0,
+                client.asset_balances.id(asset),
+                quotes.get(asset.as_str()).and_then(|q| q.ask_price),
+            ) {
+                orders.push(liquidation(client, OrderType::Buy, id, ask, short));
+            }
+        }
+
+        let mut deficit = loan
+            .dollars
+            .saturating_sub(u64::from(client.dollar_balance));
+        for asset in client.asset_balances.iter() {
+            let asset = &asset.symbol;
+            if deficit == 0 {
+                break;
+            }
+            let free =
+                held(asset).saturating_sub(loan.units.get(asset).copied().unwrap_or_default());
+            let (Some(id), Some(bid)) = (
+                client.asset_balances.id(asset),
+                quotes.get(asset.as_str()).and_then(|quote| quote.bid_price),
+            ) else {
+                continue;
+            };
+            if free == 0 || bid == 0 {
+                continue;
+            }
+            let volume = free.min(deficit.div_ceil(u64::from(bid)));
+            // A sell only fills strictly below the bid it meets.
+            orders.push(liquidation(client, OrderType::Sell, id, bid - 1, volume));
+            deficit = deficit.saturating_sub(volume * u64::from(bid));
+        }
+        orders
+    }
+
+    pub fn report(&self, clients: &Clients, quotes: &Quotes) -> Vec<MarginLine> {
+        self.loans
+            .iter()
+            .filter_map(|(name, loan)| {
+                let client = clients.get(name)?;
+                Some(MarginLine {
+                    client: name.clone(),
+                    equity: self.equity(client, quotes),
+                    borrowed_dollars: loan.dollars,
+                    borrowed_value: self.borrowed_value(name, quotes),
+                    requirement: self.requirement(name, quotes),
+                    interest: loan.interest,
+                })
+            })
+            .collect()
+    }
+}
+
+// Dollars and units an order needs beyond what its client holds.
+fn shortfall(client: &Client, order: &Order) -> (u64, u64) {
+    match order.operation {
+        OrderType::Buy => {
+            let needed = u64::from(order.order_price) * u64::from(order.value);
+            (needed.saturating_sub(u64::from(client.dollar_balance)), 0)
+        }
+        _ => {
+            let held = client
+                .asset_balances
+                .by_id(order.asset_id)
+                .map_or(0, |asset| asset.balance);
+            (0, u64::from(order.value.saturating_sub(held)))
+        }
+    }
+}
+
+// Loans are kept by asset name, as the client holds the asset.
+fn asset_name(client: &Client, order: &Order) -> Result<String, TradeMatchErrors> {
+    let asset = client
+        .asset_balances
+        .by_id(order.asset_id)
+        .ok_or(GeneralErrors::GetAssetError)?;
+    Ok(asset.symbol.clone())
+}
+
+fn liquidation(
+    client: &Client,
+    operation: OrderType,
+    asset: Symbol,
+    price: Price,
+    volume: u64,
+) -> Order {
+    Order {
+        index: 0,
+        client_id: client.id,
+        operation,
+        asset_id: asset,
+        order_price: price,
+        value: volume.min(u64::from(Volume::MAX)) as Volume,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::Engine,
+        ledger::{Holder, Unit},
+        orders::NewOrder,
+        DataParser,
+    };
+    use std::str::FromStr;
+
+    // C1 trades on margin with 200 dollars and no units, C2 and C3 on cash.
+    fn engine() -> Engine {
+        let mut clients = Clients::new();
+        for (index, line) in [
+            "C1\t200\t0\t0\t0\t0",
+            "C2\t1000\t0\t0\t0\t0",
+            "C3\t0\t10\t0\t0\t0",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            clients.insert(index + 1, Client::from_str(line).unwrap());
+        }
+        let mut engine = Engine::new(clients);
+        engine.margin = Margin::new(MarginConfig {
+            clients: vec!["C1".to_string()],
+            leverage: 2.0,
+            maintenance: 0.25,
+            interest: 0.01,
+        });
+        engine
+    }
+
+    fn order(index: usize, client: &str, operation: OrderType, price: u32, value: u32) -> NewOrder {
+        NewOrder {
+            index,
+            client_name: client.to_string(),
+            operation,
+            asset: "A".to_string(),
+            order_price: price,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_short_sale() {
+        let mut engine = engine();
+        engine
+            .enter(&order(1, "C2", OrderType::Buy, 10, 5))
+            .unwrap();
+        let trades = engine
+            .enter(&order(2, "C1", OrderType::Sell, 9, 5))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(engine.margin.loans["C1"].units["A"], 5);
+        let client = engine.clients.get("C1").unwrap();
+        assert_eq!(client.dollar_balance, 250);
+        assert_eq!(engine.margin.equity(client, &engine.quotes), 200.0);
+
+        // Owing 250 at the last price of 10 is over what 200 of equity allows.
+        assert!(matches!(
+            engine.enter(&order(3, "C1", OrderType::Sell, 9, 20)),
+            Err(TradeMatchErrors::MarginError(MarginErrors::LeverageError(owed, allowed)))
+                if owed == 250.0 && allowed == 200.0
+        ));
+        assert!(engine.get_order(3).is_none());
+
+        // A cash client is never lent anything.
+        let short = engine
+            .resolve(&order(4, "C2", OrderType::Sell, 9, 100))
+            .unwrap();
+        assert!(engine
+            .margin
+            .borrow(
+                engine.clients.get_mut("C2").unwrap(),
+                &short,
+                &engine.quotes
+            )
+            .is_ok());
+        assert!(!engine.margin.loans.contains_key("C2"));
+
+        // Interest is charged on the 50 owed and paid out of the dollars.
+        assert!(engine.end_session().unwrap().is_empty());
+        let loan = &engine.margin.loans["C1"];
+        assert_eq!((loan.dollars, loan.interest), (0, 1));
+        assert_eq!(engine.clients.get("C1").unwrap().dollar_balance, 249);
+        assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+        // The lender is square again and the interest is booked on its own.
+        assert_eq!(engine.ledger.balance(Holder::Lender, Unit::Dollars), 0);
+        assert_eq!(engine.ledger.balance(Holder::Interest, Unit::Dollars), 1);
+    }
+
+    #[test]
+    fn test_resting_order_borrows_when_filled() {
+        let mut engine = engine();
+        // Nothing is owed for a buy that only rests.
+        assert!(engine
+            .enter(&order(1, "C1", OrderType::Buy, 30, 10))
+            .unwrap()
+            .is_empty());
+        assert_eq!(engine.margin.loans["C1"], Loan::default());
+        assert_eq!(engine.clients.get("C1").unwrap().dollar_balance, 200);
+
+        // It borrows the 100 dollars it is short once a sell meets it.
+        let trades = engine
+            .enter(&order(2, "C3", OrderType::Sell, 29, 10))
+            .unwrap();
+        assert_eq!((trades[0].price, trades[0].volume), (30, 10));
+        assert_eq!(engine.margin.loans["C1"].dollars, 100);
+        let client = engine.clients.get("C1").unwrap();
+        assert_eq!(client.dollar_balance, 0);
+        assert_eq!(client.asset_balances.get("A").unwrap().balance, 10);
+        assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+    }
+
+    #[test]
+    fn test_liquidation() {
+        let mut engine = engine();
+        engine
+            .enter(&order(1, "C2", OrderType::Buy, 10, 5))
+            .unwrap();
+        engine
+            .enter(&order(2, "C1", OrderType::Sell, 9, 5))
+            .unwrap();
+        engine.end_session().unwrap();
+
+        // The price rises to 45: 249 dollars less 225 owed is under a quarter
+        // of 225, so the short is bought back from what C3 still offers.
+        engine
+            .enter(&order(3, "C3", OrderType::Sell, 45, 10))
+            .unwrap();
+        let trades = engine
+            .enter(&order(4, "C2", OrderType::Buy, 45, 1))
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+        let liquidation = &trades[1];
+        assert_eq!(
+            (&*liquidation.buyer, liquidation.price, liquidation.volume),
+            ("C1", 45, 5)
+        );
+        assert_eq!(liquidation.buy_index, usize::MAX);
+        assert!(engine.get_order(usize::MAX).is_none());
+
+        // The next order the caller numbers is not mistaken for it.
+        let trades = engine
+            .enter(&order(5, "C2", OrderType::Buy, 45, 2))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].buy_index, trades[0].sell_index), (5, 3));
+        assert_eq!(engine.get_order(3).unwrap().value, 2);
+
+        assert_eq!(engine.margin.calls.len(), 1);
+        assert_eq!(engine.margin.calls[0].client, "C1");
+        let loan = &engine.margin.loans["C1"];
+        assert!(loan.units.is_empty());
+        assert_eq!(loan.dollars, 0);
+        let client = engine.clients.get("C1").unwrap();
+        assert_eq!(client.dollar_balance, 24);
+        assert_eq!(client.asset_balances.get("A").unwrap().balance, 0);
+        // The borrowed units came and went through the position as well.
+        assert_eq!(client.pnl.positions["A"].quantity(), 0);
+        assert_eq!(
+            engine.margin.report(&engine.clients, &engine.quotes)[0].equity,
+            24.0
+        );
+        // Loans and repayments are in the ledger along with the fills.
+        assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+        let a = Unit::of(&engine.clients.registry, "A").unwrap();
+        assert_eq!(engine.ledger.balance(Holder::Lender, a), 0);
+    }
+}
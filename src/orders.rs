@@ -1,39 +1,107 @@
-use crate::{errors::OrderErrors, DataParser};
+use crate::{
+    errors::OrderErrors,
+    formats::Record,
+    symbols::{Registry, Symbol},
+    DataParser, Price, Volume,
+};
+use serde::{Deserialize, Serialize};
 use std::{collections::BTreeMap, str::FromStr};
 
-#[derive(Debug, Clone)]
+// Resting orders of one side of the books, by index. The number of orders
+// and the volume resting at each price of each asset are kept alongside, so
+// the best price is read off the end of a sorted map instead of found by
+// scanning the book. Change the book through its methods to keep them in step.
+#[derive(Debug, Clone, Default)]
 pub struct Orders {
     pub order: BTreeMap<usize, Order>,
+    levels: BTreeMap<(Symbol, Price), (usize, u64)>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// An order in the engine. Its client and asset go by the ids the clients'
+// registry gave their names, which are only looked up again for output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Order {
     pub index: usize,
+    pub client_id: Symbol,
+    pub operation: OrderType,
+    pub asset_id: Symbol,
+    pub order_price: u32,
+    pub value: u32,
+}
+
+// Orders as they are read, by line number.
+#[derive(Debug, Clone)]
+pub struct NewOrders {
+    pub order: BTreeMap<usize, NewOrder>,
+}
+
+// An order as it is entered, naming its client and asset. The engine
+// resolves the names before it reaches the books. In JSON the fields are
+// named like the CSV columns and the index is the line number, as in the
+// text format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewOrder {
+    #[serde(skip)]
+    pub index: usize,
+    #[serde(rename = "client")]
     pub client_name: String,
     pub operation: OrderType,
     pub asset: String,
+    #[serde(rename = "price")]
     pub order_price: u32,
+    #[serde(rename = "volume")]
     pub value: u32,
 }
 
 type Result<T> = std::result::Result<T, OrderErrors>;
 
 // types of orders
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OrderType {
+    #[serde(alias = "b")]
     Buy,
+    #[serde(alias = "s")]
     Sell,
+    #[serde(skip)]
     IsNotOrderType,
 }
 
 impl Orders {
-    //get mut from order btreemap
-    pub fn get_mut<T>(&mut self, order_index: T) -> Option<&mut Order>
-    where
-        T: Into<usize>,
-    {
-        let order_index = &order_index.into();
-        self.order.get_mut(order_index)
+    pub fn new() -> Orders {
+        Orders::default()
+    }
+
+    pub fn insert(&mut self, index: usize, order: Order) {
+        if let Some(replaced) = self.order.insert(index, order) {
+            self.leave(&replaced);
+        }
+        self.join(&order);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Order> {
+        let order = self.order.remove(&index)?;
+        self.leave(&order);
+        Some(order)
+    }
+
+    // Moves every order of `other` into this book.
+    pub fn append(&mut self, other: Orders) {
+        for (index, order) in other.order {
+            self.insert(index, order);
+        }
+    }
+
+    // Sets the volume still resting of an order, as left by a partial fill.
+    pub fn set_value(&mut self, index: usize, value: Volume) {
+        if let Some(order) = self.order.get_mut(&index) {
+            let level = self
+                .levels
+                .get_mut(&(order.asset_id, order.order_price))
+                .unwrap();
+            level.1 = level.1 - u64::from(order.value) + u64::from(value);
+            order.value = value;
+        }
     }
 
     //get from order btreemap
@@ -42,25 +110,64 @@ impl Orders {
         T: Into<usize>,
     {
         let order_index = &order_index.into();
-        Some(self.order.get(order_index)?.clone())
+        self.order.get(order_index).copied()
     }
 
-    //A function that updates the values ​​of unfulfilled orders 
+    //A function that updates the values ​​of unfulfilled orders
     pub fn update_orders(&mut self, updated_orders: Vec<Order>) {
         for updated_order in updated_orders {
-            let mut order = self.get_mut(updated_order.index).unwrap();
-            order.value = updated_order.value;
+            self.set_value(updated_order.index, updated_order.value);
+        }
+    }
+
+    // Highest price an asset rests at and the volume resting there, capped at
+    // the largest Volume.
+    pub fn highest(&self, asset: Symbol) -> Option<(Price, Volume)> {
+        self.asset_levels(asset).next_back().map(level)
+    }
+
+    // Lowest price an asset rests at and the volume resting there.
+    pub fn lowest(&self, asset: Symbol) -> Option<(Price, Volume)> {
+        self.asset_levels(asset).next().map(level)
+    }
+
+    fn asset_levels(
+        &self,
+        asset: Symbol,
+    ) -> impl DoubleEndedIterator<Item = (&(Symbol, Price), &(usize, u64))> {
+        self.levels.range((asset, Price::MIN)..=(asset, Price::MAX))
+    }
+
+    fn join(&mut self, order: &Order) {
+        let level = self
+            .levels
+            .entry((order.asset_id, order.order_price))
+            .or_default();
+        level.0 += 1;
+        level.1 += u64::from(order.value);
+    }
+
+    fn leave(&mut self, order: &Order) {
+        let key = (order.asset_id, order.order_price);
+        let level = self.levels.get_mut(&key).unwrap();
+        level.0 -= 1;
+        level.1 -= u64::from(order.value);
+        if level.0 == 0 {
+            self.levels.remove(&key);
         }
     }
 }
 
+fn level((&(_, price), &(_, volume)): (&(Symbol, Price), &(usize, u64))) -> (Price, Volume) {
+    (price, Volume::try_from(volume).unwrap_or(Volume::MAX))
+}
 
-impl DataParser for Orders {
-    type Item = Order;
+impl DataParser for NewOrders {
+    type Item = NewOrder;
     type Err = OrderErrors;
 
-    fn new() -> Orders {
-        Orders {
+    fn new() -> NewOrders {
+        NewOrders {
             order: BTreeMap::new(),
         }
     }
@@ -71,7 +178,15 @@ impl DataParser for Orders {
     }
 
     fn parse(line: &str) -> Result<Self::Item> {
-        Order::from_str(line)
+        NewOrder::from_str(line)
+    }
+
+    fn parse_record(record: &Record) -> Result<Self::Item> {
+        NewOrder::from_record(record)
+    }
+
+    fn parse_json(line: &str) -> Result<Self::Item> {
+        serde_json::from_str(line).map_err(|error| OrderErrors::ParseJsonError(error.to_string()))
     }
 
     fn remove(&mut self, orders: Vec<Self::Item>) {
@@ -81,10 +196,10 @@ impl DataParser for Orders {
     }
 }
 
-// FromStr impl for Order struct.
-impl FromStr for Order {
+// FromStr impl for NewOrder struct.
+impl FromStr for NewOrder {
     type Err = OrderErrors;
-    // Converting a String to an Order Structure
+    // Converting a String to a NewOrder Structure
     fn from_str(s: &str) -> Result<Self> {
         use OrderErrors::*;
         let vals: Vec<&str> = s.split_whitespace().collect();
@@ -112,7 +227,7 @@ impl FromStr for Order {
         let order_price = vals[3].parse::<u32>().map_err(|_| ParseItemPriceError)?;
         let value = vals[4].parse::<u32>().map_err(|_| ParseItemVolumeError)?;
 
-        Ok(Order {
+        Ok(NewOrder {
             index,
             client_name,
             operation,
@@ -123,6 +238,82 @@ impl FromStr for Order {
     }
 }
 
+impl NewOrder {
+    // Converting a CSV row with client, operation, asset, price and volume
+    // columns. The operation may also be spelled out as buy or sell.
+    pub fn from_record(record: &Record) -> Result<Self> {
+        use OrderErrors::*;
+        let field = |column| record.get(column).ok_or(ParseInsufficentInputError);
+
+        let client_name = field("client")?.to_string();
+        if client_name.is_empty() {
+            return Err(ParseClientIdError);
+        }
+        let operation = match field("operation")?.to_ascii_lowercase().as_str() {
+            "b" | "buy" => OrderType::Buy,
+            "s" | "sell" => OrderType::Sell,
+            _ => return Err(NoSuchOperationSymbolError),
+        };
+        let asset = field("asset")?.to_string();
+        if asset.is_empty() {
+            return Err(ParseSymbolError);
+        }
+        let order_price = field("price")?
+            .parse::<u32>()
+            .map_err(|_| ParseItemPriceError)?;
+        let value = field("volume")?
+            .parse::<u32>()
+            .map_err(|_| ParseItemVolumeError)?;
+
+        Ok(NewOrder {
+            index: 0,
+            client_name,
+            operation,
+            asset,
+            order_price,
+            value,
+        })
+    }
+}
+
+// The symbol of the text format.
+impl std::fmt::Display for OrderType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderType::Buy => write!(f, "b"),
+            OrderType::Sell => write!(f, "s"),
+            OrderType::IsNotOrderType => write!(f, "?"),
+        }
+    }
+}
+
+// A line of the orders file.
+impl std::fmt::Display for NewOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.client_name, self.operation, self.asset, self.order_price, self.value
+        )
+    }
+}
+
+impl Order {
+    // The order as it was entered, with its names looked up in the registry
+    // that gave its ids.
+    pub fn named(&self, registry: &Registry) -> NewOrder {
+        let name = |name: Option<&std::sync::Arc<str>>| name.map_or("?", |name| name).to_string();
+        NewOrder {
+            index: self.index,
+            client_name: name(registry.clients.name(self.client_id)),
+            operation: self.operation,
+            asset: name(registry.assets.name(self.asset_id)),
+            order_price: self.order_price,
+            value: self.value,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,19 +321,20 @@ mod tests {
     #[test]
     fn test_from_str() {
         let base_string = "C5    b    C    15    4";
-        let struct_exemplar = Order::from_str(base_string).unwrap();
+        let struct_exemplar = NewOrder::from_str(base_string).unwrap();
 
         assert_eq!(struct_exemplar.client_name, "C5");
         assert_eq!(struct_exemplar.operation, OrderType::Buy);
         assert_eq!(struct_exemplar.asset, "C");
         assert_eq!(struct_exemplar.order_price, 15);
         assert_eq!(struct_exemplar.value, 4);
+        assert_eq!(struct_exemplar.to_string(), "C5\tb\tC\t15\t4\n");
     }
 
     #[test]
     fn test_parse_insufficent_error_from_str() {
         let base_string = "C5    b    C    15    ";
-        let actual_error = Order::from_str(base_string).unwrap_err();
+        let actual_error = NewOrder::from_str(base_string).unwrap_err();
         let expected_error = OrderErrors::ParseInsufficentInputError;
         assert_eq!(actual_error, expected_error);
     }
@@ -150,7 +342,7 @@ mod tests {
     #[test]
     fn test_parse_operation_error() {
         let base_string = "C5    24    C    15    4";
-        let actual_error = Order::from_str(base_string).unwrap_err();
+        let actual_error = NewOrder::from_str(base_string).unwrap_err();
         let expected_error = OrderErrors::ParseOperationError;
         assert_eq!(actual_error, expected_error);
     }
@@ -158,7 +350,7 @@ mod tests {
     #[test]
     fn test_no_such_operation_error() {
         let base_string = "C5    c    C    15    4";
-        let actual_error = Order::from_str(base_string).unwrap_err();
+        let actual_error = NewOrder::from_str(base_string).unwrap_err();
         let expected_error = OrderErrors::NoSuchOperationSymbolError;
         assert_eq!(actual_error, expected_error);
     }
@@ -166,7 +358,7 @@ mod tests {
     #[test]
     fn test_item_price_error() {
         let base_string = "C5    b    C    a    4";
-        let actual_error = Order::from_str(base_string).unwrap_err();
+        let actual_error = NewOrder::from_str(base_string).unwrap_err();
         let expected_error = OrderErrors::ParseItemPriceError;
         assert_eq!(actual_error, expected_error);
     }
@@ -174,8 +366,22 @@ mod tests {
     #[test]
     fn test_item_volume_error() {
         let base_string = "C5    b    C    15    a";
-        let actual_error = Order::from_str(base_string).unwrap_err();
+        let actual_error = NewOrder::from_str(base_string).unwrap_err();
         let expected_error = OrderErrors::ParseItemVolumeError;
         assert_eq!(actual_error, expected_error);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_from_record_errors() {
+        let headers = csv::StringRecord::from(vec!["client", "operation", "asset", "price"]);
+        let record = csv::StringRecord::from(vec!["C5", "sell", "C", "15"]);
+        let actual_error = NewOrder::from_record(&Record::new(&headers, &record)).unwrap_err();
+        assert_eq!(actual_error, OrderErrors::ParseInsufficentInputError);
+
+        let headers =
+            csv::StringRecord::from(vec!["client", "operation", "asset", "price", "volume"]);
+        let record = csv::StringRecord::from(vec!["C5", "hold", "C", "15", "4"]);
+        let actual_error = NewOrder::from_record(&Record::new(&headers, &record)).unwrap_err();
+        assert_eq!(actual_error, OrderErrors::NoSuchOperationSymbolError);
+    }
+}
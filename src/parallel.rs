@@ -0,0 +1,360 @@
+use crate::{
+    engine::{self, Engine, Matched, Trade},
+    errors::TradeMatchErrors,
+    orders::{NewOrder, NewOrders, Order, OrderType, Orders},
+    symbols::Symbol,
+    Volume,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+    sync::mpsc,
+    thread,
+};
+
+// Multi-threaded matching. Every asset's books live on a worker thread of
+// their own, which picks the candidates an order may fill against. Balances
+// are shared between assets, so the fills themselves are settled by a single
+// account service on the calling thread, strictly in input order. Each book
+// sees the same orders in the same order and every balance check sees the
+// same balances as on one thread, so the results are identical to
+// Engine::stream over the same orders.
+
+// An order sent to the account service with the candidates its worker found.
+struct Settle {
+    sequence: usize,
+    shard: usize,
+    order: Order,
+    candidates: Vec<Order>,
+}
+
+// What the account service sends back: the order with its remaining volume,
+// the indexes of the resting orders it completed and the remaining volume of
+// those it partially filled.
+type Settled = (Order, Vec<usize>, Vec<(usize, Volume)>);
+
+// Fills of every order in turn, or why it was rejected, as Engine::stream
+// would give them.
+pub fn stream<I>(engine: &mut Engine, orders: I) -> Vec<Result<Vec<Trade>, TradeMatchErrors>>
+where
+    I: IntoIterator<Item = NewOrder>,
+{
+    // Admission does not depend on earlier fills, so rejects are known before
+    // any matching starts. Risk limits that do, and margin accounts, have to
+    // see every earlier fill.
+    if engine.risk.depends_on_fills() || engine.margin.is_active() {
+        return engine.stream(orders).collect();
+    }
+    let mut results = Vec::new();
+    let mut shards: BTreeMap<Symbol, Vec<(usize, Order)>> = BTreeMap::new();
+    let mut count = 0;
+    for (sequence, order) in orders.into_iter().enumerate() {
+        let admitted = engine
+            .resolve(&order)
+            .and_then(|order| engine.admit(&order).map(|()| order));
+        match admitted {
+            Ok(order) => {
+                results.push(Ok(Vec::new()));
+                shards
+                    .entry(order.asset_id)
+                    .or_default()
+                    .push((sequence, order));
+                count += 1;
+            }
+            Err(error) => results.push(Err(error)),
+        }
+    }
+
+    let assets: BTreeSet<Symbol> = shards.keys().copied().collect();
+    let mut books = split_books(engine, &assets);
+
+    thread::scope(|scope| {
+        let (requests, service) = mpsc::channel::<Settle>();
+        let mut replies = Vec::new();
+        let mut workers = Vec::new();
+        for (shard, (asset, orders)) in shards.into_iter().enumerate() {
+            let (reply, settled) = mpsc::channel::<Settled>();
+            replies.push(reply);
+            let requests = requests.clone();
+            let (mut buy_orders, mut sell_orders) = books.remove(&asset).unwrap();
+            workers.push(scope.spawn(move || {
+                for (sequence, order) in orders {
+                    let candidates = match order.operation {
+                        OrderType::Buy => engine::buy_candidates(&sell_orders, &order),
+                        _ => engine::sell_candidates(&buy_orders, &order),
+                    };
+                    let candidates = candidates.into_iter().copied().collect();
+                    requests
+                        .send(Settle {
+                            sequence,
+                            shard,
+                            order,
+                            candidates,
+                        })
+                        .unwrap();
+                    let (order, completed, updated) = settled.recv().unwrap();
+                    match order.operation {
+                        OrderType::Buy => engine::rest(
+                            &mut buy_orders,
+                            &mut sell_orders,
+                            order,
+                            completed,
+                            updated,
+                        ),
+                        _ => engine::rest(
+                            &mut sell_orders,
+                            &mut buy_orders,
+                            order,
+                            completed,
+                            updated,
+                        ),
+                    }
+                }
+                (asset, buy_orders, sell_orders)
+            }));
+        }
+        drop(requests);
+
+        // Workers run ahead on their own books, so requests are held back
+        // until every earlier order has been settled.
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for _ in 0..count {
+            let request = service.recv().unwrap();
+            pending.insert(request.sequence, request);
+            loop {
+                while results.get(next).is_some_and(|result| result.is_err()) {
+                    next += 1;
+                }
+                let Some(Settle {
+                    sequence,
+                    shard,
+                    mut order,
+                    candidates,
+                }) = pending.remove(&next)
+                else {
+                    break;
+                };
+                let Matched {
+                    completed,
+                    updated,
+                    trades,
+                } = match order.operation {
+                    OrderType::Buy => {
+                        engine::fill_buy(&candidates, &mut engine.clients, &mut order)
+                    }
+                    _ => engine::fill_sell(&candidates, &mut engine.clients, &mut order),
+                };
+                engine.record(&trades);
+                results[sequence] = Ok(trades);
+                replies[shard].send((order, completed, updated)).unwrap();
+                next += 1;
+            }
+        }
+
+        for worker in workers {
+            let (asset, buy_orders, sell_orders) = worker.join().unwrap();
+            engine.buy_orders.append(buy_orders);
+            engine.sell_orders.append(sell_orders);
+            engine.update_quote(asset);
+        }
+    });
+    results
+}
+
+// Same as Engine::match_orders: matches in index order and stops at the first
+// order that is rejected, without matching anything after it. Orders are
+// admitted before any is matched, which only holds while admission does not
+// depend on fills.
+pub fn match_orders(
+    engine: &mut Engine,
+    orders: NewOrders,
+) -> Result<Vec<Trade>, TradeMatchErrors> {
+    if engine.risk.depends_on_fills() || engine.margin.is_active() {
+        return engine.match_orders(orders);
+    }
+    let mut admitted = Vec::new();
+    let mut rejected = None;
+    for order in orders.order.into_values() {
+        let checked = engine
+            .resolve(&order)
+            .and_then(|resolved| engine.admit(&resolved));
+        match checked {
+            Ok(()) => admitted.push(order),
+            Err(error) => {
+                rejected = Some(error);
+                break;
+            }
+        }
+    }
+
+    let mut trades = Vec::new();
+    for fills in stream(engine, admitted) {
+        trades.append(&mut fills?);
+    }
+    match rejected {
+        Some(error) => Err(error),
+        None => Ok(trades),
+    }
+}
+
+// Takes the resting orders of each asset out of the engine's books.
+fn split_books(
+    engine: &mut Engine,
+    assets: &BTreeSet<Symbol>,
+) -> BTreeMap<Symbol, (Orders, Orders)> {
+    let mut books: BTreeMap<Symbol, (Orders, Orders)> = assets
+        .iter()
+        .map(|asset| (*asset, (Orders::new(), Orders::new())))
+        .collect();
+    let resting = mem::replace(&mut engine.buy_orders, Orders::new());
+    for (index, order) in resting.order {
+        match books.get_mut(&order.asset_id) {
+            Some((buy_orders, _)) => buy_orders.insert(index, order),
+            None => engine.buy_orders.insert(index, order),
+        }
+    }
+    let resting = mem::replace(&mut engine.sell_orders, Orders::new());
+    for (index, order) in resting.order {
+        match books.get_mut(&order.asset_id) {
+            Some((_, sell_orders)) => sell_orders.insert(index, order),
+            None => engine.sell_orders.insert(index, order),
+        }
+    }
+    books
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::{Client, Clients},
+        read_file,
+        risk::{Limits, Risk, RiskConfig},
+        DataParser,
+    };
+    use std::str::FromStr;
+
+    fn engine() -> Engine {
+        let clients: Clients = read_file("./Clients.txt".to_string()).unwrap();
+        Engine::new(clients)
+    }
+
+    fn orders() -> NewOrders {
+        read_file("./Orders.txt".to_string()).unwrap()
+    }
+
+    fn assert_same(sequential: &Engine, parallel: &Engine) {
+        assert_eq!(
+            format!("{:?}", sequential.clients),
+            format!("{:?}", parallel.clients)
+        );
+        assert_eq!(sequential.buy_orders.order, parallel.buy_orders.order);
+        assert_eq!(sequential.sell_orders.order, parallel.sell_orders.order);
+        assert_eq!(sequential.quotes.quote, parallel.quotes.quote);
+    }
+
+    #[test]
+    fn test_match_orders() {
+        let mut sequential = engine();
+        let expected = sequential.match_orders(orders()).unwrap();
+        let mut parallel = engine();
+        let trades = match_orders(&mut parallel, orders()).unwrap();
+
+        assert!(!trades.is_empty());
+        assert_eq!(trades, expected);
+        assert_same(&sequential, &parallel);
+    }
+
+    #[test]
+    fn test_match_orders_with_max_position() {
+        // C1 may hold 5 units of A and already does, so its buy is only
+        // admitted once its sell has filled.
+        let mut clients = Clients::new();
+        for (index, line) in ["C1\t1000\t5\t0\t0\t0", "C2\t1000\t0\t0\t0\t0"]
+            .iter()
+            .enumerate()
+        {
+            clients.insert(index + 1, Client::from_str(line).unwrap());
+        }
+        let mut orders = NewOrders::new();
+        for (index, line) in ["C2\tb\tA\t10\t5", "C1\ts\tA\t9\t5", "C1\tb\tA\t10\t1"]
+            .iter()
+            .enumerate()
+        {
+            orders.insert(index + 1, NewOrders::parse(line).unwrap());
+        }
+        let risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_position: Some(5),
+                ..Limits::default()
+            },
+            ..RiskConfig::default()
+        });
+
+        let mut sequential = Engine::new(clients.clone());
+        sequential.risk = risk.clone();
+        let expected = sequential.match_orders(orders.clone());
+        let mut parallel = Engine::new(clients);
+        parallel.risk = risk;
+        let results = match_orders(&mut parallel, orders);
+
+        assert_eq!(expected.as_ref().map(Vec::len).ok(), Some(1));
+        assert_eq!(format!("{:?}", results), format!("{:?}", expected));
+        assert_same(&sequential, &parallel);
+    }
+
+    #[test]
+    fn test_stream_with_rejects() {
+        let mut orders: Vec<NewOrder> = orders().order.into_values().collect();
+        orders[3].client_name = "C99".to_string();
+        orders[10].asset = "Z".to_string();
+
+        let mut sequential = engine();
+        let expected: Vec<String> = sequential
+            .stream(orders.clone())
+            .map(|result| format!("{:?}", result))
+            .collect();
+        let mut parallel = engine();
+        let results: Vec<String> = stream(&mut parallel, orders)
+            .into_iter()
+            .map(|result| format!("{:?}", result))
+            .collect();
+
+        assert_eq!(results, expected);
+        assert!(results[3].starts_with("Err"));
+        assert_same(&sequential, &parallel);
+    }
+
+    #[test]
+    fn test_stream_with_risk_limits() {
+        let orders: Vec<NewOrder> = orders().order.into_values().take(500).collect();
+        let risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_open_orders: Some(3),
+                max_order_volume: Some(8),
+                ..Limits::default()
+            },
+            ..RiskConfig::default()
+        });
+
+        let mut sequential = engine();
+        sequential.risk = risk.clone();
+        let expected: Vec<String> = sequential
+            .stream(orders.clone())
+            .map(|result| format!("{:?}", result))
+            .collect();
+        let mut parallel = engine();
+        parallel.risk = risk;
+        let results: Vec<String> = stream(&mut parallel, orders)
+            .into_iter()
+            .map(|result| format!("{:?}", result))
+            .collect();
+
+        assert_eq!(results, expected);
+        assert!(results
+            .iter()
+            .any(|result| result.contains("OpenOrdersError")));
+        assert_same(&sequential, &parallel);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    clients::{Assets, Clients},
+    quotes::Quotes,
+    Price, Volume,
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+
+pub const PNL_HEADER: &str = "client\tasset\tposition\tavg_cost\trealized\tunrealized";
+
+// How the cost of sold units is determined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostMethod {
+    #[default]
+    Fifo,
+    Average,
+}
+
+// Units bought together at one cost. Opening inventory from the clients file
+// has no known cost and is priced at the asset's first trade of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub volume: Volume,
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    pub lots: VecDeque<Lot>,
+    pub realized: f64,
+}
+
+// Cost basis and P&L of one client across assets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pnl {
+    pub method: CostMethod,
+    pub positions: BTreeMap<String, Position>,
+}
+
+impl Position {
+    pub fn quantity(&self) -> u64 {
+        self.lots.iter().map(|lot| u64::from(lot.volume)).sum()
+    }
+
+    // Total cost of the units held, pricing opening inventory at `opening`.
+    pub fn cost_basis(&self, opening: Price) -> f64 {
+        self.lots
+            .iter()
+            .map(|lot| f64::from(lot.volume) * lot.cost.unwrap_or(f64::from(opening)))
+            .sum()
+    }
+
+    pub fn average_cost(&self, opening: Price) -> f64 {
+        match self.quantity() {
+            0 => 0.0,
+            quantity => self.cost_basis(opening) / quantity as f64,
+        }
+    }
+
+    fn price_opening(&mut self, opening: Price) {
+        for lot in self.lots.iter_mut() {
+            lot.cost.get_or_insert(f64::from(opening));
+        }
+    }
+}
+
+impl Pnl {
+    // Starts with the client's balances as unpriced opening inventory.
+    pub fn open(assets: &Assets, method: CostMethod) -> Pnl {
+        let positions = assets
+            .asset
+            .values()
+            .map(|asset| {
+                let mut position = Position::default();
+                if asset.balance > 0 {
+                    position.lots.push_back(Lot {
+                        volume: asset.balance,
+                        cost: None,
+                    });
+                }
+                (asset.symbol.clone(), position)
+            })
+            .collect();
+        Pnl { method, positions }
+    }
+
+    pub fn buy(&mut self, asset: &str, price: Price, volume: Volume, opening: Price) {
+        let method = self.method;
+        let position = self.positions.entry(asset.to_string()).or_default();
+        position.price_opening(opening);
+        match method {
+            CostMethod::Fifo => position.lots.push_back(Lot {
+                volume,
+                cost: Some(f64::from(price)),
+            }),
+            CostMethod::Average => {
+                let quantity = position.quantity() + u64::from(volume);
+                let cost = position.cost_basis(opening) + f64::from(price) * f64::from(volume);
+                position.lots.clear();
+                position.lots.push_back(Lot {
+                    volume: quantity as Volume,
+                    cost: Some(cost / quantity as f64),
+                });
+            }
+        }
+    }
+
+    // Removes the sold units from the oldest lots and realizes the difference
+    // between sale price and their cost.
+    pub fn sell(&mut self, asset: &str, price: Price, volume: Volume, opening: Price) {
+        let position = self.positions.entry(asset.to_string()).or_default();
+        position.price_opening(opening);
+        let mut remaining = volume;
+        while remaining > 0 {
+            let lot = match position.lots.front_mut() {
+                Some(lot) => lot,
+                None => {
+                    // Nothing recorded for these units: treat them as opening inventory.
+                    position.realized +=
+                        (f64::from(price) - f64::from(opening)) * f64::from(remaining);
+                    break;
+                }
+            };
+            let sold = remaining.min(lot.volume);
+            let cost = lot.cost.unwrap_or(f64::from(opening));
+            position.realized += (f64::from(price) - cost) * f64::from(sold);
+            lot.volume -= sold;
+            remaining -= sold;
+            if lot.volume == 0 {
+                position.lots.pop_front();
+            }
+        }
+    }
+
+    // Units paid in join the position at no known cost, like opening
+    // inventory, and are priced at the asset's first trade.
+    pub fn deposit(&mut self, asset: &str, volume: Volume) {
+        let position = self.positions.entry(asset.to_string()).or_default();
+        position.lots.push_back(Lot { volume, cost: None });
+    }
+
+    // Units paid out leave the oldest lots at their cost, realizing nothing.
+    pub fn withdraw(&mut self, asset: &str, volume: Volume) {
+        let Some(position) = self.positions.get_mut(asset) else {
+            return;
+        };
+        let mut remaining = volume;
+        while let Some(lot) = position.lots.front_mut() {
+            let taken = remaining.min(lot.volume);
+            lot.volume -= taken;
+            remaining -= taken;
+            if lot.volume == 0 {
+                position.lots.pop_front();
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    pub fn realized(&self) -> f64 {
+        self.positions
+            .values()
+            .map(|position| position.realized)
+            .sum()
+    }
+
+    // Value of the held units at `mark` minus their cost. Assets that never
+    // traded have no mark and contribute nothing.
+    pub fn unrealized(&self, asset: &str, mark: Option<Price>, opening: Option<Price>) -> f64 {
+        match (self.positions.get(asset), mark, opening) {
+            (Some(position), Some(mark), Some(opening)) => {
+                position.quantity() as f64 * f64::from(mark) - position.cost_basis(opening)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // Unrealized P&L of every position marked to its asset's last trade price.
+    pub fn unrealized_at(&self, quotes: &Quotes) -> f64 {
+        self.positions
+            .keys()
+            .map(|asset| {
+                let quote = quotes.get(asset);
+                let last_price = quote.as_ref().and_then(|quote| quote.last_price);
+                let open_price = quote.and_then(|quote| quote.open_price);
+                self.unrealized(asset, last_price, open_price)
+            })
+            .sum()
+    }
+}
+
+// One line of the end-of-run P&L report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlLine {
+    pub client: String,
+    pub asset: String,
+    pub position: u64,
+    pub average_cost: f64,
+    pub realized: f64,
+    pub unrealized: f64,
+}
+
+// P&L of every client and asset, marked to the last trade prices in `quotes`.
+pub fn report(clients: &Clients, quotes: &Quotes) -> Vec<PnlLine> {
+    let mut lines = Vec::new();
+    for client in clients.iter() {
+        for (asset, position) in &client.pnl.positions {
+            let quote = quotes.get(asset);
+            let last_price = quote.as_ref().and_then(|quote| quote.last_price);
+            let open_price = quote.and_then(|quote| quote.open_price);
+            lines.push(PnlLine {
+                client: client.name.clone(),
+                asset: asset.clone(),
+                position: position.quantity(),
+                average_cost: open_price
+                    .map(|open_price| position.average_cost(open_price))
+                    .unwrap_or_default(),
+                realized: position.realized,
+                unrealized: client.pnl.unrealized(asset, last_price, open_price),
+            });
+        }
+    }
+    lines
+}
+
+// Preparing a P&L line for recording.
+impl std::fmt::Display for PnlLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{:.4}\t{:.2}\t{:.2}",
+            self.client,
+            self.asset,
+            self.position,
+            self.average_cost,
+            self.realized,
+            self.unrealized
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Asset;
+
+    fn opening(balance: u32, method: CostMethod) -> Pnl {
+        let mut assets = Assets::new();
+        assets.insert(Asset {
+            symbol: "A".to_string(),
+            balance,
+        });
+        Pnl::open(&assets, method)
+    }
+
+    #[test]
+    fn test_fifo() {
+        let mut pnl = opening(10, CostMethod::Fifo);
+        pnl.buy("A", 12, 5, 10);
+        pnl.sell("A", 15, 12, 10);
+
+        // 10 opening units at 10, then 2 of the units bought at 12.
+        assert_eq!(pnl.realized(), 56.0);
+        let position = &pnl.positions["A"];
+        assert_eq!(position.quantity(), 3);
+        assert_eq!(position.average_cost(10), 12.0);
+        assert_eq!(pnl.unrealized("A", Some(14), Some(10)), 6.0);
+    }
+
+    #[test]
+    fn test_average_cost() {
+        let mut pnl = opening(10, CostMethod::Average);
+        pnl.buy("A", 13, 5, 10);
+        assert_eq!(pnl.positions["A"].average_cost(10), 11.0);
+
+        pnl.sell("A", 12, 3, 10);
+        assert_eq!(pnl.realized(), 3.0);
+        assert_eq!(pnl.positions["A"].quantity(), 12);
+        assert_eq!(pnl.unrealized("A", Some(11), Some(10)), 0.0);
+    }
+
+    #[test]
+    fn test_unpriced_opening_inventory() {
+        let pnl = opening(10, CostMethod::Fifo);
+        assert_eq!(pnl.unrealized("A", None, None), 0.0);
+        assert_eq!(pnl.positions["A"].cost_basis(7), 70.0);
+    }
+}
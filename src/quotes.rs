@@ -0,0 +1,159 @@
+use crate::{engine::Trade, orders::Orders, symbols::Symbol, Price, Volume};
+use std::collections::BTreeMap;
+
+// Level 1 view of an asset: top of book, last trade and traded volume.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quote {
+    pub asset: String,
+    pub bid_price: Option<Price>,
+    pub bid_size: Volume,
+    pub ask_price: Option<Price>,
+    pub ask_size: Volume,
+    pub open_price: Option<Price>,
+    pub last_price: Option<Price>,
+    pub last_size: Volume,
+    pub volume: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Quotes {
+    pub quote: BTreeMap<String, Quote>,
+}
+
+impl Quotes {
+    pub fn new() -> Quotes {
+        Quotes {
+            quote: BTreeMap::new(),
+        }
+    }
+
+    pub fn get<T>(&self, asset: T) -> Option<Quote>
+    where
+        T: Into<String>,
+    {
+        let asset = &asset.into();
+        Some(self.quote.get(asset)?.clone())
+    }
+
+    // Brings best bid and ask of an asset, named and by its id, up to date
+    // with the price levels of the resting books.
+    pub fn update_book(
+        &mut self,
+        asset: &str,
+        id: Symbol,
+        buy_orders: &Orders,
+        sell_orders: &Orders,
+    ) {
+        let bid = buy_orders.highest(id);
+        let ask = sell_orders.lowest(id);
+        let quote = self.entry(asset);
+        quote.bid_price = bid.map(|(price, _)| price);
+        quote.bid_size = bid.map_or(0, |(_, size)| size);
+        quote.ask_price = ask.map(|(price, _)| price);
+        quote.ask_size = ask.map_or(0, |(_, size)| size);
+    }
+
+    pub fn record_trades(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            let quote = self.entry(&trade.asset);
+            quote.open_price.get_or_insert(trade.price);
+            quote.last_price = Some(trade.price);
+            quote.last_size = trade.volume;
+            quote.volume += u64::from(trade.volume);
+        }
+    }
+
+    // Starts a new trading day: traded volume goes back to zero.
+    pub fn reset_volume(&mut self) {
+        for quote in self.quote.values_mut() {
+            quote.volume = 0;
+        }
+    }
+
+    fn entry(&mut self, asset: &str) -> &mut Quote {
+        self.quote
+            .entry(asset.to_string())
+            .or_insert_with(|| Quote {
+                asset: asset.to_string(),
+                ..Quote::default()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::{Order, OrderType};
+
+    fn order(index: usize, operation: OrderType, price: u32, value: u32) -> Order {
+        Order {
+            index,
+            client_id: Symbol::default(),
+            operation,
+            asset_id: Symbol::default(),
+            order_price: price,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_update_book() {
+        let mut buy_orders = Orders::new();
+        let mut sell_orders = Orders::new();
+        buy_orders.insert(1, order(1, OrderType::Buy, 10, 3));
+        buy_orders.insert(2, order(2, OrderType::Buy, 11, 1));
+        buy_orders.insert(3, order(3, OrderType::Buy, 11, 2));
+        sell_orders.insert(4, order(4, OrderType::Sell, 14, 6));
+
+        let mut quotes = Quotes::new();
+        quotes.update_book("A", Symbol::default(), &buy_orders, &sell_orders);
+        let quote = quotes.get("A").unwrap();
+
+        assert_eq!(quote.bid_price, Some(11));
+        assert_eq!(quote.bid_size, 3);
+        assert_eq!(quote.ask_price, Some(14));
+        assert_eq!(quote.ask_size, 6);
+        assert_eq!(quote.last_price, None);
+
+        // Levels follow fills and cancels.
+        buy_orders.remove(2);
+        sell_orders.set_value(4, 2);
+        quotes.update_book("A", Symbol::default(), &buy_orders, &sell_orders);
+        let quote = quotes.get("A").unwrap();
+        assert_eq!((quote.bid_price, quote.bid_size), (Some(11), 2));
+        assert_eq!((quote.ask_price, quote.ask_size), (Some(14), 2));
+
+        buy_orders.remove(3);
+        sell_orders.remove(4);
+        quotes.update_book("A", Symbol::default(), &buy_orders, &sell_orders);
+        let quote = quotes.get("A").unwrap();
+        assert_eq!((quote.bid_price, quote.bid_size), (Some(10), 3));
+        assert_eq!((quote.ask_price, quote.ask_size), (None, 0));
+    }
+
+    #[test]
+    fn test_record_trades() {
+        let trade = Trade {
+            buy_index: 1,
+            sell_index: 2,
+            buyer: "C1".into(),
+            seller: "C2".into(),
+            asset: "A".into(),
+            price: 12,
+            volume: 4,
+            buy_limit: 12,
+            sell_limit: 12,
+        };
+        let mut quotes = Quotes::new();
+        quotes.record_trades(&[trade.clone(), Trade { price: 13, ..trade }]);
+        let quote = quotes.get("A").unwrap();
+
+        assert_eq!(quote.open_price, Some(12));
+        assert_eq!(quote.last_price, Some(13));
+        assert_eq!(quote.last_size, 4);
+        assert_eq!(quote.volume, 8);
+
+        quotes.reset_volume();
+        assert_eq!(quotes.get("A").unwrap().volume, 0);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{engine::Trade, orders::OrderType};
+use std::{collections::BTreeMap, sync::Arc};
+
+pub const REPORT_HEADER: &str = "client\tasset\tside\tvolume\tavg_price\tmarket_vwap\tslippage";
+
+// Execution quality of one client's fills on one side of one asset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionQuality {
+    pub client: String,
+    pub asset: String,
+    pub operation: OrderType,
+    pub volume: u64,
+    pub notional: u64,
+    // Sum of (fill price - limit) * volume for buys and (limit - fill price) * volume
+    // for sells: how much worse than the limit the fills were, so negative
+    // values are price improvement.
+    pub slippage: i64,
+    pub market_vwap: f64,
+}
+
+impl ExecutionQuality {
+    pub fn average_price(&self) -> f64 {
+        self.notional as f64 / self.volume as f64
+    }
+
+    pub fn average_slippage(&self) -> f64 {
+        self.slippage as f64 / self.volume as f64
+    }
+}
+
+// Volume weighted average trade price of every asset.
+pub fn vwap(trades: &[Trade]) -> BTreeMap<String, f64> {
+    let mut totals: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for trade in trades {
+        let (notional, volume) = totals.entry(trade.asset.to_string()).or_insert((0, 0));
+        *notional += u64::from(trade.price) * u64::from(trade.volume);
+        *volume += u64::from(trade.volume);
+    }
+    totals
+        .into_iter()
+        .map(|(asset, (notional, volume))| (asset, notional as f64 / volume as f64))
+        .collect()
+}
+
+// Per client, asset and side execution quality, ordered by client then asset.
+pub fn execution_quality(trades: &[Trade]) -> Vec<ExecutionQuality> {
+    let vwap = vwap(trades);
+    let mut report: BTreeMap<(Arc<str>, Arc<str>, bool), ExecutionQuality> = BTreeMap::new();
+
+    for trade in trades {
+        let volume = i64::from(trade.volume);
+        let price = i64::from(trade.price);
+        let sides = [
+            (
+                &trade.buyer,
+                OrderType::Buy,
+                (price - i64::from(trade.buy_limit)) * volume,
+            ),
+            (
+                &trade.seller,
+                OrderType::Sell,
+                (i64::from(trade.sell_limit) - price) * volume,
+            ),
+        ];
+        for (client, operation, slippage) in sides {
+            let key = (
+                client.clone(),
+                trade.asset.clone(),
+                operation == OrderType::Sell,
+            );
+            let quality = report.entry(key).or_insert_with(|| ExecutionQuality {
+                client: client.to_string(),
+                asset: trade.asset.to_string(),
+                operation,
+                volume: 0,
+                notional: 0,
+                slippage: 0,
+                market_vwap: vwap[&*trade.asset],
+            });
+            quality.volume += u64::from(trade.volume);
+            quality.notional += u64::from(trade.price) * u64::from(trade.volume);
+            quality.slippage += slippage;
+        }
+    }
+    report.into_values().collect()
+}
+
+// Preparing a report line for recording.
+impl std::fmt::Display for ExecutionQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = match self.operation {
+            OrderType::Buy => "buy",
+            _ => "sell",
+        };
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+            self.client,
+            self.asset,
+            side,
+            self.volume,
+            self.average_price(),
+            self.market_vwap,
+            self.average_slippage()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(buyer: &str, seller: &str, price: u32, volume: u32, buy_limit: u32) -> Trade {
+        Trade {
+            buy_index: 1,
+            sell_index: 2,
+            buyer: buyer.into(),
+            seller: seller.into(),
+            asset: "A".into(),
+            price,
+            volume,
+            buy_limit,
+            sell_limit: price,
+        }
+    }
+
+    #[test]
+    fn test_vwap() {
+        let trades = [trade("C1", "C2", 10, 1, 10), trade("C1", "C3", 13, 3, 14)];
+        assert_eq!(vwap(&trades)["A"], 12.25);
+    }
+
+    #[test]
+    fn test_execution_quality() {
+        let trades = [trade("C1", "C2", 10, 1, 12), trade("C1", "C3", 13, 3, 14)];
+        let report = execution_quality(&trades);
+
+        assert_eq!(report.len(), 3);
+        let buyer = &report[0];
+        assert_eq!(buyer.client, "C1");
+        assert_eq!(buyer.operation, OrderType::Buy);
+        assert_eq!(buyer.volume, 4);
+        assert_eq!(buyer.average_price(), 12.25);
+        assert_eq!(buyer.slippage, -5);
+        assert_eq!(buyer.average_slippage(), -1.25);
+
+        let seller = &report[1];
+        assert_eq!(seller.client, "C2");
+        assert_eq!(seller.slippage, 0);
+        assert_eq!(
+            seller.to_string(),
+            "C2\tA\tsell\t1\t10.0000\t12.2500\t0.0000\n"
+        );
+    }
+}
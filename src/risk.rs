@@ -0,0 +1,311 @@
+use crate::{
+    clients::Client,
+    engine::Trade,
+    errors::RiskErrors,
+    orders::{Order, OrderType},
+    Volume,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+// Pre-trade risk limits. Every order is checked against its client's limits
+// before it can enter the books; a limit that is not set is not checked.
+
+// Limits of one client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Limits {
+    pub max_order_volume: Option<Volume>,
+    pub max_order_notional: Option<u64>,
+    // Resting orders, not counting the one being checked.
+    pub max_open_orders: Option<usize>,
+    // Units of any one asset held, or owed by a margin account that is
+    // short, once the order and the client's resting orders on the same side
+    // are filled in full.
+    pub max_position: Option<u64>,
+    // Notional of both sides of the day's fills, with the order's own added.
+    pub max_daily_notional: Option<u64>,
+}
+
+impl Limits {
+    // Limits set here, the other's where they are not.
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            max_order_volume: self.max_order_volume.or(other.max_order_volume),
+            max_order_notional: self.max_order_notional.or(other.max_order_notional),
+            max_open_orders: self.max_open_orders.or(other.max_open_orders),
+            max_position: self.max_position.or(other.max_position),
+            max_daily_notional: self.max_daily_notional.or(other.max_daily_notional),
+        }
+    }
+}
+
+// The `risk` section of the config: limits for every client, and per client
+// limits that override them one by one.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RiskConfig {
+    #[serde(default)]
+    pub default: Limits,
+    #[serde(default)]
+    pub clients: BTreeMap<String, Limits>,
+}
+
+// What a client has at stake in the asset of the order being checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Exposure {
+    // Resting orders in any asset.
+    pub open_orders: usize,
+    // Units held less units owed; below zero when the account is short.
+    pub position: i64,
+    // Units the client's resting buys and sells of the asset would trade.
+    pub buying: u64,
+    pub selling: u64,
+    // Whether the client trades on margin and so may sell what it does not hold.
+    pub may_short: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Risk {
+    pub config: RiskConfig,
+    // Notional each client has traded since the day started.
+    traded: HashMap<Arc<str>, u64>,
+}
+
+impl Risk {
+    pub fn new(config: RiskConfig) -> Risk {
+        Risk {
+            config,
+            traded: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self, client: &str) -> Limits {
+        match self.config.clients.get(client) {
+            Some(limits) => limits.or(self.config.default),
+            None => self.config.default,
+        }
+    }
+
+    // Whether any client has a limit that earlier fills can change the
+    // outcome of. Orders can then only be admitted one at a time, in order.
+    pub fn depends_on_fills(&self) -> bool {
+        self.config
+            .clients
+            .values()
+            .chain([&self.config.default])
+            .any(|limits| {
+                limits.max_open_orders.is_some()
+                    || limits.max_position.is_some()
+                    || limits.max_daily_notional.is_some()
+            })
+    }
+
+    // `exposure` looks at the client's resting orders. It is only called
+    // when the client has a limit on them or on its position.
+    pub fn check(
+        &self,
+        order: &Order,
+        client: &Client,
+        exposure: impl FnOnce() -> Exposure,
+    ) -> Result<(), RiskErrors> {
+        let limits = self.limits(&client.name);
+        let notional = u64::from(order.order_price) * u64::from(order.value);
+        if let Some(limit) = limits.max_order_volume {
+            if order.value > limit {
+                return Err(RiskErrors::OrderVolumeError(order.value, limit));
+            }
+        }
+        if let Some(limit) = limits.max_order_notional {
+            if notional > limit {
+                return Err(RiskErrors::OrderNotionalError(notional, limit));
+            }
+        }
+        let exposure = (limits.max_open_orders.is_some() || limits.max_position.is_some())
+            .then(exposure)
+            .unwrap_or_default();
+        if let Some(limit) = limits.max_open_orders {
+            if exposure.open_orders >= limit {
+                return Err(RiskErrors::OpenOrdersError(exposure.open_orders, limit));
+            }
+        }
+        if let Some(limit) = limits.max_position {
+            let value = i64::from(order.value);
+            // Cash clients can only sell what they hold, so only their buys
+            // can take them over.
+            let position = match order.operation {
+                OrderType::Buy => exposure.position + exposure.buying as i64 + value,
+                _ if exposure.may_short => -(exposure.position - exposure.selling as i64 - value),
+                _ => 0,
+            };
+            if position > 0 && position as u64 > limit {
+                let asset = client
+                    .asset_balances
+                    .by_id(order.asset_id)
+                    .map_or_else(String::new, |asset| asset.symbol.clone());
+                return Err(RiskErrors::PositionError(position as u64, asset, limit));
+            }
+        }
+        if let Some(limit) = limits.max_daily_notional {
+            let traded = self
+                .traded
+                .get(client.name.as_str())
+                .copied()
+                .unwrap_or_default()
+                + notional;
+            if traded > limit {
+                return Err(RiskErrors::DailyNotionalError(traded, limit));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            let notional = u64::from(trade.price) * u64::from(trade.volume);
+            for client in [&trade.buyer, &trade.seller] {
+                *self.traded.entry(client.clone()).or_default() += notional;
+            }
+        }
+    }
+
+    pub fn start_day(&mut self) {
+        self.traded.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn order(operation: OrderType, price: u32, value: u32) -> Order {
+        Order {
+            index: 1,
+            client_id: Default::default(),
+            operation,
+            asset_id: Default::default(),
+            order_price: price,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let client = Client::from_str("C1\t1000\t10\t0\t0\t0").unwrap();
+        let mut risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_order_volume: Some(50),
+                max_order_notional: Some(500),
+                max_open_orders: Some(2),
+                max_position: Some(40),
+                max_daily_notional: Some(300),
+            },
+            clients: BTreeMap::new(),
+        });
+        let check = |risk: &Risk, order: Order, open_orders| {
+            risk.check(&order, &client, || Exposure {
+                open_orders,
+                position: 10,
+                ..Exposure::default()
+            })
+        };
+
+        assert_eq!(check(&risk, order(OrderType::Buy, 5, 20), 1), Ok(()));
+        assert_eq!(
+            check(&risk, order(OrderType::Sell, 1, 51), 0),
+            Err(RiskErrors::OrderVolumeError(51, 50))
+        );
+        assert_eq!(
+            check(&risk, order(OrderType::Sell, 20, 26), 0),
+            Err(RiskErrors::OrderNotionalError(520, 500))
+        );
+        assert_eq!(
+            check(&risk, order(OrderType::Buy, 5, 20), 2),
+            Err(RiskErrors::OpenOrdersError(2, 2))
+        );
+        assert_eq!(
+            check(&risk, order(OrderType::Buy, 5, 31), 0),
+            Err(RiskErrors::PositionError(41, "A".to_string(), 40))
+        );
+        // Selling never adds to the position of a cash client.
+        assert_eq!(check(&risk, order(OrderType::Sell, 5, 31), 0), Ok(()));
+
+        risk.record(&[Trade {
+            buy_index: 1,
+            sell_index: 2,
+            buyer: "C2".into(),
+            seller: "C1".into(),
+            asset: "A".into(),
+            price: 10,
+            volume: 25,
+            buy_limit: 10,
+            sell_limit: 10,
+        }]);
+        assert_eq!(
+            check(&risk, order(OrderType::Sell, 10, 6), 0),
+            Err(RiskErrors::DailyNotionalError(310, 300))
+        );
+        risk.start_day();
+        assert_eq!(check(&risk, order(OrderType::Sell, 10, 6), 0), Ok(()));
+    }
+
+    #[test]
+    fn test_position_counts_resting_orders() {
+        let client = Client::from_str("C1\t1000\t10\t0\t0\t0").unwrap();
+        let risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_position: Some(40),
+                ..Limits::default()
+            },
+            clients: BTreeMap::new(),
+        });
+        let check = |order: Order, may_short| {
+            risk.check(&order, &client, || Exposure {
+                open_orders: 2,
+                position: 10,
+                buying: 20,
+                selling: 20,
+                may_short,
+            })
+        };
+
+        assert_eq!(check(order(OrderType::Buy, 5, 10), false), Ok(()));
+        assert_eq!(
+            check(order(OrderType::Buy, 5, 11), false),
+            Err(RiskErrors::PositionError(41, "A".to_string(), 40))
+        );
+        // 10 held less 20 resting and 31 sold leaves a margin account 41 short.
+        assert_eq!(check(order(OrderType::Sell, 5, 30), true), Ok(()));
+        assert_eq!(
+            check(order(OrderType::Sell, 5, 31), true),
+            Err(RiskErrors::PositionError(41, "A".to_string(), 40))
+        );
+        assert_eq!(check(order(OrderType::Sell, 5, 31), false), Ok(()));
+    }
+
+    #[test]
+    fn test_client_overrides() {
+        let mut clients = BTreeMap::new();
+        clients.insert(
+            "C1".to_string(),
+            Limits {
+                max_order_volume: Some(100),
+                ..Limits::default()
+            },
+        );
+        let risk = Risk::new(RiskConfig {
+            default: Limits {
+                max_order_volume: Some(10),
+                max_order_notional: Some(500),
+                ..Limits::default()
+            },
+            clients,
+        });
+        assert_eq!(risk.limits("C1").max_order_volume, Some(100));
+        assert_eq!(risk.limits("C1").max_order_notional, Some(500));
+        assert_eq!(risk.limits("C2").max_order_volume, Some(10));
+        assert!(!risk.depends_on_fills());
+    }
+}
@@ -0,0 +1,478 @@
+use crate::{
+    clients::Client,
+    engine::{Engine, Trade},
+    errors::{GeneralErrors, TradeMatchErrors},
+    generator::Rng,
+    orders::{NewOrder, Order, OrderType},
+    quotes::Quote,
+    Price, Volume,
+};
+use std::collections::VecDeque;
+
+pub const SIMULATION_HEADER: &str = "client\tdollar_balance\tA\tB\tC\tD\trealized\tunrealized";
+
+// Agent-based market simulation. Each agent trades as one of the engine's
+// clients. On every step the agents take turns: each looks at the market as
+// the earlier turns left it and sends its orders and cancels straight into
+// the engine. The agent going first rotates from step to step.
+
+// What an agent can do on its turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Order {
+        operation: OrderType,
+        asset: String,
+        price: Price,
+        volume: Volume,
+    },
+    // Cancels one of the agent's own resting orders by index.
+    Cancel(usize),
+}
+
+// The market as an agent sees it on its turn.
+pub struct View<'a> {
+    pub step: usize,
+    pub client: &'a Client,
+    pub engine: &'a Engine,
+    // Every fill of the previous step.
+    pub trades: &'a [Trade],
+}
+
+impl View<'_> {
+    pub fn quote(&self, asset: &str) -> Option<Quote> {
+        self.engine.quote(asset)
+    }
+
+    // Last trade price of an asset, if it has traded.
+    pub fn last_price(&self, asset: &str) -> Option<Price> {
+        self.quote(asset).and_then(|quote| quote.last_price)
+    }
+
+    // The agent's own orders still resting in the books.
+    pub fn resting(&self) -> Vec<&Order> {
+        self.engine
+            .buy_orders
+            .order
+            .values()
+            .chain(self.engine.sell_orders.order.values())
+            .filter(|order| order.client_id == self.client.id)
+            .collect()
+    }
+}
+
+pub trait Agent {
+    // Name of the client the agent trades as.
+    fn client(&self) -> &str;
+
+    fn act(&mut self, view: &View) -> Vec<Action>;
+}
+
+// Final balances and P&L of an agent's client.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub client: Client,
+    pub realized: f64,
+    pub unrealized: f64,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}\t{:.2}\t{:.2}",
+            self.client.to_string().trim_end(),
+            self.realized,
+            self.unrealized
+        )
+    }
+}
+
+pub struct Simulator {
+    pub engine: Engine,
+    agents: Vec<Box<dyn Agent>>,
+    step: usize,
+    orders: usize,
+    trades: Vec<Trade>,
+}
+
+impl Simulator {
+    // Every agent has to trade as a client of the engine.
+    pub fn new(engine: Engine, agents: Vec<Box<dyn Agent>>) -> Result<Simulator, GeneralErrors> {
+        if agents
+            .iter()
+            .any(|agent| engine.clients.get(agent.client()).is_none())
+        {
+            return Err(GeneralErrors::GetClientError);
+        }
+        Ok(Simulator {
+            engine,
+            agents,
+            step: 0,
+            orders: 0,
+            trades: Vec::new(),
+        })
+    }
+
+    // Gives every agent one turn and returns the fills of the step. An order
+    // the engine rejects, or a cancel of an order that is not the agent's
+    // own resting one, ends the simulation.
+    pub fn step(&mut self) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let mut trades = Vec::new();
+        let count = self.agents.len();
+        for turn in 0..count {
+            let agent = &mut self.agents[(self.step + turn) % count];
+            let client = self
+                .engine
+                .clients
+                .get(agent.client())
+                .cloned()
+                .ok_or(GeneralErrors::GetClientError)?;
+            let actions = agent.act(&View {
+                step: self.step,
+                client: &client,
+                engine: &self.engine,
+                trades: &self.trades,
+            });
+            for action in actions {
+                match action {
+                    Action::Order {
+                        operation,
+                        asset,
+                        price,
+                        volume,
+                    } => {
+                        self.orders += 1;
+                        let mut fills = self.engine.enter(&NewOrder {
+                            index: self.orders,
+                            client_name: client.name.clone(),
+                            operation,
+                            asset,
+                            order_price: price,
+                            value: volume,
+                        })?;
+                        trades.append(&mut fills);
+                    }
+                    Action::Cancel(index) => {
+                        let owned = self
+                            .engine
+                            .get_order(index)
+                            .is_some_and(|order| order.client_id == client.id);
+                        if !owned {
+                            return Err(GeneralErrors::GetOrderError.into());
+                        }
+                        self.engine.cancel(index)?;
+                    }
+                }
+            }
+        }
+        self.step += 1;
+        self.trades = trades.clone();
+        Ok(trades)
+    }
+
+    // Runs the given number of steps and returns every fill.
+    pub fn run(&mut self, steps: usize) -> Result<Vec<Trade>, TradeMatchErrors> {
+        let mut trades = Vec::new();
+        for _ in 0..steps {
+            trades.append(&mut self.step()?);
+        }
+        Ok(trades)
+    }
+
+    // Balances and P&L of every agent's client, in the order they were given.
+    pub fn report(&self) -> Vec<Outcome> {
+        self.agents
+            .iter()
+            .filter_map(|agent| self.engine.clients.get(agent.client()).cloned())
+            .map(|client| Outcome {
+                realized: client.realized_pnl(),
+                unrealized: client.unrealized_pnl(&self.engine.quotes),
+                client,
+            })
+            .collect()
+    }
+}
+
+// Price an agent trades around: the last trade, or its own fair price
+// before the asset has traded.
+fn reference(view: &View, asset: &str, fair: Price) -> Price {
+    view.last_price(asset).unwrap_or(fair)
+}
+
+fn order(operation: OrderType, asset: &str, price: Price, volume: Volume) -> Action {
+    Action::Order {
+        operation,
+        asset: asset.to_string(),
+        price: price.max(1),
+        volume,
+    }
+}
+
+// Keeps one bid and one ask in every asset, half_spread either side of the
+// last trade. Its quotes of the previous step are cancelled first.
+pub struct MarketMaker {
+    pub client: String,
+    pub assets: Vec<String>,
+    pub fair: Price,
+    pub half_spread: Price,
+    pub size: Volume,
+}
+
+impl Agent for MarketMaker {
+    fn client(&self) -> &str {
+        &self.client
+    }
+
+    fn act(&mut self, view: &View) -> Vec<Action> {
+        let mut actions: Vec<Action> = view
+            .resting()
+            .iter()
+            .map(|order| Action::Cancel(order.index))
+            .collect();
+        for asset in &self.assets {
+            let price = reference(view, asset, self.fair);
+            actions.push(order(
+                OrderType::Buy,
+                asset,
+                price.saturating_sub(self.half_spread),
+                self.size,
+            ));
+            actions.push(order(
+                OrderType::Sell,
+                asset,
+                price.saturating_add(self.half_spread),
+                self.size,
+            ));
+        }
+        actions
+    }
+}
+
+// Follows the trend: when an asset's last price is above where it was
+// lookback steps ago it takes the best ask, when below it hits the best bid.
+pub struct MomentumTaker {
+    pub client: String,
+    pub assets: Vec<String>,
+    pub lookback: usize,
+    pub size: Volume,
+    history: Vec<VecDeque<Price>>,
+}
+
+impl MomentumTaker {
+    pub fn new(client: &str, assets: Vec<String>, lookback: usize, size: Volume) -> MomentumTaker {
+        MomentumTaker {
+            client: client.to_string(),
+            history: vec![VecDeque::new(); assets.len()],
+            assets,
+            lookback,
+            size,
+        }
+    }
+}
+
+impl Agent for MomentumTaker {
+    fn client(&self) -> &str {
+        &self.client
+    }
+
+    fn act(&mut self, view: &View) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for (asset, history) in self.assets.iter().zip(self.history.iter_mut()) {
+            let Some(quote) = view.quote(asset) else {
+                continue;
+            };
+            let Some(last_price) = quote.last_price else {
+                continue;
+            };
+            history.push_back(last_price);
+            if history.len() <= self.lookback {
+                continue;
+            }
+            let then = history.pop_front().unwrap_or(last_price);
+            match (last_price.cmp(&then), quote.ask_price, quote.bid_price) {
+                (std::cmp::Ordering::Greater, Some(ask), _) => {
+                    actions.push(order(OrderType::Buy, asset, ask, self.size))
+                }
+                // A sell only fills strictly below the bid it meets.
+                (std::cmp::Ordering::Less, _, Some(bid)) if bid > 1 => {
+                    actions.push(order(OrderType::Sell, asset, bid - 1, self.size))
+                }
+                _ => {}
+            }
+        }
+        actions
+    }
+}
+
+// Sends one order a step on a random side of a random asset, up to width
+// away from the last trade, with a random volume up to max_volume.
+pub struct NoiseTrader {
+    pub client: String,
+    pub assets: Vec<String>,
+    pub fair: Price,
+    pub width: Price,
+    pub max_volume: Volume,
+    rng: Rng,
+}
+
+impl NoiseTrader {
+    // The seed makes the trader's choices repeatable.
+    pub fn new(
+        client: &str,
+        assets: Vec<String>,
+        fair: Price,
+        width: Price,
+        max_volume: Volume,
+        seed: u64,
+    ) -> NoiseTrader {
+        NoiseTrader {
+            client: client.to_string(),
+            assets,
+            fair,
+            width,
+            max_volume,
+            rng: Rng(seed),
+        }
+    }
+}
+
+impl Agent for NoiseTrader {
+    fn client(&self) -> &str {
+        &self.client
+    }
+
+    fn act(&mut self, view: &View) -> Vec<Action> {
+        if self.assets.is_empty() || self.max_volume == 0 {
+            return Vec::new();
+        }
+        let asset = &self.assets[self.rng.below(self.assets.len())];
+        let price = reference(view, asset, self.fair);
+        let offset = self.rng.below(self.width as usize + 1) as Price;
+        let volume = self.rng.below(self.max_volume as usize) as Volume + 1;
+        let action = match self.rng.chance(0.5) {
+            true => order(OrderType::Buy, asset, price.saturating_add(offset), volume),
+            false => order(OrderType::Sell, asset, price.saturating_sub(offset), volume),
+        };
+        vec![action]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clients::Clients, DataParser};
+    use std::str::FromStr;
+
+    fn engine() -> Engine {
+        let mut clients = Clients::new();
+        for (index, line) in [
+            "MM\t100000\t1000\t1000\t1000\t1000",
+            "MO\t100000\t1000\t1000\t1000\t1000",
+            "N1\t100000\t1000\t1000\t1000\t1000",
+            "N2\t100000\t1000\t1000\t1000\t1000",
+        ]
+        .iter()
+        .enumerate()
+        {
+            clients.insert(index, Client::from_str(line).unwrap());
+        }
+        Engine::new(clients)
+    }
+
+    fn agents() -> Vec<Box<dyn Agent>> {
+        let assets = vec!["A".to_string(), "B".to_string()];
+        vec![
+            Box::new(MarketMaker {
+                client: "MM".to_string(),
+                assets: assets.clone(),
+                fair: 50,
+                half_spread: 2,
+                size: 10,
+            }),
+            Box::new(MomentumTaker::new("MO", assets.clone(), 3, 5)),
+            Box::new(NoiseTrader::new("N1", assets.clone(), 50, 4, 8, 1)),
+            Box::new(NoiseTrader::new("N2", assets, 50, 4, 8, 2)),
+        ]
+    }
+
+    #[test]
+    fn test_run() {
+        let mut simulator = Simulator::new(engine(), agents()).unwrap();
+        let trades = simulator.run(200).unwrap();
+        assert!(!trades.is_empty());
+
+        let outcomes = simulator.report();
+        let names: Vec<&str> = outcomes
+            .iter()
+            .map(|outcome| outcome.client.name.as_str())
+            .collect();
+        assert_eq!(names, ["MM", "MO", "N1", "N2"]);
+        // Trades only move dollars between the agents.
+        let dollars: u32 = outcomes
+            .iter()
+            .map(|outcome| outcome.client.dollar_balance)
+            .sum();
+        assert_eq!(dollars, 400_000);
+        // The market maker quotes at most one bid and one ask per asset.
+        let maker = simulator.engine.clients.id("MM");
+        let quotes = simulator
+            .engine
+            .buy_orders
+            .order
+            .values()
+            .chain(simulator.engine.sell_orders.order.values())
+            .filter(|order| Some(order.client_id) == maker)
+            .count();
+        assert!(quotes <= 4);
+
+        let mut again = Simulator::new(engine(), agents()).unwrap();
+        assert_eq!(again.run(200).unwrap(), trades);
+    }
+
+    struct Rogue;
+
+    impl Agent for Rogue {
+        fn client(&self) -> &str {
+            "N1"
+        }
+
+        fn act(&mut self, view: &View) -> Vec<Action> {
+            match view.step {
+                0 => vec![order(OrderType::Buy, "A", 10, 1)],
+                1 => vec![Action::Cancel(1)],
+                _ => vec![Action::Cancel(4)],
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let maker: Box<dyn Agent> = Box::new(MarketMaker {
+            client: "MM".to_string(),
+            assets: vec!["A".to_string()],
+            fair: 50,
+            half_spread: 2,
+            size: 10,
+        });
+        assert!(Simulator::new(
+            engine(),
+            vec![Box::new(NoiseTrader::new("X", vec![], 1, 1, 1, 1))]
+        )
+        .is_err());
+
+        // The rogue's own order rests as index 1 and the maker quotes 2 and
+        // 3, then requotes them as 4 and 5 before the rogue cancels. The
+        // maker's orders are not the rogue's to cancel.
+        let mut simulator = Simulator::new(engine(), vec![Box::new(Rogue), maker]).unwrap();
+        simulator.step().unwrap();
+        let owner = |simulator: &Simulator, index| {
+            let order = simulator.engine.get_order(index).unwrap();
+            order.named(&simulator.engine.clients.registry).client_name
+        };
+        assert_eq!(owner(&simulator, 1), "N1");
+        simulator.step().unwrap();
+        assert!(simulator.engine.get_order(1).is_none());
+        assert!(simulator.step().is_err());
+        assert_eq!(owner(&simulator, 4), "MM");
+    }
+}
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, sync::Arc};
+
+// Numeric id standing in for a client or asset name inside the engine, so
+// the books compare and copy integers instead of strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(pub u32);
+
+// Interns names into dense ids in the order they are first seen. A name keeps
+// its id for the lifetime of the registry and can always be looked up again,
+// so everything read or written still uses names. Names are shared, so
+// handing one out does not copy it.
+#[derive(Clone, Default)]
+pub struct Symbols {
+    names: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, Symbol>,
+}
+
+// Separate id spaces for clients and assets.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    pub clients: Symbols,
+    pub assets: Symbols,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    // Id of the name, allocating the next one the first time it is seen.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = Symbol(self.names.len() as u32);
+        let name: Arc<str> = name.into();
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: Symbol) -> Option<&Arc<str>> {
+        self.names.get(id.0 as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+// The names in id order say everything the lookup table does, and unlike
+// it they always print in the same order.
+impl std::fmt::Debug for Symbols {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.names).finish()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut symbols = Symbols::new();
+        let c1 = symbols.intern("C1");
+        let c2 = symbols.intern("C2");
+        assert_eq!(symbols.intern("C1"), c1);
+        assert_ne!(c1, c2);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.get("C2"), Some(c2));
+        assert_eq!(symbols.get("C3"), None);
+        assert_eq!(symbols.name(c2).map(|name| &**name), Some("C2"));
+        assert_eq!(symbols.name(Symbol(7)), None);
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::BTreeSet,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+use trade_match::{
+    clients::Clients,
+    engine::Engine,
+    fix::{msg_type, sending_time, tags::*, take_frame, Acceptor, Message},
+    read_file,
+};
+
+// Minimal FIX initiator driving the acceptor over TCP.
+struct Initiator {
+    stream: TcpStream,
+    comp_id: String,
+    next_seq: u64,
+    buffer: Vec<u8>,
+}
+
+impl Initiator {
+    fn logon(address: SocketAddr, comp_id: &str, heartbeat: u64) -> Initiator {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let mut initiator = Initiator {
+            stream,
+            comp_id: comp_id.to_string(),
+            next_seq: 1,
+            buffer: Vec::new(),
+        };
+        initiator.send(
+            Message::new(msg_type::LOGON)
+                .with(ENCRYPT_METHOD, 0)
+                .with(HEART_BT_INT, heartbeat),
+        );
+        let logon = initiator.receive();
+        assert_eq!(logon.msg_type(), msg_type::LOGON);
+        assert_eq!(logon.get(MSG_SEQ_NUM), Some("1"));
+        initiator
+    }
+
+    fn send(&mut self, message: Message) {
+        let mut stamped = Message::new(message.msg_type())
+            .with(SENDER_COMP_ID, &self.comp_id)
+            .with(TARGET_COMP_ID, "MATCH")
+            .with(MSG_SEQ_NUM, self.next_seq)
+            .with(SENDING_TIME, sending_time());
+        stamped.fields.extend(message.fields.into_iter().skip(1));
+        self.next_seq += 1;
+        self.stream.write_all(stamped.encode().as_bytes()).unwrap();
+    }
+
+    fn receive(&mut self) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(frame) = take_frame(&mut self.buffer) {
+                return frame.unwrap();
+            }
+            assert!(Instant::now() < deadline, "no message from the acceptor");
+            if let Ok(read) = self.stream.read(&mut chunk) {
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        }
+    }
+}
+
+fn new_order(cl_ord_id: &str, account: &str, side: u32, price: u32, quantity: u32) -> Message {
+    Message::new(msg_type::NEW_ORDER_SINGLE)
+        .with(CL_ORD_ID, cl_ord_id)
+        .with(ACCOUNT, account)
+        .with(SYMBOL, "A")
+        .with(SIDE, side)
+        .with(ORD_TYPE, 2)
+        .with(ORDER_QTY, quantity)
+        .with(PRICE, price)
+}
+
+// SELLER trades as C2, BUYER as C1 and QUIET as nobody.
+fn start() -> Acceptor {
+    let clients: Clients = read_file("./Clients.txt".to_string()).unwrap();
+    let accounts = [("SELLER", "C2"), ("BUYER", "C1")]
+        .into_iter()
+        .map(|(comp_id, account)| (comp_id.to_string(), BTreeSet::from([account.to_string()])))
+        .collect();
+    Acceptor::bind("127.0.0.1:0", "MATCH", Engine::new(clients), accounts).unwrap()
+}
+
+#[test]
+fn test_order_entry_session() {
+    let acceptor = start();
+    let address = acceptor.local_addr().unwrap();
+    let gateway = acceptor.gateway();
+    thread::spawn(move || acceptor.run());
+
+    let mut seller = Initiator::logon(address, "SELLER", 30);
+    let mut buyer = Initiator::logon(address, "BUYER", 30);
+
+    seller.send(new_order("S1", "C2", 2, 10, 5));
+    let new = seller.receive();
+    assert_eq!(new.msg_type(), msg_type::EXECUTION_REPORT);
+    assert_eq!(new.get(EXEC_TYPE), Some("0"));
+    assert_eq!(new.get(LEAVES_QTY), Some("5"));
+
+    buyer.send(new_order("B1", "C1", 1, 12, 8));
+    assert_eq!(buyer.receive().get(EXEC_TYPE), Some("0"));
+    let fill = buyer.receive();
+    assert_eq!(fill.get(EXEC_TYPE), Some("F"));
+    assert_eq!(fill.get(ORD_STATUS), Some("1"));
+    assert_eq!(fill.get(LAST_PX), Some("10"));
+    assert_eq!(fill.get(LAST_QTY), Some("5"));
+    assert_eq!(fill.get(LEAVES_QTY), Some("3"));
+
+    let counterparty = seller.receive();
+    assert_eq!(counterparty.get(CL_ORD_ID), Some("S1"));
+    assert_eq!(counterparty.get(ORD_STATUS), Some("2"));
+    assert_eq!(counterparty.get(MSG_SEQ_NUM), Some("3"));
+
+    buyer.send(
+        Message::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(ORIG_CL_ORD_ID, "B1")
+            .with(CL_ORD_ID, "B2")
+            .with(ACCOUNT, "C1")
+            .with(SYMBOL, "A")
+            .with(SIDE, 1)
+            .with(ORDER_QTY, 10)
+            .with(PRICE, 11),
+    );
+    let replaced = buyer.receive();
+    assert_eq!(replaced.get(EXEC_TYPE), Some("5"));
+    assert_eq!(replaced.get(ORIG_CL_ORD_ID), Some("B1"));
+    assert_eq!(replaced.get(CUM_QTY), Some("5"));
+    assert_eq!(replaced.get(LEAVES_QTY), Some("5"));
+
+    buyer.send(
+        Message::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "B2")
+            .with(CL_ORD_ID, "B3"),
+    );
+    let canceled = buyer.receive();
+    assert_eq!(canceled.get(EXEC_TYPE), Some("4"));
+    assert_eq!(canceled.get(LEAVES_QTY), Some("0"));
+
+    buyer.send(
+        Message::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "B3")
+            .with(CL_ORD_ID, "B4"),
+    );
+    let reject = buyer.receive();
+    assert_eq!(reject.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+    assert_eq!(reject.get(CXL_REJ_RESPONSE_TO), Some("1"));
+
+    buyer.send(Message::new(msg_type::TEST_REQUEST).with(TEST_REQ_ID, "ping"));
+    let heartbeat = buyer.receive();
+    assert_eq!(heartbeat.msg_type(), msg_type::HEARTBEAT);
+    assert_eq!(heartbeat.get(TEST_REQ_ID), Some("ping"));
+
+    buyer.send(Message::new(msg_type::LOGOUT));
+    assert_eq!(buyer.receive().msg_type(), msg_type::LOGOUT);
+    seller.send(Message::new(msg_type::LOGOUT));
+    assert_eq!(seller.receive().msg_type(), msg_type::LOGOUT);
+
+    let gateway = gateway.lock().unwrap();
+    let c1 = gateway.engine.clients.get("C1").unwrap();
+    assert_eq!(c1.dollar_balance, 950);
+    assert_eq!(c1.asset_balances.get("A").unwrap().balance, 135);
+    assert!(gateway.engine.buy_orders.order.is_empty());
+}
+
+#[test]
+fn test_trading_another_account() {
+    let acceptor = start();
+    let address = acceptor.local_addr().unwrap();
+    let gateway = acceptor.gateway();
+    thread::spawn(move || acceptor.run());
+
+    let mut seller = Initiator::logon(address, "SELLER", 30);
+    let mut buyer = Initiator::logon(address, "BUYER", 30);
+
+    // BUYER may only trade C1, so selling out of C2's balances is refused.
+    buyer.send(new_order("X1", "C2", 2, 10, 5));
+    let reject = buyer.receive();
+    assert_eq!(reject.get(ORD_STATUS), Some("8"));
+    assert_eq!(reject.get(TEXT), Some("Account not allowed"));
+
+    seller.send(new_order("S1", "C2", 2, 10, 5));
+    assert_eq!(seller.receive().get(EXEC_TYPE), Some("0"));
+    buyer.send(new_order("B1", "C1", 1, 9, 5));
+    assert_eq!(buyer.receive().get(EXEC_TYPE), Some("0"));
+
+    // Nor can it cancel its own order while naming somebody else's account.
+    buyer.send(
+        Message::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(ORIG_CL_ORD_ID, "B1")
+            .with(CL_ORD_ID, "B2")
+            .with(ACCOUNT, "C2"),
+    );
+    let reject = buyer.receive();
+    assert_eq!(reject.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+    assert_eq!(reject.get(TEXT), Some("Account not allowed"));
+
+    buyer.send(Message::new(msg_type::LOGOUT));
+    assert_eq!(buyer.receive().msg_type(), msg_type::LOGOUT);
+    seller.send(Message::new(msg_type::LOGOUT));
+    assert_eq!(seller.receive().msg_type(), msg_type::LOGOUT);
+
+    let gateway = gateway.lock().unwrap();
+    assert_eq!(gateway.engine.sell_orders.order.len(), 1);
+    assert_eq!(gateway.engine.buy_orders.order.len(), 1);
+}
+
+#[test]
+fn test_heartbeat_and_sequence_gap() {
+    let acceptor = start();
+    let address = acceptor.local_addr().unwrap();
+    thread::spawn(move || acceptor.run());
+
+    let mut initiator = Initiator::logon(address, "QUIET", 1);
+    assert_eq!(initiator.receive().msg_type(), msg_type::HEARTBEAT);
+
+    initiator.next_seq += 3;
+    initiator.send(Message::new(msg_type::HEARTBEAT));
+    let mut resend = initiator.receive();
+    while resend.msg_type() != msg_type::RESEND_REQUEST {
+        assert_ne!(resend.msg_type(), msg_type::LOGOUT);
+        resend = initiator.receive();
+    }
+    assert_eq!(resend.msg_type(), msg_type::RESEND_REQUEST);
+    assert_eq!(resend.get(BEGIN_SEQ_NO), Some("2"));
+
+    // The held heartbeat is released once the gap is filled, so the logout
+    // that follows it is answered.
+    let next_seq = initiator.next_seq;
+    initiator.next_seq = 2;
+    initiator.send(
+        Message::new(msg_type::SEQUENCE_RESET)
+            .with(GAP_FILL_FLAG, "Y")
+            .with(NEW_SEQ_NO, 5),
+    );
+    initiator.next_seq = next_seq;
+    let message = Message::from_str(&Message::new(msg_type::LOGOUT).encode()).unwrap();
+    initiator.send(message);
+    let mut logout = initiator.receive();
+    while logout.msg_type() != msg_type::LOGOUT {
+        logout = initiator.receive();
+    }
+}
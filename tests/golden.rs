@@ -0,0 +1,145 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Golden-file scenarios run through the real binary. Every directory under
+// tests/scenarios holds a clients.txt and an orders.txt, the result.txt and
+// trades.txt they are expected to produce and, optionally, an args.txt with
+// extra command line arguments one per line. Each scenario is run on one
+// thread and sharded by asset, and both have to match.
+//
+// After an intended change in matching, rewrite the expected files with
+//     UPDATE_GOLDEN=1 cargo test --test golden
+// and review the diff.
+
+const SCENARIOS: &str = "tests/scenarios";
+
+struct Outputs {
+    result: String,
+    trades: String,
+}
+
+fn trade_match(args: &[&str], extra: &[String]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_trade_match"))
+        .args(["--config", &format!("{}/config", SCENARIOS)])
+        .args(args)
+        .args(extra)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "trade_match {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn run(clients: &Path, orders: &Path, extra: &[String], name: &str) -> Outputs {
+    let clients = clients.to_str().unwrap();
+    let orders = orders.to_str().unwrap();
+    let result = env::temp_dir().join(format!("trade_match_golden_{}.txt", name));
+    let result = result.to_str().unwrap();
+
+    trade_match(
+        &["--clients", clients, "--orders", orders, "--output", result],
+        extra,
+    );
+    let trades = trade_match(&["replay", "--clients", clients, "--orders", orders], extra);
+    Outputs {
+        result: fs::read_to_string(result).unwrap(),
+        trades,
+    }
+}
+
+fn check(expected: &Path, actual: &str, failures: &mut Vec<String>) {
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(expected, actual).unwrap();
+        return;
+    }
+    let expected_text = fs::read_to_string(expected).unwrap_or_default();
+    if expected_text != actual {
+        failures.push(format!(
+            "{} differs:\n--- expected\n{}--- actual\n{}",
+            expected.display(),
+            expected_text,
+            actual
+        ));
+    }
+}
+
+fn scenarios() -> Vec<PathBuf> {
+    let mut scenarios: Vec<PathBuf> = fs::read_dir(SCENARIOS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    scenarios.sort();
+    scenarios
+}
+
+#[test]
+fn test_scenarios() {
+    let mut failures = Vec::new();
+    let scenarios = scenarios();
+    assert!(!scenarios.is_empty());
+    for scenario in scenarios {
+        let name = scenario.file_name().unwrap().to_str().unwrap().to_string();
+        let args: Vec<String> = fs::read_to_string(scenario.join("args.txt"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        let mut parallel = args.clone();
+        parallel.push("--parallel".to_string());
+
+        let clients = scenario.join("clients.txt");
+        let orders = scenario.join("orders.txt");
+        for (extra, suffix) in [(&args, ""), (&parallel, "_parallel")] {
+            let outputs = run(&clients, &orders, extra, &format!("{}{}", name, suffix));
+            check(&scenario.join("result.txt"), &outputs.result, &mut failures);
+            check(&scenario.join("trades.txt"), &outputs.trades, &mut failures);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+// The shipped input still gives the shipped Result.txt.
+#[test]
+fn test_shipped() {
+    let mut failures = Vec::new();
+    let outputs = run(
+        Path::new("./Clients.txt"),
+        Path::new("./Orders.txt"),
+        &[],
+        "shipped",
+    );
+    check(Path::new("./Result.txt"), &outputs.result, &mut failures);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+// An output that cannot be written is reported, not a panic.
+#[test]
+fn test_unwritable_output() {
+    let config = env::temp_dir().join("trade_match_unwritable.yaml");
+    fs::write(
+        &config,
+        "orders: ./Orders.txt\nclients: ./Clients.txt\nbook: ./no_such_dir/Book.txt\n",
+    )
+    .unwrap();
+    let result = env::temp_dir().join("trade_match_unwritable.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_trade_match"))
+        .args(["--config", config.with_extension("").to_str().unwrap()])
+        .args(["--output", result.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.starts_with("Error: Unable to write ./no_such_dir/Book.txt"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,228 @@
+use proptest::{collection::vec, prelude::*};
+use std::{collections::BTreeMap, str::FromStr};
+use trade_match::{
+    clients::{Client, Clients},
+    engine::{Engine, Trade},
+    orders::{NewOrder, OrderType, Orders},
+    DataParser,
+};
+
+const ASSETS: [&str; 4] = ["A", "B", "C", "D"];
+
+#[derive(Debug, Clone)]
+enum Message {
+    Order {
+        client: usize,
+        operation: OrderType,
+        asset: usize,
+        price: u32,
+        volume: u32,
+    },
+    // Cancels the n-th order sent so far, counting round, if it still rests.
+    Cancel(usize),
+}
+
+// Dollar balance and the balances of A to D.
+type Balances = (u32, [u32; 4]);
+
+fn message(clients: usize) -> impl Strategy<Value = Message> {
+    let side = prop_oneof![Just(OrderType::Buy), Just(OrderType::Sell)];
+    prop_oneof![
+        9 => (0..clients, side, 0..ASSETS.len(), 1..20u32, 0..50u32).prop_map(
+            |(client, operation, asset, price, volume)| Message::Order {
+                client,
+                operation,
+                asset,
+                price,
+                volume,
+            }
+        ),
+        1 => any::<usize>().prop_map(Message::Cancel),
+    ]
+}
+
+// Poor clients run into every balance check. Rich ones never fail one, so
+// every order that can trade does.
+fn balances(rich: bool) -> BoxedStrategy<Balances> {
+    if rich {
+        Just((10_000_000, [1_000_000; 4])).boxed()
+    } else {
+        (0..5_000u32, [0..200u32, 0..200u32, 0..200u32, 0..200u32]).boxed()
+    }
+}
+
+fn scenario(rich: bool) -> impl Strategy<Value = (Vec<Balances>, Vec<Message>)> {
+    (2..6usize).prop_flat_map(move |count| (vec(balances(rich), count), vec(message(count), 1..80)))
+}
+
+// Per order: volume sent, filled and cancelled so far.
+#[derive(Debug, Default)]
+struct Volumes {
+    original: u32,
+    filled: u32,
+    cancelled: u32,
+}
+
+// Feeds the messages to a fresh engine and checks every invariant after each.
+fn run(balances: &[Balances], messages: &[Message], rich: bool) -> Result<(), TestCaseError> {
+    let mut clients = Clients::new();
+    for (index, (dollars, assets)) in balances.iter().enumerate() {
+        let line = format!(
+            "C{}\t{}\t{}\t{}\t{}\t{}",
+            index, dollars, assets[0], assets[1], assets[2], assets[3]
+        );
+        clients.insert(index, Client::from_str(&line).unwrap());
+    }
+    let mut engine = Engine::new(clients);
+    let totals = totals(&engine);
+    let mut volumes: BTreeMap<usize, Volumes> = BTreeMap::new();
+
+    for message in messages {
+        match message {
+            Message::Order {
+                client,
+                operation,
+                asset,
+                price,
+                volume,
+            } => {
+                let index = volumes.len() + 1;
+                let trades = engine
+                    .enter(&NewOrder {
+                        index,
+                        client_name: format!("C{}", client),
+                        operation: *operation,
+                        asset: ASSETS[*asset].to_string(),
+                        order_price: *price,
+                        value: *volume,
+                    })
+                    .unwrap();
+                volumes.insert(
+                    index,
+                    Volumes {
+                        original: *volume,
+                        ..Volumes::default()
+                    },
+                );
+                for trade in &trades {
+                    check_trade(trade)?;
+                    volumes.get_mut(&trade.buy_index).unwrap().filled += trade.volume;
+                    volumes.get_mut(&trade.sell_index).unwrap().filled += trade.volume;
+                }
+            }
+            Message::Cancel(n) if !volumes.is_empty() => {
+                let index = n % volumes.len() + 1;
+                if let Ok(order) = engine.cancel(index) {
+                    volumes.get_mut(&index).unwrap().cancelled += order.value;
+                }
+            }
+            Message::Cancel(_) => {}
+        }
+
+        prop_assert_eq!(&self::totals(&engine), &totals, "totals are not conserved");
+        prop_assert_eq!(engine.ledger.reconcile(&engine.clients), Ok(()));
+        for (index, volume) in &volumes {
+            let remaining = engine.get_order(*index).map_or(0, |order| order.value);
+            prop_assert_eq!(
+                volume.filled + remaining + volume.cancelled,
+                volume.original,
+                "volume of order {} does not add up",
+                index
+            );
+        }
+        check_quotes(&engine)?;
+        if rich {
+            check_uncrossed(&engine)?;
+        }
+    }
+    Ok(())
+}
+
+// Total dollars and units of each asset over all clients. Balances are
+// unsigned, so one going negative would already have panicked.
+fn totals(engine: &Engine) -> Vec<u64> {
+    let mut totals = vec![0; ASSETS.len() + 1];
+    for client in engine.clients.client.values() {
+        totals[0] += u64::from(client.dollar_balance);
+        for (total, asset) in totals[1..].iter_mut().zip(ASSETS) {
+            *total += u64::from(client.asset_balances.get(asset).unwrap().balance);
+        }
+    }
+    totals
+}
+
+fn check_trade(trade: &Trade) -> Result<(), TestCaseError> {
+    prop_assert!(trade.volume > 0);
+    prop_assert_ne!(&trade.buyer, &trade.seller);
+    prop_assert!(trade.price <= trade.buy_limit);
+    prop_assert!(trade.price >= trade.sell_limit);
+    Ok(())
+}
+
+// No resting buy and sell of an asset from different clients could still
+// trade. Whichever came later would have filled against the other: a buy
+// at or above the sell price, a sell strictly below the buy price. Orders of
+// the same client never trade with each other.
+fn check_uncrossed(engine: &Engine) -> Result<(), TestCaseError> {
+    for buy in engine.buy_orders.order.values() {
+        for sell in engine.sell_orders.order.values() {
+            if buy.asset_id != sell.asset_id || buy.client_id == sell.client_id {
+                continue;
+            }
+            let crossed = if buy.index > sell.index {
+                buy.order_price >= sell.order_price
+            } else {
+                sell.order_price < buy.order_price
+            };
+            prop_assert!(!crossed, "{:?} and {:?} are crossed", buy, sell);
+        }
+    }
+    Ok(())
+}
+
+// Best bid and ask of every quoted asset are those a scan of the books finds,
+// with all the volume resting at them.
+fn check_quotes(engine: &Engine) -> Result<(), TestCaseError> {
+    for asset in ASSETS {
+        let Some(quote) = engine.quote(asset) else {
+            continue;
+        };
+        let id = engine.clients.registry.assets.get(asset);
+        let best = |orders: &Orders, highest: bool| {
+            let prices = orders
+                .order
+                .values()
+                .filter(|order| Some(order.asset_id) == id)
+                .map(|order| order.order_price);
+            let price = if highest { prices.max() } else { prices.min() };
+            let size: u32 = orders
+                .order
+                .values()
+                .filter(|order| Some(order.asset_id) == id && Some(order.order_price) == price)
+                .map(|order| order.value)
+                .sum();
+            (price, size)
+        };
+        prop_assert_eq!(
+            (quote.bid_price, quote.bid_size),
+            best(&engine.buy_orders, true)
+        );
+        prop_assert_eq!(
+            (quote.ask_price, quote.ask_size),
+            best(&engine.sell_orders, false)
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_invariants((balances, messages) in scenario(false)) {
+        run(&balances, &messages, false)?;
+    }
+
+    #[test]
+    fn test_invariants_uncrossed((balances, messages) in scenario(true)) {
+        run(&balances, &messages, true)?;
+    }
+}